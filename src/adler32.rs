@@ -0,0 +1,35 @@
+const MOD_ADLER: u32 = 65521;
+// largest n such that 255n(n+1)/2 + (n+1)(MOD_ADLER-1) fits into u32
+const NMAX: usize = 5552;
+
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        for chunk in input.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MOD_ADLER;
+            self.b %= MOD_ADLER;
+        }
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Adler32 {
+        Adler32::new()
+    }
+}
@@ -0,0 +1,182 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// Glue letting async readers be written once for both tokio's and futures' `AsyncRead`: they
+// feed their decoders through the `poll_read` of either trait taken as a plain function over byte
+// slices, and `impl_async_read!` implements the traits of enabled features on top of them.
+
+pub(crate) type PollRead<R> =
+    fn(Pin<&mut R>, &mut Context<'_>, &mut [u8]) -> Poll<io::Result<usize>>;
+
+#[cfg(feature = "tokio")]
+pub(crate) fn tokio_poll_read<R: tokio::io::AsyncRead>(
+    reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<io::Result<usize>> {
+    let mut read_buf = tokio::io::ReadBuf::new(buf);
+    std::task::ready!(reader.poll_read(cx, &mut read_buf))?;
+    Poll::Ready(Ok(read_buf.filled().len()))
+}
+
+#[cfg(feature = "futures-io")]
+pub(crate) fn futures_poll_read<R: futures_io::AsyncRead>(
+    reader: Pin<&mut R>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<io::Result<usize>> {
+    reader.poll_read(cx, buf)
+}
+
+/// Implements `AsyncRead` and `AsyncBufRead` of enabled features for a reader with
+/// `poll_fill_buf_with(&mut self, &mut Context, PollRead<R>) -> Poll<io::Result<&[u8]>>` and
+/// `consume_decompressed(&mut self, usize)`.
+macro_rules! impl_async_read {
+    ($reader:ident) => {
+        #[cfg(feature = "tokio")]
+        impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for $reader<R> {
+            fn poll_read(
+                self: std::pin::Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+                buf: &mut tokio::io::ReadBuf<'_>,
+            ) -> std::task::Poll<std::io::Result<()>> {
+                let this = self.get_mut();
+                let rem = std::task::ready!(
+                    this.poll_fill_buf_with(cx, $crate::async_io::tokio_poll_read)
+                )?;
+                let len = rem.len().min(buf.remaining());
+                buf.put_slice(&rem[..len]);
+                this.consume_decompressed(len);
+                std::task::Poll::Ready(Ok(()))
+            }
+        }
+
+        #[cfg(feature = "tokio")]
+        impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncBufRead for $reader<R> {
+            fn poll_fill_buf(
+                self: std::pin::Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<std::io::Result<&[u8]>> {
+                self.get_mut()
+                    .poll_fill_buf_with(cx, $crate::async_io::tokio_poll_read)
+            }
+
+            fn consume(self: std::pin::Pin<&mut Self>, amt: usize) {
+                self.get_mut().consume_decompressed(amt);
+            }
+        }
+
+        #[cfg(feature = "futures-io")]
+        impl<R: futures_io::AsyncRead + Unpin> futures_io::AsyncRead for $reader<R> {
+            fn poll_read(
+                self: std::pin::Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+                buf: &mut [u8],
+            ) -> std::task::Poll<std::io::Result<usize>> {
+                let this = self.get_mut();
+                let rem = std::task::ready!(
+                    this.poll_fill_buf_with(cx, $crate::async_io::futures_poll_read)
+                )?;
+                let len = rem.len().min(buf.len());
+                buf[..len].copy_from_slice(&rem[..len]);
+                this.consume_decompressed(len);
+                std::task::Poll::Ready(Ok(len))
+            }
+        }
+
+        #[cfg(feature = "futures-io")]
+        impl<R: futures_io::AsyncRead + Unpin> futures_io::AsyncBufRead for $reader<R> {
+            fn poll_fill_buf(
+                self: std::pin::Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<std::io::Result<&[u8]>> {
+                self.get_mut()
+                    .poll_fill_buf_with(cx, $crate::async_io::futures_poll_read)
+            }
+
+            fn consume(self: std::pin::Pin<&mut Self>, amt: usize) {
+                self.get_mut().consume_decompressed(amt);
+            }
+        }
+    };
+}
+
+pub(crate) use impl_async_read;
+
+/// Source for tests of async readers, returning `Pending` before each read and handing out a
+/// single byte per read.
+#[cfg(test)]
+pub(crate) struct Trickle {
+    data: Vec<u8>,
+    pos: usize,
+    ready: bool,
+}
+
+#[cfg(test)]
+impl Trickle {
+    pub(crate) fn new(data: Vec<u8>) -> Trickle {
+        Trickle {
+            data,
+            pos: 0,
+            ready: false,
+        }
+    }
+
+    /// Data not read yet.
+    pub(crate) fn rest(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+
+    fn poll_trickle(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.ready = !self.ready;
+        if !self.ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let len = self.rest().len().min(buf.len()).min(1);
+        buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+impl tokio::io::AsyncRead for Trickle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = std::task::ready!(self.get_mut().poll_trickle(cx, buf.initialize_unfilled()))?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "futures-io"))]
+impl futures_io::AsyncRead for Trickle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_trickle(cx, buf)
+    }
+}
+
+/// Reads `reader` to the end through `poll_read`, polling again for as long as it is pending.
+#[cfg(test)]
+pub(crate) fn poll_to_end<R: Unpin>(reader: &mut R, poll_read: PollRead<R>) -> io::Result<Vec<u8>> {
+    let mut cx = Context::from_waker(std::task::Waker::noop());
+    let mut buf = [0; 7];
+    let mut data = Vec::new();
+    loop {
+        match poll_read(Pin::new(&mut *reader), &mut cx, &mut buf) {
+            Poll::Pending => {}
+            Poll::Ready(Ok(0)) => return Ok(data),
+            Poll::Ready(Ok(len)) => data.extend_from_slice(&buf[..len]),
+            Poll::Ready(Err(err)) => return Err(err),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+/// Copies `run_len` bytes located `ref_offset` bytes back from `out_pos` to `out_pos`.
+///
+/// When the reference overlaps the output being produced, the last `ref_offset` bytes are
+/// repeated, which is what every lz77 style format expects. Callers are responsible for
+/// checking that `ref_offset` is within `1..=out_pos` and that `run_len` bytes fit into `buf`.
+pub(crate) fn copy_back_reference(
+    buf: &mut [u8],
+    out_pos: usize,
+    ref_offset: usize,
+    run_len: usize,
+) {
+    let ref_pos = out_pos - ref_offset;
+
+    if ref_pos + run_len <= out_pos {
+        // non-overlapping
+        let (src, dst) = buf.split_at_mut(out_pos);
+        dst[..run_len].copy_from_slice(&src[ref_pos..ref_pos + run_len]);
+    } else {
+        // overlapping
+        for i in 0..run_len {
+            buf[out_pos + i] = buf[ref_pos + i];
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+// Index of a stream made of independently compressed blocks, mapping decompressed offsets to
+// blocks so that a reader can jump right to the block holding some position. Offsets are counted
+// from the start of the stream, which is not necessarily the start of the file holding it.
+
+pub const BLOCK_INDEX_MAGIC: &[u8; 8] = b"pakuidx1";
+
+/// Where a block holding data starts, in compressed and decompressed stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIndexEntry {
+    pub compressed_offset: u64,
+    pub decompressed_offset: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    entries: Vec<BlockIndexEntry>,
+    compressed_length: u64,
+    decompressed_length: u64,
+}
+
+impl BlockIndex {
+    pub fn new() -> BlockIndex {
+        BlockIndex::default()
+    }
+
+    /// Adds the block following all blocks added so far. Blocks without data only move the end
+    /// of compressed stream, they never get an entry.
+    pub fn push(
+        &mut self,
+        compressed_offset: u64,
+        compressed_length: u64,
+        decompressed_length: u64,
+    ) -> io::Result<()> {
+        if compressed_offset < self.compressed_length {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "block index blocks must be added in stream order",
+            ));
+        }
+        if decompressed_length != 0 {
+            self.entries.push(BlockIndexEntry {
+                compressed_offset,
+                decompressed_offset: self.decompressed_length,
+            });
+        }
+        self.compressed_length = compressed_offset + compressed_length;
+        self.decompressed_length += decompressed_length;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[BlockIndexEntry] {
+        &self.entries
+    }
+
+    /// Length of compressed stream up to the end of the last block.
+    pub fn compressed_length(&self) -> u64 {
+        self.compressed_length
+    }
+
+    pub fn decompressed_length(&self) -> u64 {
+        self.decompressed_length
+    }
+
+    /// Index of the entry for the block holding `decompressed_offset`, `None` past the end.
+    pub fn find(&self, decompressed_offset: u64) -> Option<usize> {
+        if decompressed_offset >= self.decompressed_length {
+            return None;
+        }
+        // first entry always starts at 0, so there is one at or before the offset
+        Some(
+            self.entries
+                .partition_point(|entry| entry.decompressed_offset <= decompressed_offset)
+                - 1,
+        )
+    }
+
+    /// Reads an index stored by `write_to`, for example from a sidecar file.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<BlockIndex> {
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != BLOCK_INDEX_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "wrong block index magic",
+            ));
+        }
+
+        let compressed_length = reader.read_u64::<LittleEndian>()?;
+        let decompressed_length = reader.read_u64::<LittleEndian>()?;
+        let entry_count = reader.read_u64::<LittleEndian>()?;
+
+        // entries are pushed as they are read, so that a corrupt count can't make us allocate
+        // more than the data actually holds
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            let compressed_offset = reader.read_u64::<LittleEndian>()?;
+            let decompressed_offset = reader.read_u64::<LittleEndian>()?;
+            let in_order = match entries.last() {
+                Some(&BlockIndexEntry {
+                    compressed_offset: prev_compressed_offset,
+                    decompressed_offset: prev_decompressed_offset,
+                }) => {
+                    compressed_offset > prev_compressed_offset
+                        && decompressed_offset > prev_decompressed_offset
+                }
+                None => decompressed_offset == 0,
+            };
+            if !in_order
+                || compressed_offset >= compressed_length
+                || decompressed_offset >= decompressed_length
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "block index entries are out of order",
+                ));
+            }
+            entries.push(BlockIndexEntry {
+                compressed_offset,
+                decompressed_offset,
+            });
+        }
+        if entries.is_empty() && decompressed_length != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "block index has data but no entries",
+            ));
+        }
+
+        Ok(BlockIndex {
+            entries,
+            compressed_length,
+            decompressed_length,
+        })
+    }
+
+    /// Writes the index as magic, compressed and decompressed lengths, number of entries and the
+    /// entries themselves, all little-endian u64.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(BLOCK_INDEX_MAGIC)?;
+        writer.write_u64::<LittleEndian>(self.compressed_length)?;
+        writer.write_u64::<LittleEndian>(self.decompressed_length)?;
+        writer.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+        for entry in &self.entries {
+            writer.write_u64::<LittleEndian>(entry.compressed_offset)?;
+            writer.write_u64::<LittleEndian>(entry.decompressed_offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_index() -> BlockIndex {
+        let mut block_index = BlockIndex::new();
+        block_index.push(0, 30, 100).unwrap();
+        // empty block, moving only the end of compressed stream
+        block_index.push(30, 10, 0).unwrap();
+        block_index.push(40, 50, 100).unwrap();
+        block_index.push(90, 20, 7).unwrap();
+        block_index
+    }
+
+    // index as `write_to` stores it, from raw numbers
+    fn stored(compressed_length: u64, decompressed_length: u64, entries: &[(u64, u64)]) -> Vec<u8> {
+        let mut stored = BLOCK_INDEX_MAGIC.to_vec();
+        for n in [compressed_length, decompressed_length, entries.len() as u64] {
+            stored.extend_from_slice(&n.to_le_bytes());
+        }
+        for &(compressed_offset, decompressed_offset) in entries {
+            stored.extend_from_slice(&compressed_offset.to_le_bytes());
+            stored.extend_from_slice(&decompressed_offset.to_le_bytes());
+        }
+        stored
+    }
+
+    #[test]
+    fn index_survives_round_trip() {
+        let block_index = sample_index();
+        assert_eq!(block_index.compressed_length(), 110);
+        assert_eq!(block_index.decompressed_length(), 207);
+        assert_eq!(
+            block_index
+                .entries()
+                .iter()
+                .map(|entry| (entry.compressed_offset, entry.decompressed_offset))
+                .collect::<Vec<_>>(),
+            [(0, 0), (40, 100), (90, 200)]
+        );
+        assert_eq!(
+            [0, 99, 100, 206, 207].map(|offset| block_index.find(offset)),
+            [Some(0), Some(0), Some(1), Some(2), None]
+        );
+
+        let mut buf = Vec::new();
+        block_index.write_to(&mut buf).unwrap();
+        assert_eq!(buf, stored(110, 207, &[(0, 0), (40, 100), (90, 200)]));
+        assert_eq!(BlockIndex::read_from(&buf[..]).unwrap(), block_index);
+
+        // index of an empty stream has no entries
+        let mut buf = Vec::new();
+        BlockIndex::new().write_to(&mut buf).unwrap();
+        assert_eq!(BlockIndex::read_from(&buf[..]).unwrap(), BlockIndex::new());
+    }
+
+    #[test]
+    fn blocks_out_of_order_are_rejected() {
+        let mut block_index = sample_index();
+        let err = block_index.push(100, 10, 10).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn invalid_stored_index_is_rejected() {
+        let invalid = [
+            // entries out of order, or not starting at 0
+            stored(110, 207, &[(0, 0), (90, 200), (40, 100)]),
+            stored(110, 207, &[(0, 0), (40, 100), (40, 200)]),
+            stored(110, 207, &[(0, 0), (40, 100), (90, 100)]),
+            stored(110, 207, &[(40, 100), (90, 200)]),
+            // entries at or past the end of stream
+            stored(110, 207, &[(0, 0), (40, 100), (110, 200)]),
+            stored(110, 207, &[(0, 0), (40, 100), (90, 207)]),
+            // data with no entries
+            stored(110, 207, &[]),
+        ];
+        for buf in invalid {
+            let err = BlockIndex::read_from(&buf[..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{buf:?}");
+        }
+
+        let mut buf = stored(110, 207, &[(0, 0)]);
+        buf[0] = b'P';
+        let err = BlockIndex::read_from(&buf[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // entry count over what the data holds
+        let buf = stored(110, 207, &[(0, 0), (40, 100)]);
+        let err = BlockIndex::read_from(&buf[..buf.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
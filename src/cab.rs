@@ -0,0 +1,417 @@
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::inflate::inflate;
+use crate::lzx_decompress::lzx_decompress;
+
+// Microsoft Cabinet files, see https://learn.microsoft.com/en-us/previous-versions/bb417343(v=msdn.10)
+// Files are stored back to back in folders, and each folder is a sequence of data blocks
+// compressed as one stream, so extracting a file means decompressing its folder up to it.
+
+pub const CAB_SIGNATURE: &[u8; 4] = b"MSCF";
+
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+const COMPRESSION_MASK: u16 = 0x000F;
+const COMPRESSION_NONE: u16 = 0;
+const COMPRESSION_MSZIP: u16 = 1;
+const COMPRESSION_QUANTUM: u16 = 2;
+const COMPRESSION_LZX: u16 = 3;
+
+// folder indices above this one mean the file continues from or into another cabinet
+const MAX_FOLDER_INDEX: u16 = 0xFFFC;
+const ATTRIBUTE_NAME_IS_UTF: u16 = 0x80;
+
+const MAX_DATA_BLOCK_SIZE: usize = 32768;
+const MSZIP_SIGNATURE: &[u8; 2] = b"CK";
+
+/// A file stored in a cabinet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CabEntry {
+    pub name: String,
+    pub size: u32,
+    /// Index of the folder holding the file.
+    pub folder: u16,
+    /// Offset of the file within decompressed folder data.
+    pub folder_offset: u32,
+    /// MS-DOS date and time.
+    pub date: u16,
+    pub time: u16,
+    pub attributes: u16,
+}
+
+struct CabFolder {
+    data_offset: u64,
+    data_blocks: u16,
+    compression: u16,
+}
+
+/// Lists and extracts files of a single cabinet, files spanning several cabinets are not
+/// supported. The most recently decompressed folder is kept in memory, so extracting the files
+/// in order only decompresses every folder once.
+pub struct CabReader<R> {
+    inner: R,
+    folders: Vec<CabFolder>,
+    entries: Vec<CabEntry>,
+    data_reserve_size: u8,
+    cached_folder: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> CabReader<R> {
+    pub fn new(mut reader: R) -> io::Result<CabReader<R>> {
+        let cab_start = reader.stream_position()?;
+
+        let mut signature = [0_u8; 4];
+        reader.read_exact(&mut signature)?;
+        if &signature != CAB_SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "wrong cab signature"));
+        }
+
+        let _reserved1 = reader.read_u32::<LittleEndian>()?;
+        let _cabinet_size = reader.read_u32::<LittleEndian>()?;
+        let _reserved2 = reader.read_u32::<LittleEndian>()?;
+        let files_offset = reader.read_u32::<LittleEndian>()?;
+        let _reserved3 = reader.read_u32::<LittleEndian>()?;
+        let _version_minor = reader.read_u8()?;
+        let _version_major = reader.read_u8()?;
+        let folder_count = reader.read_u16::<LittleEndian>()?;
+        let file_count = reader.read_u16::<LittleEndian>()?;
+        let flags = reader.read_u16::<LittleEndian>()?;
+        let _set_id = reader.read_u16::<LittleEndian>()?;
+        let _cabinet_index = reader.read_u16::<LittleEndian>()?;
+
+        let (folder_reserve_size, data_reserve_size) = if flags & FLAG_RESERVE_PRESENT != 0 {
+            let header_reserve_size = reader.read_u16::<LittleEndian>()?;
+            let folder_reserve_size = reader.read_u8()?;
+            let data_reserve_size = reader.read_u8()?;
+            reader.seek(SeekFrom::Current(header_reserve_size as i64))?;
+            (folder_reserve_size, data_reserve_size)
+        } else {
+            (0, 0)
+        };
+
+        // names of previous and next cabinets and disks, not needed for anything
+        if flags & FLAG_PREV_CABINET != 0 {
+            read_null_terminated(&mut reader)?;
+            read_null_terminated(&mut reader)?;
+        }
+        if flags & FLAG_NEXT_CABINET != 0 {
+            read_null_terminated(&mut reader)?;
+            read_null_terminated(&mut reader)?;
+        }
+
+        let mut folders = Vec::with_capacity(folder_count as usize);
+        for _ in 0..folder_count {
+            let data_offset = reader.read_u32::<LittleEndian>()?;
+            let data_blocks = reader.read_u16::<LittleEndian>()?;
+            let compression = reader.read_u16::<LittleEndian>()?;
+            reader.seek(SeekFrom::Current(folder_reserve_size as i64))?;
+            folders.push(CabFolder {
+                // offsets are relative to the start of cabinet
+                data_offset: cab_start + data_offset as u64,
+                data_blocks,
+                compression,
+            });
+        }
+
+        reader.seek(SeekFrom::Start(cab_start + files_offset as u64))?;
+        let mut entries = Vec::with_capacity(file_count as usize);
+        for _ in 0..file_count {
+            let size = reader.read_u32::<LittleEndian>()?;
+            let folder_offset = reader.read_u32::<LittleEndian>()?;
+            let folder = reader.read_u16::<LittleEndian>()?;
+            let date = reader.read_u16::<LittleEndian>()?;
+            let time = reader.read_u16::<LittleEndian>()?;
+            let attributes = reader.read_u16::<LittleEndian>()?;
+            let name = read_null_terminated(&mut reader)?;
+            let name = if attributes & ATTRIBUTE_NAME_IS_UTF != 0 {
+                String::from_utf8_lossy(&name).into_owned()
+            } else {
+                // names in the system code page, latin-1 keeps them at least readable
+                name.iter().map(|&b| b as char).collect()
+            };
+
+            entries.push(CabEntry {
+                name,
+                size,
+                folder,
+                folder_offset,
+                date,
+                time,
+                attributes,
+            });
+        }
+
+        Ok(CabReader {
+            inner: reader,
+            folders,
+            entries,
+            data_reserve_size,
+            cached_folder: None,
+        })
+    }
+
+    pub fn entries(&self) -> &[CabEntry] {
+        &self.entries
+    }
+
+    /// Extracts the contents of the file at `index` in `entries()`.
+    pub fn read_entry(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "cab entry index out of range"))?;
+        if entry.folder > MAX_FOLDER_INDEX {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "cab files spanning several cabinets are not supported",
+            ));
+        }
+        let folder_index = entry.folder as usize;
+        let start = entry.folder_offset as usize;
+        let end = start + entry.size as usize;
+
+        if !matches!(&self.cached_folder, Some((cached, _)) if *cached == folder_index) {
+            let folder_data = self.decompress_folder(folder_index)?;
+            self.cached_folder = Some((folder_index, folder_data));
+        }
+        let (_, folder_data) = self.cached_folder.as_ref().unwrap();
+
+        let data = folder_data.get(start..end).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "cab file extends past the end of its folder",
+            )
+        })?;
+        Ok(data.to_vec())
+    }
+
+    fn decompress_folder(&mut self, folder_index: usize) -> io::Result<Vec<u8>> {
+        let folder = self
+            .folders
+            .get(folder_index)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "cab folder index out of range"))?;
+        let compression = folder.compression & COMPRESSION_MASK;
+        match compression {
+            COMPRESSION_NONE | COMPRESSION_MSZIP | COMPRESSION_LZX => (),
+            COMPRESSION_QUANTUM => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "cab quantum compression is not supported",
+                ))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "unknown cab compression type",
+                ))
+            }
+        }
+
+        self.inner.seek(SeekFrom::Start(folder.data_offset))?;
+
+        let mut buf_decompressed = Vec::new();
+        // lzx data blocks are pieces of a single bit stream, so it is decoded in one go
+        let mut lzx_compressed = Vec::new();
+        let mut lzx_decompressed_length = 0;
+
+        for _ in 0..folder.data_blocks {
+            let checksum = self.inner.read_u32::<LittleEndian>()?;
+            let compressed_length = self.inner.read_u16::<LittleEndian>()?;
+            let decompressed_length = self.inner.read_u16::<LittleEndian>()?;
+            self.inner
+                .seek(SeekFrom::Current(self.data_reserve_size as i64))?;
+
+            let mut data = vec![0; compressed_length as usize];
+            self.inner.read_exact(&mut data)?;
+
+            if checksum != 0 {
+                let mut lengths = [0_u8; 4];
+                lengths[..2].copy_from_slice(&compressed_length.to_le_bytes());
+                lengths[2..].copy_from_slice(&decompressed_length.to_le_bytes());
+                if cab_checksum(&lengths, cab_checksum(&data, 0)) != checksum {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "cab data block checksum mismatch",
+                    ));
+                }
+            }
+
+            let decompressed_length = decompressed_length as usize;
+            if decompressed_length > MAX_DATA_BLOCK_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "cab data block is larger than 32KB",
+                ));
+            }
+
+            match compression {
+                COMPRESSION_NONE => {
+                    if data.len() != decompressed_length {
+                        return Err(block_size_mismatch());
+                    }
+                    buf_decompressed.extend_from_slice(&data);
+                }
+                COMPRESSION_MSZIP => {
+                    // every block is a complete deflate stream, with the previous block's
+                    // output as its dictionary
+                    if !data.starts_with(MSZIP_SIGNATURE) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "wrong cab mszip block signature",
+                        ));
+                    }
+                    let block_start = buf_decompressed.len();
+                    inflate(
+                        &data[MSZIP_SIGNATURE.len()..],
+                        &mut buf_decompressed,
+                        block_start + decompressed_length,
+                    )?;
+                    if buf_decompressed.len() != block_start + decompressed_length {
+                        return Err(block_size_mismatch());
+                    }
+                }
+                _ => {
+                    lzx_compressed.extend_from_slice(&data);
+                    lzx_decompressed_length += decompressed_length;
+                }
+            }
+        }
+
+        if compression == COMPRESSION_LZX {
+            let window_bits = ((folder.compression >> 8) & 0x1F) as u32;
+            buf_decompressed = vec![0; lzx_decompressed_length];
+            lzx_decompress(&lzx_compressed, &mut buf_decompressed, window_bits)?;
+        }
+
+        Ok(buf_decompressed)
+    }
+}
+
+// xor of little-endian u32 words, with the tail bytes packed in reverse order
+fn cab_checksum(data: &[u8], seed: u32) -> u32 {
+    let mut checksum = seed;
+    let mut words = data.chunks_exact(4);
+    for word in words.by_ref() {
+        checksum ^= u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    let tail = words
+        .remainder()
+        .iter()
+        .fold(0_u32, |acc, &b| (acc << 8) | b as u32);
+    checksum ^ tail
+}
+
+fn read_null_terminated<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_u8()? {
+            0 => return Ok(buf),
+            b => buf.push(b),
+        }
+    }
+}
+
+fn block_size_mismatch() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "cab data block decompressed size mismatch",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // a.txt and b.txt in an mszip folder, c.txt in an lzx one, every data block with a checksum
+    const CABINET: &[u8] = b"MSCF\x00\x00\x00\x00\xc8\x00\x00\x00\x00\x00\x00\x00\x34\x00\x00\x00\
+        \x00\x00\x00\x00\x03\x01\x02\x00\x03\x00\x00\x00\xd2\x04\x00\x00\x76\x00\x00\x00\x01\x00\x01\
+        \x00\x98\x00\x00\x00\x01\x00\x03\x10\x30\x00\x00\x00\x00\x00\x00\x00\x00\x00\x21\x50\x00\x10\
+        \x20\x00a.txt\x00\x0b\x00\x00\x00\x30\x00\x00\x00\x00\x00\x21\x50\x00\x10\x20\x00b.txt\x00\
+        \x17\x00\x00\x00\x00\x00\x00\x00\x01\x00\x21\x50\x00\x10\x20\x00c.txt\x00\xc8\x19\x80\xfe\x1a\
+        \x00\x3b\x00\x43\x4b\x4b\xcb\x2c\x2a\x2e\x51\x48\xcb\xcc\x49\xd5\x01\x92\x84\xd9\xc5\xa9\xc9\
+        \xf9\x79\x29\x60\x0e\x00\x2d\x73\x24\x63\x28\x00\x17\x00\x00\x30\x70\x01\x01\x00\x00\x00\x01\
+        \x00\x00\x00\x01\x00\x00\x00lzx folder of a cabinet\x00";
+    // where data blocks of the two folders start
+    const MSZIP_DATA_OFFSET: usize = 0x76;
+    const LZX_DATA_OFFSET: usize = 0x98;
+
+    fn read_all(cabinet: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut reader = CabReader::new(Cursor::new(cabinet))?;
+        (0..reader.entries().len())
+            .map(|i| reader.read_entry(i))
+            .collect()
+    }
+
+    #[test]
+    fn files_are_extracted_from_mszip_and_lzx_folders() {
+        let reader = CabReader::new(Cursor::new(CABINET)).unwrap();
+        let entries: Vec<_> = reader
+            .entries()
+            .iter()
+            .map(|entry| {
+                (
+                    &entry.name[..],
+                    entry.size,
+                    entry.folder,
+                    entry.folder_offset,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a.txt", 48, 0, 0),
+                ("b.txt", 11, 0, 48),
+                ("c.txt", 23, 1, 0)
+            ]
+        );
+
+        let files = read_all(CABINET).unwrap();
+        assert_eq!(files[0], b"first file, ".repeat(4));
+        assert_eq!(files[1], b"second file");
+        assert_eq!(files[2], b"lzx folder of a cabinet");
+    }
+
+    #[test]
+    fn data_block_checksums_are_verified() {
+        for offset in [MSZIP_DATA_OFFSET, LZX_DATA_OFFSET] {
+            let mut cabinet = CABINET.to_vec();
+            cabinet[offset] ^= 1;
+            let err = read_all(&cabinet).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), "cab data block checksum mismatch");
+
+            // checksum of 0 is not checked
+            cabinet[offset..offset + 4].copy_from_slice(&[0; 4]);
+            assert_eq!(read_all(&cabinet).unwrap(), read_all(CABINET).unwrap());
+        }
+
+        // payload not matching the checksum
+        let mut cabinet = CABINET.to_vec();
+        *cabinet.last_mut().unwrap() = b'?';
+        let err = read_all(&cabinet).unwrap_err();
+        assert_eq!(err.to_string(), "cab data block checksum mismatch");
+    }
+
+    #[test]
+    fn malformed_cabinets_are_errors() {
+        let mut reader = CabReader::new(Cursor::new(CABINET)).unwrap();
+        let err = reader.read_entry(3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let mut cabinet = CABINET.to_vec();
+        cabinet[3] = b'G';
+        let err = CabReader::new(Cursor::new(&cabinet)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        for len in [20, 60, MSZIP_DATA_OFFSET + 20, CABINET.len() - 1] {
+            assert!(read_all(&CABINET[..len]).is_err(), "{len}");
+        }
+    }
+}
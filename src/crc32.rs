@@ -0,0 +1,50 @@
+// reflected CRC-32 as used by zlib, zip and RtlComputeCrc32
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: !0 }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        for &byte in input {
+            self.crc = TABLE[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn digest(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
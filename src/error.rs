@@ -0,0 +1,64 @@
+use core::fmt;
+
+// Error of the parts of paku that work without `std`: block codecs, hashes and sans-IO decoders.
+// With `std` it converts into `std::io::Error` of the same kind, which is what readers return,
+// so `?` works on it in functions returning `io::Result`.
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Subset of `std::io::ErrorKind` that the `std`-less parts can return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidData,
+    InvalidInput,
+    UnexpectedEof,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: &'static str,
+}
+
+impl Error {
+    pub const fn new(kind: ErrorKind, message: &'static str) -> Error {
+        Error { kind, message }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<ErrorKind> for std::io::ErrorKind {
+    fn from(kind: ErrorKind) -> std::io::ErrorKind {
+        match kind {
+            ErrorKind::InvalidData => std::io::ErrorKind::InvalidData,
+            ErrorKind::InvalidInput => std::io::ErrorKind::InvalidInput,
+            ErrorKind::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            ErrorKind::Unsupported => std::io::ErrorKind::Unsupported,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> std::io::Error {
+        std::io::Error::new(error.kind.into(), error)
+    }
+}
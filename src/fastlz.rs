@@ -0,0 +1,170 @@
+use core::cmp;
+use std::io::{self, BufRead, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::adler32::Adler32;
+use crate::fastlz_decompress::fastlz_decompress;
+
+// frame format specifies both lengths as unsigned shorts
+const FASTLZ_BUF_SIZE: usize = 64 * 1024;
+
+const BLOCK_TYPE_COMPRESSED: u8 = 0x01;
+const BLOCK_WITH_CHECKSUM: u8 = 0x10;
+
+/// This reader is for streams written by Netty's `FastLzFrameEncoder`:
+/// https://github.com/netty/netty/blob/4.1/codec/src/main/java/io/netty/handler/codec/compression/FastLzFrameDecoder.java
+/// Each chunk is "FLZ" magic, options byte, optional big-endian Adler-32 checksum of the
+/// decompressed data, chunk length, original length (compressed chunks only) and the data.
+pub struct FastLzFrameReader<R> {
+    inner: R,
+    buf_compressed: Box<[u8]>,
+    buf_decompressed: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    check_checksum: bool,
+}
+
+impl<R: Read> FastLzFrameReader<R> {
+    pub fn new(reader: R, check_checksum: bool) -> FastLzFrameReader<R> {
+        FastLzFrameReader {
+            inner: reader,
+            buf_compressed: vec![0; FASTLZ_BUF_SIZE].into_boxed_slice(),
+            buf_decompressed: vec![0; FASTLZ_BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            check_checksum,
+        }
+    }
+
+    fn fill_buf_decompressed(&mut self) -> io::Result<()> {
+        assert_eq!(self.pos, self.cap);
+
+        // empty chunk does not mean end of stream
+        while self.read_chunk()? && self.cap == 0 {}
+        Ok(())
+    }
+
+    // reads the next chunk into `buf_decompressed`, `false` at the end of stream
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        // looking for 'F', if there is EOF, then we are done
+        let magic_f = match self.inner.read_u8() {
+            Ok(b) => b,
+            Err(err) => {
+                return if err.kind() == ErrorKind::UnexpectedEof {
+                    self.pos = 0;
+                    self.cap = 0;
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+        };
+        if magic_f != b'F' {
+            return Err(Error::new(ErrorKind::InvalidData, "wrong fastlz magic"));
+        }
+
+        let mut magic_leftovers = [0_u8; 2];
+        self.inner.read_exact(&mut magic_leftovers)?;
+        if &magic_leftovers != b"LZ" {
+            return Err(Error::new(ErrorKind::InvalidData, "wrong fastlz magic"));
+        }
+
+        let options = self.inner.read_u8()?;
+        let is_compressed = options & BLOCK_TYPE_COMPRESSED != 0;
+        let has_checksum = options & BLOCK_WITH_CHECKSUM != 0;
+
+        let original_checksum = if has_checksum {
+            self.inner.read_u32::<BigEndian>()?
+        } else {
+            0
+        };
+        let chunk_length = self.inner.read_u16::<BigEndian>()? as usize;
+
+        if is_compressed {
+            let original_length = self.inner.read_u16::<BigEndian>()? as usize;
+
+            let buf_compressed_capped = &mut self.buf_compressed[..chunk_length];
+            self.inner.read_exact(buf_compressed_capped)?;
+
+            let buf_decompressed_capped = &mut self.buf_decompressed[..original_length];
+            let decompressed_length =
+                fastlz_decompress(buf_compressed_capped, buf_decompressed_capped)?;
+            if decompressed_length != original_length {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "fastlz decompressed length mismatch",
+                ));
+            }
+
+            self.pos = 0;
+            self.cap = decompressed_length;
+        } else {
+            let buf_decompressed_capped = &mut self.buf_decompressed[..chunk_length];
+            self.inner.read_exact(buf_decompressed_capped)?;
+
+            self.pos = 0;
+            self.cap = chunk_length;
+        }
+
+        if has_checksum && self.check_checksum {
+            let mut adler32 = Adler32::new();
+            adler32.update(&self.buf_decompressed[..self.cap]);
+
+            if original_checksum != adler32.digest() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "fastlz checksum mismatch",
+                ));
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for FastLzFrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = {
+            let mut rem = self.fill_buf()?;
+            rem.read(buf)?
+        };
+        self.consume(bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read> BufRead for FastLzFrameReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // If we've reached the end of our internal buffer then we need to fetch
+        // some more data from the underlying reader.
+        // Branch using `>=` instead of the more correct `==`
+        // to tell the compiler that the pos..cap slice is always valid.
+        if self.pos >= self.cap {
+            self.fill_buf_decompressed()?;
+        }
+        Ok(&self.buf_decompressed[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn many_empty_chunks_are_skipped() {
+        let mut stream = b"FLZ\0\0\0".repeat(2_000_000);
+        stream.extend_from_slice(b"FLZ\0\0\x03abc");
+        stream.extend_from_slice(b"FLZ\0\0\0");
+
+        let mut buf_decompressed = Vec::new();
+        FastLzFrameReader::new(&stream[..], true)
+            .read_to_end(&mut buf_decompressed)
+            .unwrap();
+        assert_eq!(buf_decompressed, b"abc");
+    }
+}
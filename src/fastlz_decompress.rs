@@ -0,0 +1,122 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// level 2 back references with this distance marker carry an extra 16 bit distance
+const LEVEL_2_MAX_DISTANCE: usize = 8191;
+
+/// goal is to match `fastlz_decompress` from https://github.com/ariya/FastLZ and its Java port
+/// in Netty, which is used by `FastLzFrameDecoder`.
+///
+/// Compression level (1 or 2) is taken from the top bits of the first byte. Output is limited by
+/// the size of `buf_decompressed` and the number of decompressed bytes is returned.
+pub fn fastlz_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let first = match buf_compressed.first() {
+        Some(&b) => b,
+        None => return Ok(0),
+    };
+    let level_2 = match first >> 5 {
+        0 => false,
+        1 => true,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unknown fastlz compression level",
+            ))
+        }
+    };
+
+    let mut in_pos = 1;
+    let mut out_pos = 0;
+    let mut ctrl = (first & 0x1F) as usize;
+
+    loop {
+        if ctrl < (1 << 5) {
+            // literal run
+            let run_len = ctrl + 1;
+
+            if buf_compressed.len() - in_pos < run_len {
+                return Err(truncated());
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+
+            buf_decompressed[out_pos..(out_pos + run_len)]
+                .copy_from_slice(&buf_compressed[in_pos..(in_pos + run_len)]);
+
+            in_pos += run_len;
+            out_pos += run_len;
+        } else {
+            // back reference
+            let mut run_len = (ctrl >> 5) + 2;
+            let ref_offset_msb = (ctrl & 0x1F) << 8;
+
+            if run_len == 9 {
+                if level_2 {
+                    loop {
+                        let code = read_u8(buf_compressed, &mut in_pos)?;
+                        run_len += code as usize;
+                        if code != 0xFF {
+                            break;
+                        }
+                    }
+                } else {
+                    run_len += read_u8(buf_compressed, &mut in_pos)? as usize;
+                }
+            }
+
+            let ref_offset_lsb = read_u8(buf_compressed, &mut in_pos)? as usize;
+            let ref_offset = if level_2 && ref_offset_lsb == 0xFF && ref_offset_msb == (0x1F << 8) {
+                // match from 16 bit distance
+                let far_msb = read_u8(buf_compressed, &mut in_pos)? as usize;
+                let far_lsb = read_u8(buf_compressed, &mut in_pos)? as usize;
+                ((far_msb << 8) | far_lsb) + LEVEL_2_MAX_DISTANCE + 1
+            } else {
+                ref_offset_msb + ref_offset_lsb + 1
+            };
+
+            if ref_offset > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "fastlz back reference points before start of output",
+                ));
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+
+            copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+            out_pos += run_len;
+        }
+
+        if in_pos >= buf_compressed.len() {
+            return Ok(out_pos);
+        }
+        ctrl = buf_compressed[in_pos] as usize;
+        in_pos += 1;
+    }
+}
+
+fn read_u8(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u8> {
+    let b = *buf_compressed.get(*in_pos).ok_or_else(truncated)?;
+    *in_pos += 1;
+    Ok(b)
+}
+
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "fastlz compressed data is truncated",
+    )
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "fastlz decompressed data does not fit into output buffer",
+    )
+}
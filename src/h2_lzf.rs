@@ -0,0 +1,248 @@
+use core::cmp;
+use std::io::{self, BufRead, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::h2_lzf_decompress::h2_lzf_decompress;
+use crate::lzf_decompress::LZF_MAX_EXPANSION;
+
+// same as default buffer size of LZFOutputStream
+const H2_LZF_STARTING_BUF_SIZE: usize = 128 * 1024;
+
+const H2_LZF_STREAM_MAGIC: &[u8; 4] = b"H2IS";
+
+const H2_COMPRESS_NO: u8 = 0;
+const H2_COMPRESS_LZF: u8 = 1;
+const H2_COMPRESS_DEFLATE: u8 = 2;
+
+/// Expands data produced by `CompressTool.compress` of the H2 database, such as values of
+/// compressed columns and results of the `COMPRESS` function: algorithm byte, variable length
+/// int with the uncompressed length, and the compressed data itself.
+pub fn h2_compress_tool_expand(buf: &[u8]) -> io::Result<Vec<u8>> {
+    let (&algorithm, buf) = buf.split_first().ok_or_else(truncated)?;
+    let (original_length, buf) = read_variable_int(buf)?;
+
+    match algorithm {
+        H2_COMPRESS_NO => {
+            let buf = buf.get(..original_length).ok_or_else(truncated)?;
+            Ok(buf.to_vec())
+        }
+        H2_COMPRESS_LZF => {
+            if original_length / LZF_MAX_EXPANSION > buf.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "h2 lzf uncompressed length is too large for compressed data",
+                ));
+            }
+            let mut buf_decompressed = vec![0; original_length];
+            h2_lzf_decompress(buf, &mut buf_decompressed)?;
+            Ok(buf_decompressed)
+        }
+        H2_COMPRESS_DEFLATE => Err(Error::new(
+            ErrorKind::Unsupported,
+            "h2 deflate compression is not supported",
+        )),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unknown h2 compression algorithm",
+        )),
+    }
+}
+
+// matches `CompressTool.readVariableInt`, returns value and the rest of the buffer
+fn read_variable_int(buf: &[u8]) -> io::Result<(usize, &[u8])> {
+    let (&first, buf) = buf.split_first().ok_or_else(truncated)?;
+    let first = first as usize;
+    let (extra_bytes, value) = match first {
+        0x00..=0x7F => (0, first),
+        0x80..=0xBF => (1, first & 0x3F),
+        0xC0..=0xDF => (2, first & 0x1F),
+        0xE0..=0xEF => (3, first & 0x0F),
+        _ => (4, 0),
+    };
+    let extra = buf.get(..extra_bytes).ok_or_else(truncated)?;
+    let value = extra.iter().fold(value, |acc, &b| (acc << 8) | b as usize);
+    Ok((value, &buf[extra_bytes..]))
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "h2 compressed data is truncated")
+}
+
+/// This reader is for streams written by `LZFOutputStream` of the H2 database, such as
+/// compressed backups and lob storage:
+/// https://github.com/h2database/h2database/blob/master/h2/src/main/org/h2/compress/LZFInputStream.java
+/// Stream starts with "H2IS" magic and is followed by chunks, each either a negative big-endian
+/// int length and that many stored bytes, or compressed length, uncompressed length and lzf data.
+pub struct H2LzfReader<R> {
+    inner: R,
+    buf_compressed: Vec<u8>,
+    buf_decompressed: Vec<u8>,
+    pos: usize,
+    cap: usize,
+    saw_magic: bool,
+}
+
+impl<R: Read> H2LzfReader<R> {
+    pub fn new(reader: R) -> H2LzfReader<R> {
+        H2LzfReader {
+            inner: reader,
+            buf_compressed: vec![0; H2_LZF_STARTING_BUF_SIZE],
+            buf_decompressed: vec![0; H2_LZF_STARTING_BUF_SIZE],
+            pos: 0,
+            cap: 0,
+            saw_magic: false,
+        }
+    }
+
+    fn fill_buf_decompressed(&mut self) -> io::Result<()> {
+        assert_eq!(self.pos, self.cap);
+
+        // empty chunk does not mean end of stream
+        while self.read_chunk()? && self.cap == 0 {}
+        Ok(())
+    }
+
+    // reads the next chunk into `buf_decompressed`, `false` at the end of stream
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        if !self.saw_magic {
+            let mut magic = [0_u8; 4];
+            self.inner.read_exact(&mut magic)?;
+            if &magic != H2_LZF_STREAM_MAGIC {
+                return Err(Error::new(ErrorKind::InvalidData, "wrong h2 lzf magic"));
+            }
+            self.saw_magic = true;
+        }
+
+        // looking for chunk length, if there is EOF, then we are done
+        let first = match self.inner.read_u8() {
+            Ok(b) => b,
+            Err(err) => {
+                return if err.kind() == ErrorKind::UnexpectedEof {
+                    self.pos = 0;
+                    self.cap = 0;
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+        };
+        let mut rest = [0_u8; 3];
+        self.inner.read_exact(&mut rest)?;
+        let chunk_length = i32::from_be_bytes([first, rest[0], rest[1], rest[2]]);
+
+        if chunk_length < 0 {
+            // stored chunk
+            let chunk_length = chunk_length.unsigned_abs() as usize;
+            read_payload(&mut self.inner, chunk_length, &mut self.buf_decompressed)?;
+
+            self.pos = 0;
+            self.cap = chunk_length;
+        } else {
+            // compressed chunk
+            let chunk_length = chunk_length as usize;
+            let original_length = self.inner.read_u32::<BigEndian>()? as usize;
+
+            if original_length / LZF_MAX_EXPANSION > chunk_length {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "h2 lzf uncompressed length is too large for compressed data",
+                ));
+            }
+            read_payload(&mut self.inner, chunk_length, &mut self.buf_compressed)?;
+            // only grown once the compressed data is actually there, so it is bounded by it
+            if self.buf_decompressed.len() < original_length {
+                self.buf_decompressed.resize(original_length, 0);
+            }
+
+            let buf_decompressed_capped = &mut self.buf_decompressed[..original_length];
+            let decompressed_length =
+                h2_lzf_decompress(&self.buf_compressed, buf_decompressed_capped)?;
+
+            self.pos = 0;
+            self.cap = decompressed_length;
+        }
+
+        Ok(true)
+    }
+}
+
+// replaces contents of `buf` with `length` bytes of `reader`, growing it only as data comes in,
+// so that a corrupted length does not allocate more than the stream holds
+fn read_payload<R: Read>(reader: &mut R, length: usize, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.clear();
+    reader.take(length as u64).read_to_end(buf)?;
+    if buf.len() != length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "h2 lzf chunk is truncated",
+        ));
+    }
+    Ok(())
+}
+
+impl<R: Read> Read for H2LzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = {
+            let mut rem = self.fill_buf()?;
+            rem.read(buf)?
+        };
+        self.consume(bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read> BufRead for H2LzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // If we've reached the end of our internal buffer then we need to fetch
+        // some more data from the underlying reader.
+        // Branch using `>=` instead of the more correct `==`
+        // to tell the compiler that the pos..cap slice is always valid.
+        if self.pos >= self.cap {
+            self.fill_buf_decompressed()?;
+        }
+        Ok(&self.buf_decompressed[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(stream: &[u8]) -> io::Result<Vec<u8>> {
+        let mut buf_decompressed = Vec::new();
+        H2LzfReader::new(stream).read_to_end(&mut buf_decompressed)?;
+        Ok(buf_decompressed)
+    }
+
+    #[test]
+    fn many_empty_chunks_are_skipped() {
+        let mut stream = H2_LZF_STREAM_MAGIC.to_vec();
+        stream.extend_from_slice(&[0; 8].repeat(2_000_000));
+        stream.extend_from_slice(&(-3_i32).to_be_bytes());
+        stream.extend_from_slice(b"abc");
+        stream.extend_from_slice(&[0; 8]);
+
+        assert_eq!(read_all(&stream).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn lengths_past_end_of_stream_are_truncated() {
+        let mut stream = H2_LZF_STREAM_MAGIC.to_vec();
+        stream.extend_from_slice(&i32::MIN.to_be_bytes());
+        stream.extend_from_slice(b"abc");
+        let err = read_all(&stream).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let mut stream = H2_LZF_STREAM_MAGIC.to_vec();
+        stream.extend_from_slice(&i32::MAX.to_be_bytes());
+        stream.extend_from_slice(&u32::MAX.to_be_bytes());
+        stream.extend_from_slice(b"abc");
+        let err = read_all(&stream).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
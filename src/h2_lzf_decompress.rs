@@ -0,0 +1,82 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+/// goal is to match `CompressLZF.expand` from the H2 database:
+/// https://github.com/h2database/h2database/blob/master/h2/src/main/org/h2/compress/CompressLZF.java
+///
+/// Unlike `lzf_decompress`, decoding is driven by the expected output length instead of the
+/// input length, so `buf_decompressed` has to be exactly the size H2 recorded next to the data.
+/// Anything left in `buf_compressed` after the output is filled is ignored, same as in H2.
+pub fn h2_lzf_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+
+    while out_pos < buf_decompressed.len() {
+        let ctrl = *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
+        in_pos += 1;
+
+        if ctrl < (1 << 5) {
+            // literal run
+            let run_len = ctrl + 1;
+
+            if buf_compressed.len() - in_pos < run_len {
+                return Err(truncated());
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+
+            buf_decompressed[out_pos..(out_pos + run_len)]
+                .copy_from_slice(&buf_compressed[in_pos..(in_pos + run_len)]);
+
+            in_pos += run_len;
+            out_pos += run_len;
+        } else {
+            // back reference, highest 3 bits are the length, 7 means that next byte adds to it
+            let mut run_len = ctrl >> 5;
+            if run_len == 7 {
+                run_len += *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
+                in_pos += 1;
+            }
+            // minimum back reference is 3 bytes, so 2 was subtracted before storing it
+            run_len += 2;
+
+            let ref_offset_msb = (ctrl & 0x1F) << 8;
+            let ref_offset_lsb = *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
+            in_pos += 1;
+            let ref_offset = ref_offset_msb + ref_offset_lsb + 1;
+            if ref_offset > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "h2 lzf back reference points before start of output",
+                ));
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+
+            copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+            out_pos += run_len;
+        }
+    }
+
+    Ok(out_pos)
+}
+
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "h2 lzf compressed data is truncated",
+    )
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "h2 lzf decompressed data does not fit into output buffer",
+    )
+}
@@ -0,0 +1,92 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+// longest code of any format, deflate and xpress codes are at most 15 bits long, lzx ones 16
+pub const MAX_CODE_LENGTH: usize = 16;
+
+/// Canonical huffman code, decoded one bit at a time the same way as `decode` in zlib's
+/// https://github.com/madler/zlib/blob/master/contrib/puff/puff.c
+pub struct Huffman {
+    // number of codes of each length
+    count: [u16; MAX_CODE_LENGTH + 1],
+    // symbols ordered by their codes
+    symbol: Vec<u16>,
+    // longest code the format allows, decoding gives up after that many bits
+    max_length: usize,
+}
+
+impl Huffman {
+    /// Builds the code from code lengths indexed by symbol, 0 meaning the symbol is not used.
+    /// Incomplete codes are allowed, over-subscribed ones are not, and neither are lengths over
+    /// `max_length`, which can't be more than `MAX_CODE_LENGTH`.
+    pub fn new(lengths: &[u8], max_length: usize) -> error::Result<Huffman> {
+        debug_assert!(max_length <= MAX_CODE_LENGTH);
+        let mut count = [0_u16; MAX_CODE_LENGTH + 1];
+        for &length in lengths {
+            if length as usize > max_length {
+                return Err(invalid_code());
+            }
+            count[length as usize] += 1;
+        }
+
+        // check for an over-subscribed set of lengths
+        let mut left = 1_i32;
+        for &n in &count[1..] {
+            left <<= 1;
+            left -= n as i32;
+            if left < 0 {
+                return Err(invalid_code());
+            }
+        }
+
+        let mut offsets = [0_u16; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + count[length];
+        }
+
+        let mut symbol = vec![0; offsets[MAX_CODE_LENGTH + 1] as usize];
+        for (sym, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbol[offsets[length as usize] as usize] = sym as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        count[0] = 0;
+        Ok(Huffman {
+            count,
+            symbol,
+            max_length,
+        })
+    }
+
+    /// Decodes one symbol, with `next_bit` supplying code bits starting from the most
+    /// significant one. It is called at most `max_length` times.
+    pub fn decode(&self, mut next_bit: impl FnMut() -> error::Result<u32>) -> error::Result<u16> {
+        let mut code = 0_i32; // bits of the code read so far
+        let mut first = 0_i32; // first code of the current length
+        let mut index = 0_i32; // index of the first code of the current length in `symbol`
+
+        for &count in &self.count[1..=self.max_length] {
+            code |= next_bit()? as i32;
+            let count = count as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "huffman code is not in the table",
+        ))
+    }
+}
+
+fn invalid_code() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid huffman code lengths")
+}
@@ -0,0 +1,391 @@
+use alloc::vec::Vec;
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::huffman::Huffman;
+
+// Raw deflate (RFC 1951) decoder, closely following zlib's
+// https://github.com/madler/zlib/blob/master/contrib/puff/puff.c
+
+const MAX_CODE_LENGTH: usize = 15;
+const MAX_LITERAL_LENGTH_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+const FIXED_LITERAL_LENGTH_CODES: usize = 288;
+const END_OF_BLOCK: u16 = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order in which code length code lengths are stored in dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a raw deflate stream, appending the output to `buf_decompressed`.
+///
+/// Back references may reach into whatever `buf_decompressed` already holds, which is how
+/// preset dictionaries and formats sharing a window between streams (such as MSZIP) work.
+/// Decoding stops after the final block and fails if `buf_decompressed` would grow past
+/// `max_length` bytes. Returns the number of compressed bytes consumed.
+pub fn inflate(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut Vec<u8>,
+    max_length: usize,
+) -> error::Result<usize> {
+    let mut state = Inflate {
+        bits: BitReader::new(buf_compressed),
+        out: buf_decompressed,
+        max_length,
+    };
+
+    loop {
+        let last = state.bits.read(1)?;
+        match state.bits.read(2)? {
+            0 => state.stored()?,
+            1 => state.fixed()?,
+            2 => state.dynamic()?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid deflate block type",
+                ))
+            }
+        }
+        if last == 1 {
+            return Ok(state.bits.in_pos);
+        }
+    }
+}
+
+struct Inflate<'a, 'b> {
+    bits: BitReader<'a>,
+    out: &'b mut Vec<u8>,
+    max_length: usize,
+}
+
+impl Inflate<'_, '_> {
+    fn stored(&mut self) -> error::Result<()> {
+        // stored blocks start at a byte boundary, with length and its one's complement
+        self.bits.align_to_byte();
+        let header = self.bits.read_bytes(4)?;
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        let length_complement = u16::from_le_bytes([header[2], header[3]]);
+        if length != !length_complement {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "deflate stored block length does not match its complement",
+            ));
+        }
+
+        self.check_room(length as usize)?;
+        let data = self.bits.read_bytes(length as usize)?;
+        self.out.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn fixed(&mut self) -> error::Result<()> {
+        let mut lengths = [0_u8; FIXED_LITERAL_LENGTH_CODES + MAX_DISTANCE_CODES];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..FIXED_LITERAL_LENGTH_CODES].fill(8);
+        lengths[FIXED_LITERAL_LENGTH_CODES..].fill(5);
+
+        let literal_length_code =
+            Huffman::new(&lengths[..FIXED_LITERAL_LENGTH_CODES], MAX_CODE_LENGTH)?;
+        let distance_code = Huffman::new(&lengths[FIXED_LITERAL_LENGTH_CODES..], MAX_CODE_LENGTH)?;
+        self.codes(&literal_length_code, &distance_code)
+    }
+
+    fn dynamic(&mut self) -> error::Result<()> {
+        let literal_length_count = self.bits.read(5)? as usize + 257;
+        let distance_count = self.bits.read(5)? as usize + 1;
+        let code_length_count = self.bits.read(4)? as usize + 4;
+        if literal_length_count > MAX_LITERAL_LENGTH_CODES || distance_count > MAX_DISTANCE_CODES {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "deflate dynamic block has too many codes",
+            ));
+        }
+
+        let mut lengths = [0_u8; MAX_LITERAL_LENGTH_CODES + MAX_DISTANCE_CODES];
+        for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+            lengths[index] = self.bits.read(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths[..19], MAX_CODE_LENGTH)?;
+
+        let total = literal_length_count + distance_count;
+        let mut index = 0;
+        while index < total {
+            let symbol = self.decode(&code_length_code)?;
+            if symbol < 16 {
+                lengths[index] = symbol as u8;
+                index += 1;
+                continue;
+            }
+
+            let (length, repeat) = match symbol {
+                16 => {
+                    if index == 0 {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "deflate repeat code with no previous length",
+                        ));
+                    }
+                    (lengths[index - 1], 3 + self.bits.read(2)? as usize)
+                }
+                17 => (0, 3 + self.bits.read(3)? as usize),
+                _ => (0, 11 + self.bits.read(7)? as usize),
+            };
+            if total - index < repeat {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "deflate code lengths repeat past the end",
+                ));
+            }
+            lengths[index..index + repeat].fill(length);
+            index += repeat;
+        }
+
+        if lengths[END_OF_BLOCK as usize] == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "deflate dynamic block has no end of block code",
+            ));
+        }
+
+        let literal_length_code = Huffman::new(&lengths[..literal_length_count], MAX_CODE_LENGTH)?;
+        let distance_code = Huffman::new(&lengths[literal_length_count..total], MAX_CODE_LENGTH)?;
+        self.codes(&literal_length_code, &distance_code)
+    }
+
+    // decodes literals and back references until the end of block code
+    fn codes(
+        &mut self,
+        literal_length_code: &Huffman,
+        distance_code: &Huffman,
+    ) -> error::Result<()> {
+        loop {
+            let symbol = self.decode(literal_length_code)?;
+            if symbol < 256 {
+                self.check_room(1)?;
+                self.out.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                return Ok(());
+            }
+
+            let symbol = (symbol - 257) as usize;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid deflate length code",
+                ));
+            }
+            let run_len = LENGTH_BASE[symbol] as usize
+                + self.bits.read(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = self.decode(distance_code)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid deflate distance code",
+                ));
+            }
+            let ref_offset = DISTANCE_BASE[symbol] as usize
+                + self.bits.read(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+            if ref_offset > self.out.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "deflate back reference points before start of output",
+                ));
+            }
+            self.check_room(run_len)?;
+
+            let ref_pos = self.out.len() - ref_offset;
+            if ref_offset >= run_len {
+                self.out.extend_from_within(ref_pos..ref_pos + run_len);
+            } else {
+                for i in 0..run_len {
+                    let byte = self.out[ref_pos + i];
+                    self.out.push(byte);
+                }
+            }
+        }
+    }
+
+    fn decode(&mut self, code: &Huffman) -> error::Result<u16> {
+        let bits = &mut self.bits;
+        code.decode(|| bits.read(1))
+    }
+
+    fn check_room(&self, len: usize) -> error::Result<()> {
+        if self.max_length.saturating_sub(self.out.len()) < len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "inflated data is over the allowed maximum length",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// bits are taken starting from the least significant bit of each byte
+struct BitReader<'a> {
+    buf: &'a [u8],
+    in_pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            buf,
+            in_pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn read(&mut self, n: u32) -> error::Result<u32> {
+        while self.bit_count < n {
+            let byte = *self.buf.get(self.in_pos).ok_or_else(truncated)?;
+            self.in_pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1 << n) - 1);
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    fn read_bytes(&mut self, n: usize) -> error::Result<&'a [u8]> {
+        if self.buf.len() - self.in_pos < n {
+            return Err(truncated());
+        }
+        let bytes = &self.buf[self.in_pos..self.in_pos + n];
+        self.in_pos += n;
+        Ok(bytes)
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "deflate compressed data is truncated",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "abc" in a stored block, not the last one
+    const STORED: &[u8] = b"\x00\x03\x00\xfc\xffabc";
+    // "hello hello hello, deflate world" with fixed codes
+    const FIXED: &[u8] =
+        b"\xcb\x48\xcd\xc9\xc9\x57\xc8\x40\x90\x3a\x0a\x29\xa9\x69\x39\x89\x25\xa9\
+        \x0a\xe5\xf9\x45\x39\x29\x00";
+    const FIXED_DATA: &[u8] = b"hello hello hello, deflate world";
+    const DYNAMIC: &[u8] = b"\x75\xcb\xd1\x09\x80\x30\x0c\x45\xd1\x55\xde\x00\x4e\xe0\x36\x31\x49\
+        \x69\x90\x26\xd0\x54\xd4\xed\x2d\xe8\xaf\xe7\xf7\x72\x89\x5e\xdb\xc4\x2c\x0b\xe4\x76\x6a\xc6\
+        \xa8\x47\x29\x8d\x1c\x1c\xa2\x89\x12\x1d\xb9\xeb\xa9\x82\xa1\xd7\x58\xa1\x9f\x31\xcd\x3d\x22\
+        \xcc\xdc\x33\x6b\xff\x2f\x0f";
+    const DYNAMIC_DATA: &[u8] = b"aaaaaaaabbbbccd, dynamic huffman codes for skewed text: \
+        eeeeeeeettttaaaoooiinnsshreeeeeeeettttaaaoooiinnsshr";
+    // "world, hello world" with "hello world" as dictionary
+    const WITH_DICTIONARY: &[u8] = b"\x03\x13\x3a\x0a\x19\x08\x11\x00";
+
+    #[test]
+    fn blocks_of_every_type_are_inflated() {
+        for (buf_compressed, data) in [(FIXED, FIXED_DATA), (DYNAMIC, DYNAMIC_DATA)] {
+            let mut buf_decompressed = Vec::new();
+            let len = inflate(buf_compressed, &mut buf_decompressed, usize::MAX).unwrap();
+            assert_eq!(len, buf_compressed.len());
+            assert_eq!(buf_decompressed, data);
+        }
+
+        // stored block followed by the last one, and data after the stream left alone
+        let buf_compressed = [STORED, FIXED, b"trailing"].concat();
+        let mut buf_decompressed = Vec::new();
+        let len = inflate(&buf_compressed, &mut buf_decompressed, usize::MAX).unwrap();
+        assert_eq!(len, STORED.len() + FIXED.len());
+        assert_eq!(buf_decompressed, [b"abc", FIXED_DATA].concat());
+    }
+
+    #[test]
+    fn back_references_reach_into_previous_output() {
+        let mut buf_decompressed = b"hello world".to_vec();
+        inflate(WITH_DICTIONARY, &mut buf_decompressed, usize::MAX).unwrap();
+        assert_eq!(buf_decompressed, b"hello worldworld, hello world");
+
+        let err = inflate(WITH_DICTIONARY, &mut Vec::new(), usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn output_is_limited_to_max_length() {
+        // limit counts what the output already holds
+        let mut buf_decompressed = b"xy".to_vec();
+        inflate(DYNAMIC, &mut buf_decompressed, 2 + DYNAMIC_DATA.len()).unwrap();
+        assert_eq!(buf_decompressed.len(), 2 + DYNAMIC_DATA.len());
+
+        for buf_compressed in [FIXED, DYNAMIC, b"\x01\x03\x00\xfc\xffabc"] {
+            let mut buf_decompressed = b"xy".to_vec();
+            let err = inflate(buf_compressed, &mut buf_decompressed, 4).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn malformed_streams_are_errors() {
+        let invalid: [&[u8]; 3] = [
+            // reserved block type
+            b"\x07",
+            // stored length not matching its complement
+            b"\x01\x03\x00\xfc\xfeabc",
+            // stored block not being the last one, with nothing after it
+            STORED,
+        ];
+        for buf_compressed in invalid {
+            let err = inflate(buf_compressed, &mut Vec::new(), usize::MAX).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{buf_compressed:?}");
+        }
+
+        for buf_compressed in [FIXED, DYNAMIC] {
+            for len in 0..buf_compressed.len() {
+                let err = inflate(&buf_compressed[..len], &mut Vec::new(), usize::MAX).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::InvalidData, "{len}");
+            }
+            // no single bit off makes inflating panic
+            for pos in 0..buf_compressed.len() {
+                for bit in 0..8 {
+                    let mut corrupt = buf_compressed.to_vec();
+                    corrupt[pos] ^= 1 << bit;
+                    let _ = inflate(&corrupt, &mut Vec::new(), 1024);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::lz4_jblock_decoder::{
+    max_compressed_length, COMPRESSION_METHOD_LZ4, COMPRESSION_METHOD_RAW, DEFAULT_SEED,
+    LZ4_BLOCK_HEADER_LENGTH, LZ4_BLOCK_MAGIC,
+};
+use crate::lz4_jblock_decompress::lz4_jblock_decompress;
+use crate::lzf_decoder::{
+    CHUNK_TYPE_COMPRESSED, CHUNK_TYPE_UNCOMPRESSED, COMPRESSED_HEADER_LENGTH,
+    UNCOMPRESSED_HEADER_LENGTH,
+};
+use crate::lzf_decompress::lzf_decompress;
+use crate::xxhash32::XXHash32;
+
+// Block by block dump of LZ4Block and LZF streams for looking into corrupt files. Unlike the
+// readers, which stop at the first problem, inspection notes what is wrong with a block and
+// carries on for as long as block boundaries can still be trusted.
+
+const LZF_MAGIC: &[u8; 2] = b"ZV";
+// magic and chunk type, telling how long the rest of the header is
+const LZF_HEADER_START_LENGTH: usize = 2 + 1;
+
+/// Problem found with a single block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// Header doesn't start with the magic of the format. Nothing after it can be trusted, so
+    /// inspection stops there.
+    BadMagic,
+    /// Compression method or chunk type is not one of the known ones.
+    UnknownMethod,
+    /// Original length is over the block size of the level, or compressed length over what lz4
+    /// can produce for a block of that size.
+    LengthOverMaximum,
+    /// Only one of compressed and original length is 0.
+    EmptyLengthMismatch,
+    /// Raw block whose compressed and original lengths differ.
+    RawLengthMismatch,
+    NonZeroChecksumOnEmptyBlock,
+    ChecksumMismatch,
+    /// Payload doesn't decompress into exactly original length bytes.
+    CorruptPayload,
+    /// Input ends in the middle of the header or payload.
+    Truncated,
+    /// Data follows the end of stream marker, lz4-java stops reading before it by default.
+    TrailingData,
+}
+
+impl Anomaly {
+    /// Name used in JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Anomaly::BadMagic => "bad_magic",
+            Anomaly::UnknownMethod => "unknown_method",
+            Anomaly::LengthOverMaximum => "length_over_maximum",
+            Anomaly::EmptyLengthMismatch => "empty_length_mismatch",
+            Anomaly::RawLengthMismatch => "raw_length_mismatch",
+            Anomaly::NonZeroChecksumOnEmptyBlock => "non_zero_checksum_on_empty_block",
+            Anomaly::ChecksumMismatch => "checksum_mismatch",
+            Anomaly::CorruptPayload => "corrupt_payload",
+            Anomaly::Truncated => "truncated",
+            Anomaly::TrailingData => "trailing_data",
+        }
+    }
+}
+
+/// Header fields of a single block or chunk as found in the input, whether they make sense or
+/// not. Fields are `None` when the input ends before them, or when the format has no such field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InspectedBlock {
+    /// Offset of the header, counted from where inspection started.
+    pub offset: u64,
+    /// Magic as found, shorter than the format's when input ends within it.
+    pub magic: Vec<u8>,
+    /// Compression method bits of the LZ4Block token, or LZF chunk type.
+    pub method: Option<u8>,
+    /// Base 2 logarithm of the writer's block size, from the LZ4Block token.
+    pub level: Option<u8>,
+    pub compressed_length: Option<u32>,
+    pub original_length: Option<u32>,
+    /// Checksum stored in the LZ4Block header.
+    pub stored_checksum: Option<u32>,
+    /// Checksum of the decompressed data, computed the same way, when it could be decompressed.
+    pub computed_checksum: Option<u32>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Result of `inspect_lz4_jblock` or `inspect_lzf`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inspection {
+    pub blocks: Vec<InspectedBlock>,
+    /// Length of input inspected, it all belongs to `blocks` unless inspection stopped at a
+    /// block with `BadMagic` or `UnknownMethod`.
+    pub length: u64,
+}
+
+impl Inspection {
+    pub fn anomaly_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.anomalies.len()).sum()
+    }
+
+    /// Writes the inspection as a JSON object with `length`, `anomaly_count` and `blocks`,
+    /// fields of blocks named the same as in `InspectedBlock`, missing ones as `null`. Magic is
+    /// a string with one character per byte.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(
+            writer,
+            "{{\"length\":{},\"anomaly_count\":{},\"blocks\":[",
+            self.length,
+            self.anomaly_count()
+        )?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            write!(writer, "{{\"offset\":{},\"magic\":\"", block.offset)?;
+            for &b in &block.magic {
+                match b {
+                    b'"' | b'\\' => write!(writer, "\\{}", b as char)?,
+                    0x20..=0x7E => write!(writer, "{}", b as char)?,
+                    _ => write!(writer, "\\u{:04x}", b)?,
+                }
+            }
+            writer.write_all(b"\"")?;
+            write_json_field(&mut writer, "method", block.method)?;
+            write_json_field(&mut writer, "level", block.level)?;
+            write_json_field(&mut writer, "compressed_length", block.compressed_length)?;
+            write_json_field(&mut writer, "original_length", block.original_length)?;
+            write_json_field(&mut writer, "stored_checksum", block.stored_checksum)?;
+            write_json_field(&mut writer, "computed_checksum", block.computed_checksum)?;
+            writer.write_all(b",\"anomalies\":[")?;
+            for (j, anomaly) in block.anomalies.iter().enumerate() {
+                if j > 0 {
+                    writer.write_all(b",")?;
+                }
+                write!(writer, "\"{}\"", anomaly.name())?;
+            }
+            writer.write_all(b"]}")?;
+        }
+        writer.write_all(b"]}\n")
+    }
+}
+
+fn write_json_field<W: Write, T: fmt::Display>(
+    writer: &mut W,
+    name: &str,
+    value: Option<T>,
+) -> io::Result<()> {
+    match value {
+        Some(value) => write!(writer, ",\"{}\":{}", name, value),
+        None => write!(writer, ",\"{}\":null", name),
+    }
+}
+
+/// Lists every block of an LZ4Block stream, going on past end of stream markers up to EOF.
+/// Only errors of `reader` itself are returned, problems with the data are noted in the blocks.
+pub fn inspect_lz4_jblock<R: Read>(mut reader: R) -> io::Result<Inspection> {
+    let mut inspection = Inspection::default();
+    let mut header = [0_u8; LZ4_BLOCK_HEADER_LENGTH as usize];
+    loop {
+        let header_len = read_up_to(&mut reader, &mut header)?;
+        if header_len == 0 {
+            break;
+        }
+        if let Some(previous) = inspection.blocks.last_mut() {
+            if previous.compressed_length == Some(0) && previous.original_length == Some(0) {
+                previous.anomalies.push(Anomaly::TrailingData);
+            }
+        }
+
+        let mut block = InspectedBlock {
+            offset: inspection.length,
+            magic: header[..header_len.min(LZ4_BLOCK_MAGIC.len())].to_vec(),
+            ..InspectedBlock::default()
+        };
+        inspection.length += header_len as u64;
+        let bad_magic = block.magic[..] != LZ4_BLOCK_MAGIC[..block.magic.len()];
+        if bad_magic {
+            block.anomalies.push(Anomaly::BadMagic);
+        }
+        if header_len < header.len() {
+            block.anomalies.push(Anomaly::Truncated);
+            inspection.blocks.push(block);
+            break;
+        }
+
+        let token = header[8];
+        let method = token & 0xF0;
+        let level = 10 + (token & 0x0F);
+        let compressed_length = u32::from_le_bytes([header[9], header[10], header[11], header[12]]);
+        let original_length = u32::from_le_bytes([header[13], header[14], header[15], header[16]]);
+        let stored_checksum = u32::from_le_bytes([header[17], header[18], header[19], header[20]]);
+        block.method = Some(method);
+        block.level = Some(level);
+        block.compressed_length = Some(compressed_length);
+        block.original_length = Some(original_length);
+        block.stored_checksum = Some(stored_checksum);
+        if bad_magic {
+            inspection.blocks.push(block);
+            break;
+        }
+
+        if method != COMPRESSION_METHOD_RAW && method != COMPRESSION_METHOD_LZ4 {
+            block.anomalies.push(Anomaly::UnknownMethod);
+        }
+        let length_over_maximum = original_length as u64 > 1_u64 << level
+            || compressed_length as u64 > max_compressed_length(level);
+        if length_over_maximum {
+            block.anomalies.push(Anomaly::LengthOverMaximum);
+        }
+        if (compressed_length == 0) != (original_length == 0) {
+            block.anomalies.push(Anomaly::EmptyLengthMismatch);
+        }
+        if method == COMPRESSION_METHOD_RAW && compressed_length != original_length {
+            block.anomalies.push(Anomaly::RawLengthMismatch);
+        }
+        if compressed_length == 0 && original_length == 0 && stored_checksum != 0 {
+            block.anomalies.push(Anomaly::NonZeroChecksumOnEmptyBlock);
+        }
+
+        // lengths that make no sense are not worth allocating for, the payload is only skipped
+        let payload = if length_over_maximum {
+            None
+        } else {
+            Some(Vec::with_capacity(compressed_length as usize))
+        };
+        let payload = match read_payload(&mut reader, compressed_length as u64, payload)? {
+            (read, _) if read < compressed_length as u64 => {
+                inspection.length += read;
+                block.anomalies.push(Anomaly::Truncated);
+                inspection.blocks.push(block);
+                break;
+            }
+            (read, payload) => {
+                inspection.length += read;
+                payload
+            }
+        };
+
+        if let (Some(payload), true) = (payload, block.anomalies.is_empty()) {
+            if compressed_length != 0 {
+                let decompressed = if method == COMPRESSION_METHOD_RAW {
+                    Some(payload)
+                } else {
+                    let mut buf_decompressed = vec![0; original_length as usize];
+                    match lz4_jblock_decompress(&payload, &mut buf_decompressed) {
+                        Ok(len) if len == buf_decompressed.len() => Some(buf_decompressed),
+                        _ => None,
+                    }
+                };
+                match decompressed {
+                    Some(decompressed) => {
+                        let mut xxhash32 = XXHash32::new(DEFAULT_SEED);
+                        xxhash32.update(&decompressed);
+                        let computed_checksum = xxhash32.digest() & 0x0FFFFFFF;
+                        block.computed_checksum = Some(computed_checksum);
+                        if computed_checksum != stored_checksum {
+                            block.anomalies.push(Anomaly::ChecksumMismatch);
+                        }
+                    }
+                    None => block.anomalies.push(Anomaly::CorruptPayload),
+                }
+            }
+        }
+        inspection.blocks.push(block);
+    }
+    Ok(inspection)
+}
+
+/// Lists every chunk of a ZV chunk stream up to EOF. Only errors of `reader` itself are
+/// returned, problems with the data are noted in the chunks.
+pub fn inspect_lzf<R: Read>(mut reader: R) -> io::Result<Inspection> {
+    let mut inspection = Inspection::default();
+    let mut header = [0_u8; COMPRESSED_HEADER_LENGTH as usize];
+    loop {
+        let start_len = read_up_to(&mut reader, &mut header[..LZF_HEADER_START_LENGTH])?;
+        if start_len == 0 {
+            break;
+        }
+
+        let mut block = InspectedBlock {
+            offset: inspection.length,
+            magic: header[..start_len.min(LZF_MAGIC.len())].to_vec(),
+            ..InspectedBlock::default()
+        };
+        inspection.length += start_len as u64;
+        if block.magic[..] != LZF_MAGIC[..block.magic.len()] {
+            block.anomalies.push(Anomaly::BadMagic);
+            if start_len < LZF_HEADER_START_LENGTH {
+                block.anomalies.push(Anomaly::Truncated);
+            } else {
+                block.method = Some(header[2]);
+            }
+            inspection.blocks.push(block);
+            break;
+        }
+        if start_len < LZF_HEADER_START_LENGTH {
+            block.anomalies.push(Anomaly::Truncated);
+            inspection.blocks.push(block);
+            break;
+        }
+
+        let chunk_type = header[2];
+        block.method = Some(chunk_type);
+        let header_length = match chunk_type {
+            CHUNK_TYPE_UNCOMPRESSED => UNCOMPRESSED_HEADER_LENGTH as usize,
+            CHUNK_TYPE_COMPRESSED => COMPRESSED_HEADER_LENGTH as usize,
+            _ => {
+                // length of the rest of the header is unknown, so is where the next chunk starts
+                block.anomalies.push(Anomaly::UnknownMethod);
+                inspection.blocks.push(block);
+                break;
+            }
+        };
+        let rest_len = read_up_to(
+            &mut reader,
+            &mut header[LZF_HEADER_START_LENGTH..header_length],
+        )?;
+        inspection.length += rest_len as u64;
+        if LZF_HEADER_START_LENGTH + rest_len < header_length {
+            block.anomalies.push(Anomaly::Truncated);
+            inspection.blocks.push(block);
+            break;
+        }
+
+        let compressed_length = u16::from_be_bytes([header[3], header[4]]);
+        let original_length = if chunk_type == CHUNK_TYPE_COMPRESSED {
+            u16::from_be_bytes([header[5], header[6]])
+        } else {
+            compressed_length
+        };
+        block.compressed_length = Some(compressed_length as u32);
+        block.original_length = Some(original_length as u32);
+
+        let payload = Vec::with_capacity(compressed_length as usize);
+        let payload = match read_payload(&mut reader, compressed_length as u64, Some(payload))? {
+            (read, Some(payload)) if read == compressed_length as u64 => {
+                inspection.length += read;
+                payload
+            }
+            (read, _) => {
+                inspection.length += read;
+                block.anomalies.push(Anomaly::Truncated);
+                inspection.blocks.push(block);
+                break;
+            }
+        };
+
+        if chunk_type == CHUNK_TYPE_COMPRESSED {
+            let mut buf_decompressed = vec![0; original_length as usize];
+            match lzf_decompress(&payload, &mut buf_decompressed) {
+                Ok(len) if len == buf_decompressed.len() => (),
+                _ => block.anomalies.push(Anomaly::CorruptPayload),
+            }
+        }
+        inspection.blocks.push(block);
+    }
+    Ok(inspection)
+}
+
+// reads until `buf` is full or EOF, returns how much was read
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+// reads `len` bytes of payload into `buf`, or skips them if there is none, returns how much of
+// it there was before EOF
+fn read_payload<R: Read>(
+    reader: &mut R,
+    len: u64,
+    buf: Option<Vec<u8>>,
+) -> io::Result<(u64, Option<Vec<u8>>)> {
+    let mut payload = reader.by_ref().take(len);
+    match buf {
+        Some(mut buf) => {
+            let read = payload.read_to_end(&mut buf)?;
+            Ok((read as u64, Some(buf)))
+        }
+        None => Ok((io::copy(&mut payload, &mut io::sink())?, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lz4_jblock::Lz4JBlockWriter;
+    use crate::lzf::LzfWriter;
+    use crate::test_data::sample_data;
+
+    fn lz4_stream(data: &[u8]) -> Vec<u8> {
+        let mut writer = Lz4JBlockWriter::new(Vec::new(), 1024).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn lz4_block(token: u8, lengths: (u32, u32), checksum: u32, payload: &[u8]) -> Vec<u8> {
+        let mut block = LZ4_BLOCK_MAGIC.to_vec();
+        block.push(token);
+        block.extend_from_slice(&lengths.0.to_le_bytes());
+        block.extend_from_slice(&lengths.1.to_le_bytes());
+        block.extend_from_slice(&checksum.to_le_bytes());
+        block.extend_from_slice(payload);
+        block
+    }
+
+    fn anomalies(inspection: &Inspection) -> Vec<&[Anomaly]> {
+        inspection
+            .blocks
+            .iter()
+            .map(|block| &block.anomalies[..])
+            .collect()
+    }
+
+    #[test]
+    fn stream_from_writer_has_no_anomalies() {
+        let data = sample_data(3 * 1024 + 100);
+        let stream = lz4_stream(&data);
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(inspection.length, stream.len() as u64);
+        assert_eq!(inspection.anomaly_count(), 0);
+        assert_eq!(inspection.blocks.len(), 5);
+        for block in &inspection.blocks[..4] {
+            assert_eq!(block.magic, LZ4_BLOCK_MAGIC);
+            assert_eq!(block.level, Some(10));
+            assert_eq!(block.computed_checksum, block.stored_checksum);
+        }
+        let end_marker = &inspection.blocks[4];
+        assert_eq!(
+            end_marker.offset,
+            stream.len() as u64 - LZ4_BLOCK_HEADER_LENGTH
+        );
+        assert_eq!(
+            (end_marker.compressed_length, end_marker.original_length),
+            (Some(0), Some(0))
+        );
+        assert_eq!(end_marker.computed_checksum, None);
+    }
+
+    #[test]
+    fn bad_magic_stops_inspection() {
+        let mut stream = lz4_stream(b"abc");
+        let first_length = stream.len() as u64;
+        stream.extend_from_slice(&lz4_block(COMPRESSION_METHOD_RAW, (3, 3), 0, b"abc"));
+        stream[first_length as usize + 7] = b'x';
+        stream.extend_from_slice(&lz4_stream(b"abc"));
+
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        // anything after the end of stream marker is trailing, even if not a block
+        assert_eq!(
+            anomalies(&inspection),
+            [&[][..], &[Anomaly::TrailingData], &[Anomaly::BadMagic]]
+        );
+        // header is still read, payload and what follows are not
+        let block = &inspection.blocks[2];
+        assert_eq!(block.offset, first_length);
+        assert_eq!(block.magic, b"LZ4Blocx");
+        assert_eq!(block.original_length, Some(3));
+        assert_eq!(inspection.length, first_length + LZ4_BLOCK_HEADER_LENGTH);
+    }
+
+    #[test]
+    fn data_after_empty_block_is_trailing() {
+        let mut stream = lz4_stream(b"abc");
+        stream.extend_from_slice(&lz4_stream(b"def"));
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[][..], &[Anomaly::TrailingData], &[], &[]]
+        );
+    }
+
+    #[test]
+    fn empty_block_must_have_zero_checksum() {
+        let stream = lz4_block(COMPRESSION_METHOD_RAW, (0, 0), 5, b"");
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::NonZeroChecksumOnEmptyBlock][..]]
+        );
+    }
+
+    #[test]
+    fn lengths_over_maximum_are_skipped() {
+        // original length over the block size of level 10, payload is skipped whole
+        let mut stream = lz4_block(COMPRESSION_METHOD_RAW, (2000, 2000), 0, &[b'a'; 2000]);
+        stream.extend_from_slice(&lz4_stream(b"abc"));
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::LengthOverMaximum][..], &[], &[]]
+        );
+        assert_eq!(inspection.blocks[0].computed_checksum, None);
+        assert_eq!(inspection.blocks[1].offset, 2000 + LZ4_BLOCK_HEADER_LENGTH);
+        assert_eq!(inspection.length, stream.len() as u64);
+
+        // compressed length far too long to allocate a payload buffer for, little of it there
+        let stream = lz4_block(
+            COMPRESSION_METHOD_LZ4 | 0x0F,
+            (0xFFFF_FFF0, 1 << 25),
+            0,
+            &[0; 10],
+        );
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::LengthOverMaximum, Anomaly::Truncated][..]]
+        );
+        assert_eq!(inspection.length, stream.len() as u64);
+    }
+
+    #[test]
+    fn truncation_is_noted_in_header_and_payload() {
+        let stream = lz4_stream(&sample_data(2000));
+        for len in [3, 10, LZ4_BLOCK_HEADER_LENGTH as usize + 5] {
+            let inspection = inspect_lz4_jblock(&stream[..len]).unwrap();
+            assert_eq!(anomalies(&inspection), [&[Anomaly::Truncated][..]], "{len}");
+            assert_eq!(inspection.length, len as u64);
+        }
+        let inspection = inspect_lz4_jblock(&stream[..10]).unwrap();
+        assert_eq!(inspection.blocks[0].magic, LZ4_BLOCK_MAGIC);
+        assert_eq!(inspection.blocks[0].method, None);
+
+        // magic cut short is still checked
+        let inspection = inspect_lz4_jblock(&b"LZX"[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::BadMagic, Anomaly::Truncated][..]]
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch_has_computed_checksum() {
+        let mut stream = lz4_stream(b"abc");
+        stream[LZ4_BLOCK_HEADER_LENGTH as usize - 4] ^= 1;
+        let inspection = inspect_lz4_jblock(&stream[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::ChecksumMismatch][..], &[]]
+        );
+        let block = &inspection.blocks[0];
+        let computed_checksum = block.computed_checksum.unwrap();
+        assert_eq!(computed_checksum, block.stored_checksum.unwrap() ^ 1);
+    }
+
+    #[test]
+    fn lzf_chunk_anomalies() {
+        let mut writer = LzfWriter::new(Vec::new(), 1000).unwrap();
+        writer.write_all(&sample_data(1500)).unwrap();
+        let stream = writer.finish().unwrap();
+        let inspection = inspect_lzf(&stream[..]).unwrap();
+        assert_eq!(inspection.anomaly_count(), 0);
+        assert_eq!(inspection.blocks.len(), 2);
+        assert_eq!(inspection.length, stream.len() as u64);
+
+        // literal run decompressing into less than the original length, inspection goes on
+        let mut corrupt = b"ZV\x01\x00\x02\x00\x05\x00A".to_vec();
+        corrupt.extend_from_slice(&stream);
+        let inspection = inspect_lzf(&corrupt[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[Anomaly::CorruptPayload][..], &[], &[]]
+        );
+
+        // where the chunk ends is unknown, so inspection stops
+        let mut unknown = stream.clone();
+        unknown.extend_from_slice(b"ZV\x02\x00\x03abc");
+        unknown.extend_from_slice(&stream);
+        let inspection = inspect_lzf(&unknown[..]).unwrap();
+        assert_eq!(
+            anomalies(&inspection),
+            [&[][..], &[], &[Anomaly::UnknownMethod]]
+        );
+        assert_eq!(inspection.blocks[2].method, Some(2));
+        assert_eq!(inspection.length, stream.len() as u64 + 3);
+    }
+
+    #[test]
+    fn json_escapes_magic() {
+        let inspection = Inspection {
+            blocks: vec![InspectedBlock {
+                offset: 7,
+                magic: b"a\"\\\xe9".to_vec(),
+                method: Some(0x20),
+                compressed_length: Some(3),
+                anomalies: vec![Anomaly::BadMagic, Anomaly::Truncated],
+                ..InspectedBlock::default()
+            }],
+            length: 12,
+        };
+        let mut json = Vec::new();
+        inspection.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            concat!(
+                r#"{"length":12,"anomaly_count":2,"blocks":[{"offset":7,"magic":"a\"\\\u00e9","#,
+                r#""method":32,"level":null,"compressed_length":3,"original_length":null,"#,
+                r#""stored_checksum":null,"computed_checksum":null,"#,
+                r#""anomalies":["bad_magic","truncated"]}]}"#,
+                "\n"
+            )
+        );
+
+        let mut json = Vec::new();
+        Inspection::default().write_json(&mut json).unwrap();
+        assert_eq!(json, b"{\"length\":0,\"anomaly_count\":0,\"blocks\":[]}\n");
+    }
+}
@@ -1,5 +1,78 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod adler32;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod async_io;
+mod back_reference;
+#[cfg(feature = "std")]
+pub mod block_index;
+#[cfg(feature = "std")]
+pub mod cab;
+pub mod crc32;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod fastlz;
+pub mod fastlz_decompress;
+#[cfg(feature = "std")]
+pub mod h2_lzf;
+pub mod h2_lzf_decompress;
+#[cfg(feature = "alloc")]
+mod huffman;
+#[cfg(feature = "alloc")]
+pub mod inflate;
+#[cfg(feature = "std")]
+pub mod inspect;
+#[cfg(feature = "std")]
 pub mod lz4_jblock;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub mod lz4_jblock_async;
+#[cfg(feature = "alloc")]
+pub mod lz4_jblock_compress;
+#[cfg(feature = "alloc")]
+pub mod lz4_jblock_decoder;
 pub mod lz4_jblock_decompress;
+#[cfg(feature = "alloc")]
+pub mod lz4_with_length;
+#[cfg(feature = "std")]
 pub mod lzf;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub mod lzf_async;
+#[cfg(feature = "alloc")]
+pub mod lzf_compress;
+#[cfg(feature = "alloc")]
+pub mod lzf_decoder;
 pub mod lzf_decompress;
-mod xxhash32;
+#[cfg(feature = "alloc")]
+pub mod lzfse_decompress;
+pub mod lzjb_decompress;
+pub mod lznt1_decompress;
+pub mod lzss_decompress;
+pub mod lzvn_decompress;
+#[cfg(feature = "alloc")]
+pub mod lzx_decompress;
+#[cfg(feature = "std")]
+pub mod mam;
+#[cfg(feature = "std")]
+pub mod mozlz4;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "alloc")]
+pub mod pg_toast;
+pub mod pglz_decompress;
+#[cfg(feature = "alloc")]
+pub mod quicklz_decompress;
+#[cfg(feature = "std")]
+pub mod redis_rdb;
+#[cfg(feature = "std")]
+mod skip;
+#[cfg(all(test, feature = "std"))]
+mod test_data;
+#[cfg(feature = "alloc")]
+pub mod xpress_decompress;
+pub mod xxhash32;
+pub mod zfs_lz4;
+#[cfg(feature = "std")]
+pub mod zip;
@@ -1,31 +1,124 @@
-use core::cmp;
-use std::io::{self, BufRead, Error, ErrorKind, Read};
+use core::mem;
+use std::io::{self, BufRead, Error, ErrorKind, Read, Seek, SeekFrom, Write};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 
-use crate::lz4_jblock_decompress::lz4_jblock_decompress;
+use crate::block_index::BlockIndex;
+use crate::lz4_jblock_compress::lz4_jblock_compress;
+use crate::lz4_jblock_decoder::{
+    decode_block, verify_checksum, DEFAULT_SEED, LZ4_BLOCK_HEADER_LENGTH, LZ4_BLOCK_MAGIC,
+};
+pub use crate::lz4_jblock_decoder::{
+    BlockInfo, Lz4JBlockDecoder, COMPRESSION_METHOD_LZ4, COMPRESSION_METHOD_RAW,
+};
+use crate::parallel::{Job, ParallelBlocks, ParallelEncoder};
+use crate::skip::{skip_by_reading, skip_by_seeking, stream_len};
 use crate::xxhash32::XXHash32;
 
+/// Block sizes `Lz4JBlockWriter` accepts, same as lz4-java's.
+pub const MIN_BLOCK_SIZE: usize = 64;
+pub const MAX_BLOCK_SIZE: usize = 1 << 25;
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 16;
+
+/// Totals of a stream gathered from its block headers alone, see `scan`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanInfo {
+    /// Number of blocks, empty ones included.
+    pub block_count: u64,
+    /// Length of the stream up to where scanning stopped, headers included.
+    pub compressed_length: u64,
+    pub decompressed_length: u64,
+    /// Offset of the last empty block, which marks the end of stream.
+    pub end_marker_offset: Option<u64>,
+}
+
+/// Walks the block headers of a stream without decompressing anything, reading and discarding
+/// block payloads. Stops at EOF, or after the first empty block with `stop_after_empty_block`,
+/// same as `Lz4JBlockReader`. Offsets are counted from where `reader` is positioned.
+pub fn scan<R: Read>(reader: R, stop_after_empty_block: bool) -> io::Result<ScanInfo> {
+    scan_with(reader, stop_after_empty_block, skip_by_reading)
+}
+
+/// Same as `scan`, but seeks over block payloads instead of reading them.
+pub fn scan_seekable<R: Read + Seek>(
+    mut reader: R,
+    stop_after_empty_block: bool,
+) -> io::Result<ScanInfo> {
+    let stream_len = stream_len(&mut reader)?;
+    scan_with(reader, stop_after_empty_block, |inner, n| {
+        skip_by_seeking(inner, n, stream_len)
+    })
+}
+
+/// Builds the index `Lz4JBlockReader` needs for seeking by walking block headers, same as
+/// `scan_seekable`. Offsets in it are counted from where `reader` is positioned.
+pub fn build_block_index<R: Read + Seek>(
+    mut reader: R,
+    stop_after_empty_block: bool,
+) -> io::Result<BlockIndex> {
+    let stream_len = stream_len(&mut reader)?;
+    let mut block_index = BlockIndex::new();
+    walk_headers(
+        reader,
+        stop_after_empty_block,
+        |inner, n| skip_by_seeking(inner, n, stream_len),
+        |info| {
+            block_index.push(
+                info.offset,
+                LZ4_BLOCK_HEADER_LENGTH + info.compressed_length as u64,
+                info.original_length as u64,
+            )
+        },
+    )?;
+    Ok(block_index)
+}
+
+fn scan_with<R: Read>(
+    reader: R,
+    stop_after_empty_block: bool,
+    skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+) -> io::Result<ScanInfo> {
+    let mut scan_info = ScanInfo::default();
+    scan_info.compressed_length = walk_headers(reader, stop_after_empty_block, skip, |info| {
+        scan_info.block_count += 1;
+        scan_info.decompressed_length += info.original_length as u64;
+        if info.compressed_length == 0 {
+            scan_info.end_marker_offset = Some(info.offset);
+        }
+        Ok(())
+    })?;
+    Ok(scan_info)
+}
+
+// calls `on_block` for every block header, skipping payloads; returns the length walked
+fn walk_headers<R: Read>(
+    reader: R,
+    stop_after_empty_block: bool,
+    mut skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+    mut on_block: impl FnMut(&BlockInfo) -> io::Result<()>,
+) -> io::Result<u64> {
+    let mut reader = Lz4JBlockReader::new(reader, stop_after_empty_block, false);
+    while let Some(info) = reader.read_block_header()? {
+        skip(&mut reader.inner, info.compressed_length as u64)?;
+        reader.decoder.skip_payload();
+        on_block(&info)?;
+    }
+    Ok(reader.decoder.offset)
+}
+
 /// This reader is for files that can be read by:
 /// https://github.com/lz4/lz4-java/blob/master/src/java/net/jpountz/lz4/LZ4BlockInputStream.java
 /// It's unclear if anything else can actually read/write in this format, but unfortunately
 /// we have to be able to read files written in this format.
-
-// minimal one is just 64 bytes, but we will allocate 64kb - the default size Java writer uses,
-// since that seems to be the most common source of the LZ4Block lz4 files
-const LZ4_BLOCK_STARTING_BUF_SIZE: usize = 64 * 1024;
-
-const DEFAULT_SEED: u32 = 0x9747b28c;
-
+///
+/// Only what the stream needs is read from `reader`, so with `stop_after_empty_block` it is left
+/// right after the end of stream marker.
 pub struct Lz4JBlockReader<R> {
     inner: R,
-    buf_compressed: Vec<u8>,
-    buf_decompressed: Vec<u8>,
-    pos: usize,
-    cap: usize,
-    stop_after_empty_block: bool,
-    saw_empty_block: bool,
-    check_checksum: bool,
+    decoder: Lz4JBlockDecoder,
+    block_index: Option<BlockIndex>,
+    // position of the start of stream in `inner`, only known once seeking is involved
+    inner_start: Option<u64>,
 }
 
 impl<R: Read> Lz4JBlockReader<R> {
@@ -36,147 +129,278 @@ impl<R: Read> Lz4JBlockReader<R> {
     ) -> Lz4JBlockReader<R> {
         Lz4JBlockReader {
             inner: reader,
-            buf_compressed: vec![0; LZ4_BLOCK_STARTING_BUF_SIZE],
-            buf_decompressed: vec![0; LZ4_BLOCK_STARTING_BUF_SIZE],
-            pos: 0,
-            cap: 0,
-            stop_after_empty_block,
-            saw_empty_block: false,
-            check_checksum,
+            decoder: Lz4JBlockDecoder::new(stop_after_empty_block, check_checksum),
+            block_index: None,
+            inner_start: None,
         }
     }
 
-    fn fill_buf_decompressed(&mut self) -> io::Result<()> {
-        assert_eq!(self.pos, self.cap);
-
-        if self.stop_after_empty_block && self.saw_empty_block {
-            self.pos = 0;
-            self.cap = 0;
-            return Ok(());
+    /// Decodes the next block, appending its decompressed contents to `out`, and returns its
+    /// header. Empty blocks are returned as well, `None` means there are no more blocks.
+    ///
+    /// Can't be called while a block partially read through `Read` is pending.
+    pub fn next_block(&mut self, out: &mut Vec<u8>) -> io::Result<Option<BlockInfo>> {
+        if !self.decoder.output().is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "lz4 block is partially read",
+            ));
         }
 
-        // looking for 'L', if there is EOF, then we are done
-        let magic_z = match self.inner.read_u8() {
-            Ok(b) => b,
-            Err(err) => {
-                return if err.kind() == ErrorKind::UnexpectedEof {
-                    self.pos = 0;
-                    self.cap = 0;
-                    Ok(())
-                } else {
-                    Err(err)
-                }
+        let info = self.decode_next_block()?;
+        let output = self.decoder.output();
+        out.extend_from_slice(output);
+        let len = output.len();
+        self.decoder.consume(len);
+        Ok(info)
+    }
+
+    // feeds the decoder up to the end of next block, `None` at the end of stream
+    fn decode_next_block(&mut self) -> io::Result<Option<BlockInfo>> {
+        loop {
+            let len = match self.read_input()? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if let Some(info) = self.decoder.fill_input(len)? {
+                return Ok(Some(info));
             }
-        };
-        if magic_z != b'L' {
-            return Err(Error::new(ErrorKind::InvalidData, "wrong lz4 magic"));
         }
+    }
 
-        let mut magic_leftovers = [0 as u8; 7];
-        self.inner.read_exact(&mut magic_leftovers)?;
-        if &magic_leftovers != b"Z4Block" {
-            return Err(Error::new(ErrorKind::InvalidData, "wrong lz4 magic"));
+    // reads the header of next block into the decoder, leaving its payload to the caller, who has
+    // to call `skip_payload` on the decoder after it; `None` at the end of stream
+    fn read_block_header(&mut self) -> io::Result<Option<BlockInfo>> {
+        loop {
+            if let Some(info) = self.decoder.block {
+                return Ok(Some(info));
+            }
+            let len = match self.read_input()? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if let Some(info) = self.decoder.fill_input(len)? {
+                // empty block, header is all there is
+                return Ok(Some(info));
+            }
         }
+    }
 
-        let token = self.inner.read_u8()?;
-        let compression_method = token & 0xF0;
-        let compression_level = 10 + (token & 0x0F);
-        let max_decompressed_buf_len = (1 as usize) << compression_level;
-        let chunk_length = self.inner.read_u32::<LittleEndian>()? as usize;
-        let original_length = self.inner.read_u32::<LittleEndian>()? as usize;
-        let original_checksum = self.inner.read_u32::<LittleEndian>()?;
-
-        if chunk_length == 0 {
-            if original_length != 0 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "lz4 compressed chunk is empty, but decompressed one is not",
-                ));
+    // reads into what the decoder needs next, never more, `None` at the end of stream
+    fn read_input(&mut self) -> io::Result<Option<usize>> {
+        loop {
+            let buf = self.decoder.input_buf();
+            if buf.is_empty() {
+                return Ok(None);
             }
-            if original_checksum != 0 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "lz4 block is empty, but checksum is not 0",
-                ));
+            match self.inner.read(buf) {
+                Ok(0) => {
+                    self.decoder.finish()?;
+                    return Ok(None);
+                }
+                Ok(len) => return Ok(Some(len)),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             }
+        }
+    }
+}
 
-            self.saw_empty_block = true;
+/// Reads the same streams as `Lz4JBlockReader`, but decodes blocks and verifies their checksums
+/// on a pool of `threads` worker threads, with up to `blocks_in_flight` blocks read ahead of the
+/// data being consumed. Data still comes out in stream order.
+pub struct ParallelLz4JBlockReader<R> {
+    source: Lz4JBlockReader<R>,
+    blocks: ParallelBlocks,
+}
 
-            return if self.stop_after_empty_block {
-                self.pos = 0;
-                self.cap = 0;
-                Ok(())
-            } else {
-                self.fill_buf_decompressed()
-            };
+impl<R: Read> ParallelLz4JBlockReader<R> {
+    pub fn new(
+        reader: R,
+        stop_after_empty_block: bool,
+        check_checksum: bool,
+        threads: usize,
+        blocks_in_flight: usize,
+    ) -> io::Result<ParallelLz4JBlockReader<R>> {
+        Ok(ParallelLz4JBlockReader {
+            source: Lz4JBlockReader::new(reader, stop_after_empty_block, check_checksum),
+            blocks: ParallelBlocks::new(threads, blocks_in_flight)?,
+        })
+    }
+}
+
+// reads the next block holding data, and prepares its decoding for a worker thread
+fn next_block_job<R: Read>(source: &mut Lz4JBlockReader<R>) -> io::Result<Option<Job>> {
+    loop {
+        let info = match source.read_block_header()? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        if info.compressed_length == 0 {
+            continue;
         }
 
-        if original_length > max_decompressed_buf_len {
+        let mut payload = Vec::new();
+        source
+            .inner
+            .by_ref()
+            .take(info.compressed_length as u64)
+            .read_to_end(&mut payload)?;
+        if payload.len() != info.compressed_length as usize {
             return Err(Error::new(
-                ErrorKind::InvalidData,
-                "lz4 decompressed buf length mismatch",
+                ErrorKind::UnexpectedEof,
+                "lz4 block is truncated",
             ));
         }
+        source.decoder.skip_payload();
 
-        if self.buf_decompressed.capacity() < original_length {
-            self.buf_decompressed.resize(max_decompressed_buf_len, 0);
+        let check_checksum = source.decoder.check_checksum;
+        return Ok(Some(Box::new(move || {
+            decode_block_payload(&info, payload, check_checksum)
+        })));
+    }
+}
+
+fn decode_block_payload(
+    info: &BlockInfo,
+    payload: Vec<u8>,
+    check_checksum: bool,
+) -> io::Result<Vec<u8>> {
+    if info.compression_method == COMPRESSION_METHOD_RAW {
+        if check_checksum {
+            verify_checksum(info, &payload)?;
         }
+        return Ok(payload);
+    }
 
-        match compression_method {
-            0x10 => {
-                // uncompressed chunk
-                if original_length != chunk_length {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "lz4 uncompressed chunk length not equal original length",
-                    ));
-                }
+    let mut buf_decompressed = vec![0; info.original_length as usize];
+    decode_block(info, &payload, &mut buf_decompressed, check_checksum)?;
+    Ok(buf_decompressed)
+}
 
-                let buf_decompressed_capped = &mut self.buf_decompressed[..chunk_length];
-                self.inner.read_exact(buf_decompressed_capped)?;
+impl<R: Read> Read for ParallelLz4JBlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = {
+            let mut rem = self.fill_buf()?;
+            rem.read(buf)?
+        };
+        self.consume(bytes_read);
+        Ok(bytes_read)
+    }
+}
 
-                self.pos = 0;
-                self.cap = chunk_length;
-            }
-            0x20 => {
-                // compressed chunk
-                if self.buf_compressed.capacity() < chunk_length {
-                    self.buf_compressed
-                        .resize(max_decompressed_buf_len.max(chunk_length), 0);
-                }
+impl<R: Read> BufRead for ParallelLz4JBlockReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let source = &mut self.source;
+        self.blocks.fill_buf(|| next_block_job(source))
+    }
 
-                let buf_compressed_capped = &mut self.buf_compressed[..chunk_length];
-                self.inner.read_exact(buf_compressed_capped)?;
+    fn consume(&mut self, amt: usize) {
+        self.blocks.consume(amt);
+    }
+}
 
-                let buf_decompressed_capped = &mut self.buf_decompressed[..original_length];
-                let buf_compressed_capped = buf_compressed_capped.as_ref();
+impl<R: Read + Seek> Lz4JBlockReader<R> {
+    /// Uses `block_index` for seeking, instead of building one by walking all block headers on
+    /// first seek. It has to be built for the stream as it starts where the reader was created,
+    /// for example by `build_block_index` or loaded from a sidecar file.
+    pub fn set_block_index(&mut self, block_index: BlockIndex) {
+        self.block_index = Some(block_index);
+    }
 
-                let decompressed_length =
-                    lz4_jblock_decompress(buf_compressed_capped, buf_decompressed_capped)?;
-                assert_eq!(original_length, decompressed_length);
+    /// Index used for seeking, built by walking block headers if it was not set.
+    pub fn block_index(&mut self) -> io::Result<&BlockIndex> {
+        let inner_start = self.inner_start()?;
+        if self.block_index.is_none() {
+            let pos = self.inner.stream_position()?;
+            self.inner.seek(SeekFrom::Start(inner_start))?;
+            let block_index =
+                build_block_index(&mut self.inner, self.decoder.stop_after_empty_block);
+            self.inner.seek(SeekFrom::Start(pos))?;
+            self.block_index = Some(block_index?);
+        }
+        Ok(self.block_index.as_ref().unwrap())
+    }
 
-                self.pos = 0;
-                self.cap = decompressed_length;
-            }
-            _ => {
-                return Err(Error::new(
+    fn inner_start(&mut self) -> io::Result<u64> {
+        if let Some(inner_start) = self.inner_start {
+            return Ok(inner_start);
+        }
+        // nothing is read from inner beyond what the decoder took
+        let inner_start = self
+            .inner
+            .stream_position()?
+            .checked_sub(self.decoder.offset)
+            .ok_or_else(|| {
+                Error::new(
                     ErrorKind::InvalidData,
-                    "unknown lz4 compression method",
-                ));
+                    "lz4 reader position is out of sync with its source",
+                )
+            })?;
+        self.inner_start = Some(inner_start);
+        Ok(inner_start)
+    }
+}
+
+/// Seeking decompresses only the block holding the new position, or nothing if it is within the
+/// current one. Positions past the end are allowed, reading there returns no data.
+impl<R: Read + Seek> Seek for Lz4JBlockReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let decoder = &self.decoder;
+        let current = decoder.decompressed_offset + decoder.pos as u64;
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => current.checked_add_signed(n),
+            SeekFrom::End(n) => self
+                .block_index()?
+                .decompressed_length()
+                .checked_add_signed(n),
+        }
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // within the block already decompressed
+        let decoder = &mut self.decoder;
+        if target >= decoder.decompressed_offset
+            && target < decoder.decompressed_offset + decoder.cap as u64
+        {
+            decoder.pos = (target - decoder.decompressed_offset) as usize;
+            return Ok(target);
+        }
+
+        let inner_start = self.inner_start()?;
+        let block_index = self.block_index()?;
+        let (compressed_offset, decompressed_offset) = match block_index.find(target) {
+            Some(i) => {
+                let entry = block_index.entries()[i];
+                (entry.compressed_offset, entry.decompressed_offset)
             }
+            None => (block_index.compressed_length(), target),
         };
+        let past_end = target >= block_index.decompressed_length();
 
-        if self.check_checksum {
-            let mut xxhash32 = XXHash32::new(DEFAULT_SEED);
-            xxhash32.update(&self.buf_decompressed[..self.cap]);
-            let computed_checksum = xxhash32.digest() & 0x0FFFFFFFu32;
+        self.inner
+            .seek(SeekFrom::Start(inner_start + compressed_offset))?;
+        self.decoder
+            .reset(compressed_offset, decompressed_offset, past_end);
 
-            if original_checksum != computed_checksum {
-                return Err(Error::new(ErrorKind::InvalidData, "lz4 checksum mismatch"));
+        if !past_end {
+            self.fill_buf()?;
+            let decoder = &mut self.decoder;
+            let pos = target - decoder.decompressed_offset;
+            if pos >= decoder.cap as u64 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lz4 block index does not match the stream",
+                ));
             }
+            decoder.pos = pos as usize;
         }
-
-        Ok(())
+        Ok(target)
     }
 }
 
@@ -193,17 +417,591 @@ impl<R: Read> Read for Lz4JBlockReader<R> {
 
 impl<R: Read> BufRead for Lz4JBlockReader<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        // If we've reached the end of our internal buffer then we need to fetch
-        // some more data from the underlying reader.
-        // Branch using `>=` instead of the more correct `==`
-        // to tell the compiler that the pos..cap slice is always valid.
-        if self.pos >= self.cap {
-            self.fill_buf_decompressed()?;
+        // empty blocks are skipped, so that they don't look like the end of stream
+        while self.decoder.output().is_empty() {
+            if self.decode_next_block()?.is_none() {
+                break;
+            }
         }
-        Ok(&self.buf_decompressed[self.pos..self.cap])
+        Ok(self.decoder.output())
     }
 
     fn consume(&mut self, amt: usize) {
-        self.pos = cmp::min(self.pos + amt, self.cap);
+        self.decoder.consume(amt);
+    }
+}
+
+/// Writes streams readable by `Lz4JBlockReader` and lz4-java's `LZ4BlockInputStream`, the same
+/// way `LZ4BlockOutputStream` does: data is cut into blocks of `block_size` bytes, each stored
+/// lz4 compressed, or raw when compression doesn't make it smaller.
+///
+/// `finish` has to be called to write the last block and the empty block marking the end of
+/// stream, `flush` only flushes whole blocks.
+pub struct Lz4JBlockWriter<W: Write> {
+    inner: W,
+    block_size: usize,
+    compression_level: u8,
+    buf: Vec<u8>,
+    buf_encoded: Vec<u8>,
+}
+
+impl<W: Write> Lz4JBlockWriter<W> {
+    /// `block_size` has to be between `MIN_BLOCK_SIZE` and `MAX_BLOCK_SIZE`, lz4-java uses
+    /// `DEFAULT_BLOCK_SIZE`.
+    pub fn new(writer: W, block_size: usize) -> io::Result<Lz4JBlockWriter<W>> {
+        Ok(Lz4JBlockWriter {
+            inner: writer,
+            block_size,
+            compression_level: compression_level(block_size)?,
+            buf: Vec::with_capacity(block_size),
+            buf_encoded: Vec::new(),
+        })
+    }
+
+    /// Writes what is left and the end of stream marker, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        write_end_marker(&mut self.inner, self.compression_level)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.buf_encoded.clear();
+        encode_block(&self.buf, self.compression_level, &mut self.buf_encoded);
+        self.inner.write_all(&self.buf_encoded)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Lz4JBlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.block_size {
+            self.write_block()?;
+        }
+        let len = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() == self.block_size {
+            self.write_block()?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Same as `Lz4JBlockWriter`, but compresses blocks on a pool of `threads` worker threads. Up to
+/// `blocks_in_flight` blocks are queued, after that writing waits for the oldest block to be
+/// compressed and written out. Output is identical to that of `Lz4JBlockWriter`.
+pub struct ParallelLz4JBlockWriter<W: Write> {
+    inner: W,
+    block_size: usize,
+    compression_level: u8,
+    buf: Vec<u8>,
+    encoder: ParallelEncoder,
+}
+
+impl<W: Write> ParallelLz4JBlockWriter<W> {
+    pub fn new(
+        writer: W,
+        block_size: usize,
+        threads: usize,
+        blocks_in_flight: usize,
+    ) -> io::Result<ParallelLz4JBlockWriter<W>> {
+        Ok(ParallelLz4JBlockWriter {
+            inner: writer,
+            block_size,
+            compression_level: compression_level(block_size)?,
+            buf: Vec::with_capacity(block_size),
+            encoder: ParallelEncoder::new(threads, blocks_in_flight)?,
+        })
+    }
+
+    /// Writes what is left and the end of stream marker, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.submit_block()?;
+        self.encoder.write_pending(&mut self.inner)?;
+        write_end_marker(&mut self.inner, self.compression_level)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn submit_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let block = mem::replace(&mut self.buf, Vec::with_capacity(self.block_size));
+        let compression_level = self.compression_level;
+        self.encoder.submit(
+            &mut self.inner,
+            Box::new(move || {
+                let mut buf_encoded = Vec::new();
+                encode_block(&block, compression_level, &mut buf_encoded);
+                Ok(buf_encoded)
+            }),
+        )
+    }
+}
+
+impl<W: Write> Write for ParallelLz4JBlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.block_size {
+            self.submit_block()?;
+        }
+        let len = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Waits for all queued blocks to be compressed and written out.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() == self.block_size {
+            self.submit_block()?;
+        }
+        self.encoder.write_pending(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+/// Decompressing counterpart of `Lz4JBlockReader` for data that is pushed rather than pulled:
+/// compressed data written to it in fragments of any size is decompressed into `writer`. Partial
+/// headers and blocks are kept until the rest of them arrives, so nothing but whole blocks is
+/// decoded.
+///
+/// With `stop_after_empty_block`, whatever is written after the end of stream marker is ignored,
+/// the same data `Lz4JBlockReader` leaves unread. `finish` has to be called to learn whether the
+/// stream was complete.
+pub struct Lz4JBlockDecoderWriter<W: Write> {
+    inner: W,
+    decoder: Lz4JBlockDecoder,
+}
+
+impl<W: Write> Lz4JBlockDecoderWriter<W> {
+    pub fn new(
+        writer: W,
+        stop_after_empty_block: bool,
+        check_checksum: bool,
+    ) -> Lz4JBlockDecoderWriter<W> {
+        Lz4JBlockDecoderWriter {
+            inner: writer,
+            decoder: Lz4JBlockDecoder::new(stop_after_empty_block, check_checksum),
+        }
+    }
+
+    /// Whether the end of stream marker was written with `stop_after_empty_block`.
+    pub fn is_finished(&self) -> bool {
+        self.decoder.is_finished()
+    }
+
+    /// Writes out what is left and returns the underlying writer, failing if the stream ended in
+    /// the middle of a block.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_output()?;
+        self.decoder.finish()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.decoder.output();
+        if !output.is_empty() {
+            self.inner.write_all(output)?;
+            let len = output.len();
+            self.decoder.consume(len);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Lz4JBlockDecoderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the decoder takes nothing while the previous block is waiting to be written out
+        self.write_output()?;
+        if self.decoder.is_finished() {
+            return Ok(buf.len());
+        }
+        Ok(self.decoder.feed(buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_output()?;
+        self.inner.flush()
+    }
+}
+
+// base 2 logarithm of the block size rounded up, minus 10, as lz4-java stores it in the token
+fn compression_level(block_size: usize) -> io::Result<u8> {
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "lz4 block size must be between 64B and 32MB",
+        ));
+    }
+    let bits = usize::BITS - (block_size - 1).leading_zeros();
+    Ok(bits.saturating_sub(10) as u8)
+}
+
+// appends header and payload of a single block holding `buf`
+fn encode_block(buf: &[u8], compression_level: u8, buf_encoded: &mut Vec<u8>) {
+    let mut xxhash32 = XXHash32::new(DEFAULT_SEED);
+    xxhash32.update(buf);
+    let checksum = xxhash32.digest() & 0x0FFFFFFFu32;
+
+    let header_start = buf_encoded.len();
+    buf_encoded.resize(header_start + LZ4_BLOCK_HEADER_LENGTH as usize, 0);
+    let payload_start = buf_encoded.len();
+    lz4_jblock_compress(buf, buf_encoded);
+
+    let mut compression_method = COMPRESSION_METHOD_LZ4;
+    if buf_encoded.len() - payload_start >= buf.len() {
+        compression_method = COMPRESSION_METHOD_RAW;
+        buf_encoded.truncate(payload_start);
+        buf_encoded.extend_from_slice(buf);
+    }
+    let compressed_length = (buf_encoded.len() - payload_start) as u32;
+
+    let mut header = &mut buf_encoded[header_start..payload_start];
+    // writing into a slice of the right length can't fail
+    let _ = write_block_header(
+        &mut header,
+        compression_method | compression_level,
+        compressed_length,
+        buf.len() as u32,
+        checksum,
+    );
+}
+
+fn write_end_marker<W: Write>(writer: &mut W, compression_level: u8) -> io::Result<()> {
+    write_block_header(writer, COMPRESSION_METHOD_RAW | compression_level, 0, 0, 0)
+}
+
+fn write_block_header<W: Write>(
+    writer: &mut W,
+    token: u8,
+    compressed_length: u32,
+    original_length: u32,
+    checksum: u32,
+) -> io::Result<()> {
+    writer.write_all(LZ4_BLOCK_MAGIC)?;
+    writer.write_u8(token)?;
+    writer.write_u32::<LittleEndian>(compressed_length)?;
+    writer.write_u32::<LittleEndian>(original_length)?;
+    writer.write_u32::<LittleEndian>(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_data::{check_seeks, noise, sample_data};
+
+    const BLOCK_SIZE: usize = 1024;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut writer = Lz4JBlockWriter::new(Vec::new(), BLOCK_SIZE).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn scan_rejects_compressed_length_over_worst_case() {
+        let mut stream = LZ4_BLOCK_MAGIC.to_vec();
+        stream.push(COMPRESSION_METHOD_LZ4 | 0x0F);
+        stream.extend_from_slice(&0xFFFFFFF0_u32.to_le_bytes());
+        stream.extend_from_slice(&[0; 8]);
+
+        let err = scan_seekable(Cursor::new(&stream), false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = scan(Cursor::new(&stream), false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    // stream following some unrelated data, with more of it after the end of stream marker
+    fn stream_in_file(data: &[u8], block_size: usize) -> Cursor<Vec<u8>> {
+        let mut writer = Lz4JBlockWriter::new(b"prefix".to_vec(), block_size).unwrap();
+        writer.write_all(data).unwrap();
+        let mut file = writer.finish().unwrap();
+        file.extend_from_slice(b"trailing");
+        let mut cursor = Cursor::new(file);
+        cursor.set_position(6);
+        cursor
+    }
+
+    #[test]
+    fn seek_moves_to_any_position() {
+        let data = sample_data(10 * BLOCK_SIZE + 300);
+        let reader = Lz4JBlockReader::new(stream_in_file(&data, BLOCK_SIZE), true, true);
+        check_seeks(reader, &data, BLOCK_SIZE);
+    }
+
+    #[test]
+    fn loaded_block_index_is_used_for_seeking() {
+        let data = sample_data(10 * BLOCK_SIZE + 300);
+        let block_index = build_block_index(stream_in_file(&data, BLOCK_SIZE), true).unwrap();
+        assert_eq!(block_index.entries().len(), 11);
+        assert_eq!(block_index.decompressed_length(), data.len() as u64);
+
+        let mut sidecar = Vec::new();
+        block_index.write_to(&mut sidecar).unwrap();
+        let loaded = BlockIndex::read_from(&sidecar[..]).unwrap();
+        assert_eq!(loaded, block_index);
+
+        let mut reader = Lz4JBlockReader::new(stream_in_file(&data, BLOCK_SIZE), true, true);
+        reader.set_block_index(loaded);
+        assert_eq!(reader.block_index().unwrap(), &block_index);
+        check_seeks(reader, &data, BLOCK_SIZE);
+    }
+
+    #[test]
+    fn mismatched_block_index_is_an_error() {
+        // blocks of the indexed stream are twice as long as those of the one read
+        let data = sample_data(10 * BLOCK_SIZE);
+        let block_index = build_block_index(stream_in_file(&data, 2 * BLOCK_SIZE), true).unwrap();
+
+        let mut reader = Lz4JBlockReader::new(stream_in_file(&data, BLOCK_SIZE), true, true);
+        reader.set_block_index(block_index);
+        let err = reader
+            .seek(SeekFrom::Start(BLOCK_SIZE as u64 + 10))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "lz4 block index does not match the stream");
+    }
+
+    #[test]
+    fn parallel_reader_matches_reader() {
+        let data = sample_data(40 * BLOCK_SIZE + 123);
+        // second stream follows the end of stream marker of the first one
+        let mut stream = compress(&data);
+        stream.extend_from_slice(&compress(b"abc"));
+
+        for stop_after_empty_block in [true, false] {
+            let mut expected = Vec::new();
+            Lz4JBlockReader::new(&stream[..], stop_after_empty_block, true)
+                .read_to_end(&mut expected)
+                .unwrap();
+            for (threads, blocks_in_flight) in [(1, 1), (2, 3), (4, 16)] {
+                let mut reader = ParallelLz4JBlockReader::new(
+                    &stream[..],
+                    stop_after_empty_block,
+                    true,
+                    threads,
+                    blocks_in_flight,
+                )
+                .unwrap();
+                let mut buf_decompressed = Vec::new();
+                reader.read_to_end(&mut buf_decompressed).unwrap();
+                assert_eq!(buf_decompressed, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_reader_fails_like_reader() {
+        let stream = compress(&sample_data(10 * BLOCK_SIZE));
+        let mut bad_checksum = stream.clone();
+        // checksum of the first block
+        bad_checksum[LZ4_BLOCK_HEADER_LENGTH as usize - 1] ^= 1;
+        let truncated = &stream[..stream.len() / 2];
+
+        for (stream, kind) in [
+            (&bad_checksum[..], ErrorKind::InvalidData),
+            (truncated, ErrorKind::UnexpectedEof),
+        ] {
+            let mut buf_decompressed = Vec::new();
+            let err = Lz4JBlockReader::new(stream, true, true)
+                .read_to_end(&mut buf_decompressed)
+                .unwrap_err();
+            assert_eq!(err.kind(), kind);
+
+            let mut reader = ParallelLz4JBlockReader::new(stream, true, true, 4, 8).unwrap();
+            let err = reader.read_to_end(&mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), kind);
+            let err = reader.read(&mut [0; 16]).unwrap_err();
+            assert_eq!(err.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn parallel_writer_matches_writer() {
+        let data = sample_data(40 * BLOCK_SIZE + 123);
+        let expected = compress(&data);
+        for (threads, blocks_in_flight) in [(1, 1), (2, 3), (4, 16)] {
+            let mut writer =
+                ParallelLz4JBlockWriter::new(Vec::new(), BLOCK_SIZE, threads, blocks_in_flight)
+                    .unwrap();
+            // writes of odd sizes, crossing block boundaries
+            for part in data.chunks(777) {
+                writer.write_all(part).unwrap();
+            }
+            assert_eq!(writer.finish().unwrap(), expected);
+        }
+
+        // empty input is only the end of stream marker
+        let writer = ParallelLz4JBlockWriter::new(Vec::new(), BLOCK_SIZE, 2, 2).unwrap();
+        assert_eq!(writer.finish().unwrap(), compress(b""));
+    }
+
+    #[test]
+    fn decoder_writer_takes_writes_of_any_size() {
+        let data = sample_data(10 * BLOCK_SIZE + 123);
+        let mut stream = compress(&data);
+        let stream_length = stream.len();
+        // ignored, as it follows the end of stream marker
+        stream.extend_from_slice(b"trailing");
+
+        let mut writer = Lz4JBlockDecoderWriter::new(Vec::new(), true, true);
+        for (i, byte) in stream.iter().enumerate() {
+            assert_eq!(writer.is_finished(), i >= stream_length);
+            writer.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data);
+
+        let mut writer = Lz4JBlockDecoderWriter::new(Vec::new(), true, true);
+        writer.write_all(&stream).unwrap();
+        assert!(writer.is_finished());
+        assert_eq!(writer.finish().unwrap(), data);
+
+        let mut writer = Lz4JBlockDecoderWriter::new(Vec::new(), true, true);
+        writer.write_all(&stream[..stream_length / 2]).unwrap();
+        assert!(!writer.is_finished());
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn scan_totals_match_writer_output() {
+        let data = sample_data(5 * BLOCK_SIZE + 100);
+        // second stream follows the end of stream marker of the first one
+        let mut stream = compress(&data);
+        let first_length = stream.len() as u64;
+        stream.extend_from_slice(&compress(b"abc"));
+        let total_length = stream.len() as u64;
+
+        let expected = ScanInfo {
+            block_count: 7,
+            compressed_length: first_length,
+            decompressed_length: data.len() as u64,
+            end_marker_offset: Some(first_length - LZ4_BLOCK_HEADER_LENGTH),
+        };
+        assert_eq!(scan(&stream[..], true).unwrap(), expected);
+        assert_eq!(scan_seekable(Cursor::new(&stream), true).unwrap(), expected);
+
+        let expected = ScanInfo {
+            block_count: 9,
+            compressed_length: total_length,
+            decompressed_length: data.len() as u64 + 3,
+            end_marker_offset: Some(total_length - LZ4_BLOCK_HEADER_LENGTH),
+        };
+        assert_eq!(scan(&stream[..], false).unwrap(), expected);
+        assert_eq!(
+            scan_seekable(Cursor::new(&stream), false).unwrap(),
+            expected
+        );
+    }
+
+    // feeds `input` to `decoder` a byte at a time until it runs out or the stream is finished
+    fn feed_bytewise(decoder: &mut Lz4JBlockDecoder, input: &mut &[u8]) -> Vec<u8> {
+        let mut buf_decompressed = Vec::new();
+        while !input.is_empty() && !decoder.is_finished() {
+            let len = decoder.feed(&input[..1]).unwrap();
+            *input = &input[len..];
+            let output = decoder.output();
+            buf_decompressed.extend_from_slice(output);
+            let len = output.len();
+            decoder.consume(len);
+        }
+        buf_decompressed
+    }
+
+    #[test]
+    fn decoder_takes_input_one_byte_at_a_time() {
+        let data = sample_data(40 * BLOCK_SIZE + 123);
+        let mut stream = compress(&data);
+        // ignored, as it follows the end of stream marker
+        stream.extend_from_slice(b"trailing");
+
+        let mut decoder = Lz4JBlockDecoder::new(true, true);
+        let mut input = &stream[..];
+        assert_eq!(feed_bytewise(&mut decoder, &mut input), data);
+        decoder.finish().unwrap();
+        assert_eq!(input, b"trailing");
+
+        let mut decoder = Lz4JBlockDecoder::new(true, true);
+        feed_bytewise(&mut decoder, &mut &stream[..stream.len() / 2]);
+        let err = decoder.finish().unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn next_block_returns_block_headers() {
+        let mut data = vec![b'a'; BLOCK_SIZE];
+        data.extend_from_slice(&noise(BLOCK_SIZE / 2));
+        // second stream follows the end of stream marker of the first one
+        let mut stream = compress(&data);
+        let first_length = stream.len() as u64;
+        stream.extend_from_slice(&compress(b"abc"));
+
+        let mut reader = Lz4JBlockReader::new(&stream[..], false, true);
+        let mut buf_decompressed = Vec::new();
+        let mut blocks = Vec::new();
+        while let Some(info) = reader.next_block(&mut buf_decompressed).unwrap() {
+            blocks.push(info);
+        }
+        assert_eq!(buf_decompressed, [&data[..], b"abc"].concat());
+
+        let summary: Vec<_> = blocks
+            .iter()
+            .map(|info| {
+                (
+                    info.compression_method,
+                    info.compression_level,
+                    info.original_length,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (COMPRESSION_METHOD_LZ4, 10, BLOCK_SIZE as u32),
+                (COMPRESSION_METHOD_RAW, 10, BLOCK_SIZE as u32 / 2),
+                (COMPRESSION_METHOD_RAW, 10, 0),
+                (COMPRESSION_METHOD_RAW, 10, 3),
+                (COMPRESSION_METHOD_RAW, 10, 0),
+            ]
+        );
+        assert!(blocks[0].compressed_length < 100);
+        assert_eq!(blocks[1].compressed_length, blocks[1].original_length);
+        assert_eq!((blocks[2].compressed_length, blocks[2].checksum), (0, 0));
+
+        // each block starts where the previous one ended
+        assert_eq!(blocks[0].offset, 0);
+        for pair in blocks.windows(2) {
+            let end = pair[0].offset + LZ4_BLOCK_HEADER_LENGTH + pair[0].compressed_length as u64;
+            assert_eq!(pair[1].offset, end);
+        }
+        assert_eq!(blocks[3].offset, first_length);
+    }
+
+    #[test]
+    fn next_block_refuses_partially_read_block() {
+        let stream = compress(&sample_data(3 * BLOCK_SIZE));
+        let mut reader = Lz4JBlockReader::new(&stream[..], true, true);
+        reader.read_exact(&mut [0; 10]).unwrap();
+
+        let mut buf_decompressed = Vec::new();
+        let err = reader.next_block(&mut buf_decompressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // rest of the block, after which blocks can be taken whole again
+        reader.read_exact(&mut vec![0; BLOCK_SIZE - 10]).unwrap();
+        let info = reader.next_block(&mut buf_decompressed).unwrap().unwrap();
+        assert_eq!(info.original_length, BLOCK_SIZE as u32);
     }
 }
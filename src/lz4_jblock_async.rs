@@ -0,0 +1,99 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crate::async_io::{impl_async_read, PollRead};
+use crate::lz4_jblock::Lz4JBlockDecoder;
+
+/// Async counterpart of `Lz4JBlockReader`, reading the same streams with the same options.
+/// Implements `AsyncRead` and `AsyncBufRead` of tokio with the `tokio` feature, and of futures
+/// with the `futures-io` feature.
+pub struct AsyncLz4JBlockReader<R> {
+    inner: R,
+    decoder: Lz4JBlockDecoder,
+}
+
+impl<R: Unpin> AsyncLz4JBlockReader<R> {
+    pub fn new(
+        reader: R,
+        stop_after_empty_block: bool,
+        check_checksum: bool,
+    ) -> AsyncLz4JBlockReader<R> {
+        AsyncLz4JBlockReader {
+            inner: reader,
+            decoder: Lz4JBlockDecoder::new(stop_after_empty_block, check_checksum),
+        }
+    }
+
+    fn poll_fill_buf_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll_read: PollRead<R>,
+    ) -> Poll<io::Result<&[u8]>> {
+        // empty blocks are skipped, so that they don't look like the end of stream
+        while self.decoder.output().is_empty() {
+            let buf = self.decoder.input_buf();
+            if buf.is_empty() {
+                break;
+            }
+            let len = ready!(poll_read(Pin::new(&mut self.inner), cx, buf))?;
+            if len == 0 {
+                self.decoder.finish()?;
+                break;
+            }
+            self.decoder.fill_input(len)?;
+        }
+        Poll::Ready(Ok(self.decoder.output()))
+    }
+
+    fn consume_decompressed(&mut self, amt: usize) {
+        self.decoder.consume(amt);
+    }
+}
+
+impl_async_read!(AsyncLz4JBlockReader);
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+
+    use super::*;
+    use crate::async_io::{poll_to_end, Trickle};
+    use crate::lz4_jblock::{Lz4JBlockReader, Lz4JBlockWriter};
+    use crate::test_data::sample_data;
+
+    fn check_reader(poll_read: PollRead<AsyncLz4JBlockReader<Trickle>>) {
+        let data = sample_data(5 * 1024 + 100);
+        let mut writer = Lz4JBlockWriter::new(Vec::new(), 1024).unwrap();
+        writer.write_all(&data).unwrap();
+        let mut stream = writer.finish().unwrap();
+        let stream_length = stream.len();
+        stream.extend_from_slice(b"trailing");
+
+        let mut expected = Vec::new();
+        Lz4JBlockReader::new(&stream[..], true, true)
+            .read_to_end(&mut expected)
+            .unwrap();
+        let mut reader = AsyncLz4JBlockReader::new(Trickle::new(stream.clone()), true, true);
+        assert_eq!(poll_to_end(&mut reader, poll_read).unwrap(), expected);
+        // nothing is read past the end of stream marker
+        assert_eq!(reader.inner.rest(), b"trailing");
+
+        let truncated = stream[..stream_length / 2].to_vec();
+        let mut reader = AsyncLz4JBlockReader::new(Trickle::new(truncated), true, true);
+        let err = poll_to_end(&mut reader, poll_read).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_reader_matches_reader() {
+        check_reader(crate::async_io::tokio_poll_read);
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn futures_reader_matches_reader() {
+        check_reader(crate::async_io::futures_poll_read);
+    }
+}
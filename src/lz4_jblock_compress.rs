@@ -0,0 +1,113 @@
+use alloc::{vec, vec::Vec};
+
+const MIN_MATCH: usize = 4;
+// last match has to start at least 12 bytes before the end of the block
+const MF_LIMIT: usize = 12;
+// last 5 bytes of the block are always literals
+const LAST_LITERALS: usize = 5;
+const MAX_DISTANCE: usize = 0xFFFF;
+
+const HASH_LOG: u32 = 12;
+
+/// Compresses `buf_decompressed` into a single raw lz4 block appended to `buf_compressed`.
+///
+/// Output follows the restrictions of the official block format (minimum match, last literals),
+/// so it can be read back by `lz4_jblock_decompress` as well as by any other lz4 implementation.
+pub fn lz4_jblock_compress(buf_decompressed: &[u8], buf_compressed: &mut Vec<u8>) {
+    let len = buf_decompressed.len();
+    let mut anchor = 0;
+
+    if len > MF_LIMIT {
+        let mut hash_table = vec![usize::MAX; 1 << HASH_LOG];
+        let match_start_limit = len - MF_LIMIT;
+        let match_end_limit = len - LAST_LITERALS;
+        let mut in_pos = 0;
+
+        while in_pos < match_start_limit {
+            let sequence = read32le(buf_decompressed, in_pos);
+            let hash_index = hash(sequence);
+            let candidate = hash_table[hash_index];
+            hash_table[hash_index] = in_pos;
+
+            if candidate == usize::MAX
+                || in_pos - candidate > MAX_DISTANCE
+                || read32le(buf_decompressed, candidate) != sequence
+            {
+                // skip faster through data that does not compress
+                in_pos += 1 + ((in_pos - anchor) >> 6);
+                continue;
+            }
+
+            let mut match_pos = in_pos;
+            let mut ref_pos = candidate;
+            while match_pos > anchor
+                && ref_pos > 0
+                && buf_decompressed[match_pos - 1] == buf_decompressed[ref_pos - 1]
+            {
+                match_pos -= 1;
+                ref_pos -= 1;
+            }
+
+            let mut run_len = MIN_MATCH + (in_pos - match_pos);
+            while match_pos + run_len < match_end_limit
+                && buf_decompressed[ref_pos + run_len] == buf_decompressed[match_pos + run_len]
+            {
+                run_len += 1;
+            }
+
+            write_sequence(
+                buf_compressed,
+                &buf_decompressed[anchor..match_pos],
+                Some((match_pos - ref_pos, run_len)),
+            );
+
+            in_pos = match_pos + run_len;
+            anchor = in_pos;
+
+            if in_pos >= 2 && in_pos - 2 < match_start_limit {
+                hash_table[hash(read32le(buf_decompressed, in_pos - 2))] = in_pos - 2;
+            }
+        }
+    }
+
+    write_sequence(buf_compressed, &buf_decompressed[anchor..], None);
+}
+
+fn write_sequence(buf_compressed: &mut Vec<u8>, literals: &[u8], back_ref: Option<(usize, usize)>) {
+    let literal_len = literals.len();
+    let run_len_extra = back_ref.map_or(0, |(_, run_len)| run_len - MIN_MATCH);
+
+    let token = ((literal_len.min(15) as u8) << 4) | (run_len_extra.min(15) as u8);
+    buf_compressed.push(token);
+    if literal_len >= 15 {
+        write_multibyte_number(buf_compressed, literal_len - 15);
+    }
+    buf_compressed.extend_from_slice(literals);
+
+    if let Some((ref_offset, _)) = back_ref {
+        buf_compressed.push(ref_offset as u8);
+        buf_compressed.push((ref_offset >> 8) as u8);
+        if run_len_extra >= 15 {
+            write_multibyte_number(buf_compressed, run_len_extra - 15);
+        }
+    }
+}
+
+fn write_multibyte_number(buf_compressed: &mut Vec<u8>, mut n: usize) {
+    while n >= 0xFF {
+        buf_compressed.push(0xFF);
+        n -= 0xFF;
+    }
+    buf_compressed.push(n as u8);
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn read32le(input: &[u8], offset: usize) -> u32 {
+    (input[offset] as u32)
+        | ((input[offset + 1] as u32) << 8)
+        | ((input[offset + 2] as u32) << 16)
+        | ((input[offset + 3] as u32) << 24)
+}
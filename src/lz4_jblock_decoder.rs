@@ -0,0 +1,393 @@
+use core::{cmp, mem};
+
+use alloc::{vec, vec::Vec};
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::error::{self, Error, ErrorKind};
+use crate::lz4_jblock_decompress::lz4_jblock_decompress;
+use crate::xxhash32::XXHash32;
+
+// minimal one is just 64 bytes, but we will allocate 64kb - the default size Java writer uses,
+// since that seems to be the most common source of the LZ4Block lz4 files
+const LZ4_BLOCK_STARTING_BUF_SIZE: usize = 64 * 1024;
+
+pub(crate) const DEFAULT_SEED: u32 = 0x9747b28c;
+
+pub(crate) const LZ4_BLOCK_MAGIC: &[u8; 8] = b"LZ4Block";
+// magic, token, compressed length, original length and checksum
+pub(crate) const LZ4_BLOCK_HEADER_LENGTH: u64 = 8 + 1 + 4 + 4 + 4;
+
+pub const COMPRESSION_METHOD_RAW: u8 = 0x10;
+pub const COMPRESSION_METHOD_LZ4: u8 = 0x20;
+
+/// Header of a single LZ4Block block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Offset of the block in the source, counted from where the reader started.
+    pub offset: u64,
+    /// `COMPRESSION_METHOD_RAW` or `COMPRESSION_METHOD_LZ4`.
+    pub compression_method: u8,
+    /// Base 2 logarithm of the writer's block size, 10 to 25.
+    pub compression_level: u8,
+    pub compressed_length: u32,
+    pub original_length: u32,
+    /// Lower 28 bits of xxHash32 of the decompressed data, 0 for empty blocks.
+    pub checksum: u32,
+}
+
+/// Sans-IO decoder of LZ4Block streams: compressed data goes in through `feed` in fragments of
+/// any size, decompressed data comes out of `output` a block at a time. It does no IO of its
+/// own, so it can be driven from an event loop or by a non-blocking source without losing
+/// partially received blocks. `Lz4JBlockReader` is built on it. Needs only `alloc`.
+pub struct Lz4JBlockDecoder {
+    header: [u8; LZ4_BLOCK_HEADER_LENGTH as usize],
+    header_filled: usize,
+    // block whose header is in, but not its payload yet
+    pub(crate) block: Option<BlockInfo>,
+    buf_compressed: Vec<u8>,
+    compressed_filled: usize,
+    buf_decompressed: Vec<u8>,
+    pub(crate) pos: usize,
+    pub(crate) cap: usize,
+    pub(crate) stop_after_empty_block: bool,
+    saw_empty_block: bool,
+    pub(crate) check_checksum: bool,
+    // length of input taken so far
+    pub(crate) offset: u64,
+    // decompressed offset of the start of `buf_decompressed`
+    pub(crate) decompressed_offset: u64,
+}
+
+impl Lz4JBlockDecoder {
+    pub fn new(stop_after_empty_block: bool, check_checksum: bool) -> Lz4JBlockDecoder {
+        Lz4JBlockDecoder {
+            header: [0; LZ4_BLOCK_HEADER_LENGTH as usize],
+            header_filled: 0,
+            block: None,
+            buf_compressed: vec![0; LZ4_BLOCK_STARTING_BUF_SIZE],
+            compressed_filled: 0,
+            buf_decompressed: vec![0; LZ4_BLOCK_STARTING_BUF_SIZE],
+            pos: 0,
+            cap: 0,
+            stop_after_empty_block,
+            saw_empty_block: false,
+            check_checksum,
+            offset: 0,
+            decompressed_offset: 0,
+        }
+    }
+
+    /// Takes as much of `input` as the blocks it completes need, and returns how much that was.
+    /// Nothing is taken while decoded data is waiting in `output`, or once the stream is
+    /// finished.
+    pub fn feed(&mut self, mut input: &[u8]) -> error::Result<usize> {
+        let mut consumed = 0;
+        loop {
+            let buf = self.input_buf();
+            let len = buf.len().min(input.len());
+            if len == 0 {
+                return Ok(consumed);
+            }
+            buf[..len].copy_from_slice(&input[..len]);
+            input = &input[len..];
+            consumed += len;
+            self.fill_input(len)?;
+        }
+    }
+
+    /// Decompressed data not consumed yet, all of it from a single block.
+    pub fn output(&self) -> &[u8] {
+        &self.buf_decompressed[self.pos..self.cap]
+    }
+
+    pub fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+
+    /// Whether the end of stream marker was seen with `stop_after_empty_block`, no input is
+    /// taken after that.
+    pub fn is_finished(&self) -> bool {
+        self.stop_after_empty_block && self.saw_empty_block
+    }
+
+    /// Checks that input ended between two blocks, to be called once there is no more input.
+    pub fn finish(&self) -> error::Result<()> {
+        if self.header_filled != 0 || self.block.is_some() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "lz4 block is truncated",
+            ));
+        }
+        Ok(())
+    }
+
+    // space for the input needed next, empty while output is pending or the stream is finished
+    pub(crate) fn input_buf(&mut self) -> &mut [u8] {
+        if self.pos < self.cap || self.is_finished() {
+            return &mut [];
+        }
+        match self.block {
+            Some(info) => {
+                // grown only now, so that callers skipping the payload never allocate for it
+                let compressed_length = info.compressed_length as usize;
+                if self.buf_compressed.len() < compressed_length {
+                    self.buf_compressed.resize(
+                        (1_usize << info.compression_level).max(compressed_length),
+                        0,
+                    );
+                }
+                &mut self.buf_compressed[self.compressed_filled..compressed_length]
+            }
+            None => &mut self.header[self.header_filled..],
+        }
+    }
+
+    // takes `len` bytes written to `input_buf`, returns the block they completed, if any
+    pub(crate) fn fill_input(&mut self, len: usize) -> error::Result<Option<BlockInfo>> {
+        self.offset += len as u64;
+
+        let info = match self.block {
+            Some(info) => info,
+            None => {
+                self.header_filled += len;
+                // magic is checked right away, so that data following a stream is reported as
+                // such even when it is shorter than a header
+                let magic_len = self.header_filled.min(LZ4_BLOCK_MAGIC.len());
+                if self.header[..magic_len] != LZ4_BLOCK_MAGIC[..magic_len] {
+                    return Err(Error::new(ErrorKind::InvalidData, "wrong lz4 magic"));
+                }
+                if self.header_filled < self.header.len() {
+                    return Ok(None);
+                }
+
+                self.header_filled = 0;
+                let info = parse_block_header(&self.header, self.offset - LZ4_BLOCK_HEADER_LENGTH)?;
+                if info.compressed_length == 0 {
+                    self.saw_empty_block = true;
+                    return Ok(Some(info));
+                }
+
+                self.compressed_filled = 0;
+                self.block = Some(info);
+                return Ok(None);
+            }
+        };
+
+        self.compressed_filled += len;
+        if self.compressed_filled < info.compressed_length as usize {
+            return Ok(None);
+        }
+        self.block = None;
+        self.decode(&info)?;
+        Ok(Some(info))
+    }
+
+    // called by readers reading the payload of the pending block on their own
+    #[cfg(feature = "std")]
+    pub(crate) fn skip_payload(&mut self) {
+        if let Some(info) = self.block.take() {
+            self.offset += info.compressed_length as u64 - self.compressed_filled as u64;
+        }
+    }
+
+    fn decode(&mut self, info: &BlockInfo) -> error::Result<()> {
+        let compressed_length = info.compressed_length as usize;
+        let original_length = info.original_length as usize;
+        self.decompressed_offset += self.cap as u64;
+        self.pos = 0;
+        self.cap = 0;
+
+        if info.compression_method == COMPRESSION_METHOD_RAW {
+            // payload is the data itself, no need to copy it around
+            if self.check_checksum {
+                verify_checksum(info, &self.buf_compressed[..compressed_length])?;
+            }
+            mem::swap(&mut self.buf_compressed, &mut self.buf_decompressed);
+        } else {
+            if self.buf_decompressed.len() < original_length {
+                self.buf_decompressed.resize(1 << info.compression_level, 0);
+            }
+            decode_block(
+                info,
+                &self.buf_compressed[..compressed_length],
+                &mut self.buf_decompressed[..original_length],
+                self.check_checksum,
+            )?;
+        }
+
+        self.cap = original_length;
+        Ok(())
+    }
+
+    // continues at `offset` of the stream, which is `decompressed_offset` of decompressed data
+    #[cfg(feature = "std")]
+    pub(crate) fn reset(&mut self, offset: u64, decompressed_offset: u64, saw_empty_block: bool) {
+        self.header_filled = 0;
+        self.block = None;
+        self.pos = 0;
+        self.cap = 0;
+        self.saw_empty_block = saw_empty_block;
+        self.offset = offset;
+        self.decompressed_offset = decompressed_offset;
+    }
+}
+
+/// Parses and validates a block header, `offset` being where it starts.
+pub(crate) fn parse_block_header(
+    header: &[u8; LZ4_BLOCK_HEADER_LENGTH as usize],
+    offset: u64,
+) -> error::Result<BlockInfo> {
+    if &header[..8] != LZ4_BLOCK_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "wrong lz4 magic"));
+    }
+
+    let token = header[8];
+    let compression_method = token & 0xF0;
+    let compression_level = 10 + (token & 0x0F);
+    let max_decompressed_buf_len = 1_usize << compression_level;
+    let compressed_length = LittleEndian::read_u32(&header[9..13]);
+    let original_length = LittleEndian::read_u32(&header[13..17]);
+    let checksum = LittleEndian::read_u32(&header[17..21]);
+
+    let info = BlockInfo {
+        offset,
+        compression_method,
+        compression_level,
+        compressed_length,
+        original_length,
+        checksum,
+    };
+
+    if compressed_length == 0 {
+        if original_length != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 compressed chunk is empty, but decompressed one is not",
+            ));
+        }
+        if checksum != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 block is empty, but checksum is not 0",
+            ));
+        }
+        return Ok(info);
+    }
+
+    if original_length as usize > max_decompressed_buf_len {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lz4 decompressed buf length mismatch",
+        ));
+    }
+    if compressed_length as u64 > max_compressed_length(compression_level) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lz4 compressed block is longer than lz4 can produce",
+        ));
+    }
+
+    match compression_method {
+        COMPRESSION_METHOD_RAW => {
+            if original_length != compressed_length {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lz4 uncompressed chunk length not equal original length",
+                ));
+            }
+        }
+        COMPRESSION_METHOD_LZ4 => (),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unknown lz4 compression method",
+            ));
+        }
+    }
+
+    Ok(info)
+}
+
+/// Worst case of lz4 compressing a block of `1 << compression_level` bytes, lz4-java's
+/// `maxCompressedLength`.
+pub(crate) fn max_compressed_length(compression_level: u8) -> u64 {
+    let max_original_length = 1_u64 << compression_level;
+    max_original_length + max_original_length / 255 + 16
+}
+
+/// Decodes the payload of a block into `buf_decompressed`, which has to be exactly
+/// `original_length` long.
+pub(crate) fn decode_block(
+    info: &BlockInfo,
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+    check_checksum: bool,
+) -> error::Result<()> {
+    if info.compression_method == COMPRESSION_METHOD_RAW {
+        buf_decompressed.copy_from_slice(buf_compressed);
+    } else {
+        let decompressed_length = lz4_jblock_decompress(buf_compressed, buf_decompressed)?;
+        if decompressed_length != buf_decompressed.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 decompressed block length mismatch",
+            ));
+        }
+    }
+
+    if check_checksum {
+        verify_checksum(info, buf_decompressed)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn verify_checksum(info: &BlockInfo, buf_decompressed: &[u8]) -> error::Result<()> {
+    let mut xxhash32 = XXHash32::new(DEFAULT_SEED);
+    xxhash32.update(buf_decompressed);
+    let computed_checksum = xxhash32.digest() & 0x0FFFFFFFu32;
+
+    if info.checksum != computed_checksum {
+        return Err(Error::new(ErrorKind::InvalidData, "lz4 checksum mismatch"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_header(token: u8, compressed_length: u32, original_length: u32) -> [u8; 21] {
+        let mut header = [0; LZ4_BLOCK_HEADER_LENGTH as usize];
+        header[..8].copy_from_slice(LZ4_BLOCK_MAGIC);
+        header[8] = token;
+        LittleEndian::write_u32(&mut header[9..13], compressed_length);
+        LittleEndian::write_u32(&mut header[13..17], original_length);
+        header
+    }
+
+    #[test]
+    fn compressed_length_over_worst_case_is_rejected() {
+        let header = block_header(COMPRESSION_METHOD_LZ4 | 0x0F, 0xFFFFFFF0, 1 << 25);
+        let err = parse_block_header(&header, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let header = block_header(COMPRESSION_METHOD_LZ4, 1024 + 1024 / 255 + 17, 1024);
+        assert!(parse_block_header(&header, 0).is_err());
+        let header = block_header(COMPRESSION_METHOD_LZ4, 1024 + 1024 / 255 + 16, 1024);
+        assert!(parse_block_header(&header, 0).is_ok());
+    }
+
+    #[test]
+    fn payload_buffer_is_not_grown_by_header_alone() {
+        let length = max_compressed_length(25) as u32;
+        let header = block_header(COMPRESSION_METHOD_LZ4 | 0x0F, length, 1 << 25);
+
+        let mut decoder = Lz4JBlockDecoder::new(false, true);
+        let len = decoder.input_buf().len();
+        decoder.input_buf().copy_from_slice(&header[..len]);
+        assert!(decoder.fill_input(len).unwrap().is_none());
+        assert_eq!(decoder.buf_compressed.len(), LZ4_BLOCK_STARTING_BUF_SIZE);
+
+        assert_eq!(decoder.input_buf().len(), length as usize);
+    }
+}
@@ -1,22 +1,38 @@
-use std::io::{self, Error, ErrorKind};
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// every byte of an lz4 block can expand into at most 255 bytes of output
+#[cfg(feature = "alloc")]
+pub(crate) const LZ4_MAX_EXPANSION: usize = 255;
 
 /// goal is to match LZ4*FastDecompressor.java, which doesn't quite match the official specs
+///
+/// `buf_decompressed` has to be exactly the size of the decompressed data, since the format
+/// itself does not record where the block ends. Malformed input results in an `InvalidData`
+/// error rather than a panic.
 pub fn lz4_jblock_decompress(
     buf_compressed: &[u8],
     buf_decompressed: &mut [u8],
-) -> io::Result<usize> {
+) -> error::Result<usize> {
     let mut in_pos = 0;
     let mut out_pos = 0;
 
     loop {
-        let token = buf_compressed[in_pos];
+        let token = *buf_compressed.get(in_pos).ok_or_else(truncated)?;
         in_pos += 1;
 
         let (literal_len, extra_in_pos) =
-            read_multibyte_number(token >> 4, &buf_compressed[in_pos..]);
+            read_multibyte_number(token >> 4, &buf_compressed[in_pos..])?;
         in_pos += extra_in_pos;
 
         let buf_decompressed_remaining = buf_decompressed.len() - out_pos;
+        if buf_decompressed_remaining < literal_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 literal run does not fit into output buffer",
+            ));
+        }
         if buf_decompressed_remaining - literal_len < 8 {
             if buf_decompressed_remaining != literal_len {
                 return Err(Error::new(
@@ -24,6 +40,12 @@ pub fn lz4_jblock_decompress(
                     "lz4 terminal literal run does not fill output buffer exactly",
                 ));
             }
+            if buf_compressed.len() - in_pos != literal_len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lz4 terminal literal run does not match remaining input",
+                ));
+            }
 
             //TODO do fast copy of first (literal_len & 0xFFFFFFF8) bytes,
             // up until last (literal_len & 0x7) bytes
@@ -31,6 +53,10 @@ pub fn lz4_jblock_decompress(
             return Ok(buf_decompressed.len());
         }
 
+        if buf_compressed.len() - in_pos < literal_len + 2 {
+            return Err(truncated());
+        }
+
         //TODO do fast copy of ((literal_len & 0xFFFFFFF8) + 8) bytes, since we know it's safe
         buf_decompressed[out_pos..out_pos + literal_len]
             .copy_from_slice(&buf_compressed[in_pos..in_pos + literal_len]);
@@ -46,39 +72,37 @@ pub fn lz4_jblock_decompress(
                 "lz4 match offset should not be zero",
             ));
         }
-        let ref_pos = out_pos - ref_offset;
-
+        if ref_offset > out_pos {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 match offset points before start of output",
+            ));
+        }
         let (base_run_len, extra_in_pos) =
-            read_multibyte_number(token & 0x0F, &buf_compressed[in_pos..]);
+            read_multibyte_number(token & 0x0F, &buf_compressed[in_pos..])?;
         in_pos += extra_in_pos;
         let run_len = base_run_len + 4;
 
-        //TODO do some fast copying in the following blocks as well
-        if ref_pos + run_len <= out_pos {
-            // non-overlapping
-            let (src, dst) = buf_decompressed.split_at_mut(out_pos);
-            dst[..run_len].copy_from_slice(&src[ref_pos..ref_pos + run_len]);
-            out_pos += run_len;
-        } else {
-            // overlapping
-            let mut ref_pos = ref_pos;
-            for _ in 0..run_len {
-                buf_decompressed[out_pos] = buf_decompressed[ref_pos];
-                out_pos += 1;
-                ref_pos += 1;
-            }
+        if buf_decompressed.len() - out_pos < run_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lz4 match does not fit into output buffer",
+            ));
         }
+
+        copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+        out_pos += run_len;
     }
 }
 
-fn read_multibyte_number(base: u8, buf: &[u8]) -> (usize, usize) {
+fn read_multibyte_number(base: u8, buf: &[u8]) -> error::Result<(usize, usize)> {
     assert!(base <= 0x0F);
     let mut in_pos = 0;
     let n = match base {
         15 => {
             let mut acc: usize = 15;
             loop {
-                let v = buf[in_pos] as usize;
+                let v = *buf.get(in_pos).ok_or_else(truncated)? as usize;
                 in_pos += 1;
                 acc += v;
                 if v != 0xFF {
@@ -89,5 +113,57 @@ fn read_multibyte_number(base: u8, buf: &[u8]) -> (usize, usize) {
         }
         n => n as usize,
     };
-    (n, in_pos)
+    Ok((n, in_pos))
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lz4 compressed block is truncated")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // literal "a", match of 8 at offset 1, terminal literals "bbbbbbbb"
+    const BLOCK: &[u8] = b"\x14a\x01\x00\x80bbbbbbbb";
+    const DATA: &[u8] = b"aaaaaaaaabbbbbbbb";
+
+    fn decompress(buf_compressed: &[u8], len: usize) -> error::Result<usize> {
+        let mut buf_decompressed = [0_u8; 64];
+        lz4_jblock_decompress(buf_compressed, &mut buf_decompressed[..len])
+    }
+
+    #[test]
+    fn block_is_decompressed() {
+        let mut buf_decompressed = [0_u8; DATA.len()];
+        let len = lz4_jblock_decompress(BLOCK, &mut buf_decompressed).unwrap();
+        assert_eq!(len, DATA.len());
+        assert_eq!(&buf_decompressed, DATA);
+    }
+
+    #[test]
+    fn malformed_blocks_are_errors() {
+        let invalid = [
+            // truncated anywhere, including within the multibyte literal length
+            &BLOCK[..0],
+            &BLOCK[..3],
+            &BLOCK[..BLOCK.len() - 1],
+            b"\xF0\xFF",
+            // zero match offset, and one pointing before start of output
+            b"\x14a\x00\x00\x80bbbbbbbb",
+            b"\x14a\x02\x00\x80bbbbbbbb",
+            // input left after the terminal literals
+            b"\x14a\x01\x00\x80bbbbbbbbb",
+        ];
+        for buf_compressed in invalid {
+            let err = decompress(buf_compressed, DATA.len()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{buf_compressed:?}");
+        }
+
+        // output buffer shorter or longer than the data
+        for len in [DATA.len() - 1, DATA.len() + 1, 4] {
+            let err = decompress(BLOCK, len).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
 }
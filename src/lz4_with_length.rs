@@ -0,0 +1,77 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::lz4_jblock_compress::lz4_jblock_compress;
+use crate::lz4_jblock_decompress::{lz4_jblock_decompress, LZ4_MAX_EXPANSION};
+
+// Raw lz4 block prefixed with its little-endian u32 decompressed length, as written by
+// lz4-java's `LZ4CompressorWithLength` and python-lz4's `lz4.block.compress(store_size=True)`.
+
+/// Limit used by `lz4_with_length_decompress_default`, large enough for any sane single block.
+pub const DEFAULT_MAX_DECOMPRESSED_LENGTH: usize = 256 * 1024 * 1024;
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Decompresses a size-prefixed block, refusing to allocate more than `max_decompressed_length`
+/// bytes no matter what the (possibly corrupt) prefix says.
+pub fn lz4_with_length_decompress(
+    buf_compressed: &[u8],
+    max_decompressed_length: usize,
+) -> error::Result<Vec<u8>> {
+    if buf_compressed.len() < LENGTH_PREFIX_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lz4 length prefix is truncated",
+        ));
+    }
+
+    let (length_prefix, buf_compressed) = buf_compressed.split_at(LENGTH_PREFIX_SIZE);
+    let original_length = u32::from_le_bytes([
+        length_prefix[0],
+        length_prefix[1],
+        length_prefix[2],
+        length_prefix[3],
+    ]) as usize;
+
+    if original_length > max_decompressed_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lz4 decompressed length is over the allowed maximum",
+        ));
+    }
+    if original_length / LZ4_MAX_EXPANSION > buf_compressed.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lz4 decompressed length is too large for compressed data",
+        ));
+    }
+
+    let mut buf_decompressed = vec![0; original_length];
+    lz4_jblock_decompress(buf_compressed, &mut buf_decompressed)?;
+
+    Ok(buf_decompressed)
+}
+
+/// Same as `lz4_with_length_decompress` with `DEFAULT_MAX_DECOMPRESSED_LENGTH` as the limit.
+pub fn lz4_with_length_decompress_default(buf_compressed: &[u8]) -> error::Result<Vec<u8>> {
+    lz4_with_length_decompress(buf_compressed, DEFAULT_MAX_DECOMPRESSED_LENGTH)
+}
+
+/// Compresses `buf_decompressed` into a size-prefixed block.
+///
+/// Java reads the prefix as a signed int, so inputs over 2GB are rejected.
+pub fn lz4_with_length_compress(buf_decompressed: &[u8]) -> error::Result<Vec<u8>> {
+    let original_length = i32::try_from(buf_decompressed.len()).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "lz4 with length can't store more than 2GB of data",
+        )
+    })?;
+
+    let mut buf_compressed = Vec::with_capacity(LENGTH_PREFIX_SIZE + buf_decompressed.len() / 2);
+    buf_compressed.extend_from_slice(&original_length.to_le_bytes());
+    lz4_jblock_compress(buf_decompressed, &mut buf_compressed);
+
+    Ok(buf_compressed)
+}
@@ -1,89 +1,352 @@
-use core::cmp;
-use std::io::{self, BufRead, Error, ErrorKind, Read};
+use core::mem;
+use std::io::{self, BufRead, Error, ErrorKind, Read, Seek, SeekFrom, Write};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use crate::block_index::BlockIndex;
+use crate::lzf_compress::lzf_compress;
+use crate::lzf_decoder::{decode_chunk, COMPRESSED_HEADER_LENGTH, UNCOMPRESSED_HEADER_LENGTH};
+pub use crate::lzf_decoder::{
+    ChunkInfo, LzfDecoder, CHUNK_TYPE_COMPRESSED, CHUNK_TYPE_UNCOMPRESSED,
+};
+use crate::parallel::{Job, ParallelBlocks, ParallelEncoder};
+use crate::skip::{skip_by_reading, skip_by_seeking, stream_len};
 
-use crate::lzf_decompress::lzf_decompress;
+/// Largest chunk `LzfWriter` can write, lengths are stored as u16.
+pub const MAX_CHUNK_SIZE: usize = 0xFFFF;
 
-// LZF format specs says only 2 bytes to specify either of buffer sizes
-const LZF_BUF_SIZE: usize = 64 * 1024;
+/// Totals of a stream gathered from its chunk headers alone, see `scan`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanInfo {
+    pub chunk_count: u64,
+    /// Length of the whole stream, headers included.
+    pub compressed_length: u64,
+    pub decompressed_length: u64,
+}
+
+/// Walks the chunk headers of a stream up to EOF without decompressing anything, reading and
+/// discarding chunk payloads.
+pub fn scan<R: Read>(reader: R) -> io::Result<ScanInfo> {
+    scan_with(reader, skip_by_reading)
+}
+
+/// Same as `scan`, but seeks over chunk payloads instead of reading them.
+pub fn scan_seekable<R: Read + Seek>(mut reader: R) -> io::Result<ScanInfo> {
+    let stream_len = stream_len(&mut reader)?;
+    scan_with(reader, |inner, n| skip_by_seeking(inner, n, stream_len))
+}
+
+/// Builds the index `LzfReader` needs for seeking by walking chunk headers, same as
+/// `scan_seekable`. Offsets in it are counted from where `reader` is positioned.
+pub fn build_chunk_index<R: Read + Seek>(mut reader: R) -> io::Result<BlockIndex> {
+    let stream_len = stream_len(&mut reader)?;
+    let mut chunk_index = BlockIndex::new();
+    walk_headers(
+        reader,
+        |inner, n| skip_by_seeking(inner, n, stream_len),
+        |info| {
+            let header_length = if info.chunk_type == CHUNK_TYPE_COMPRESSED {
+                COMPRESSED_HEADER_LENGTH
+            } else {
+                UNCOMPRESSED_HEADER_LENGTH
+            };
+            chunk_index.push(
+                info.offset,
+                header_length + info.compressed_length as u64,
+                info.original_length as u64,
+            )
+        },
+    )?;
+    Ok(chunk_index)
+}
+
+fn scan_with<R: Read>(
+    reader: R,
+    skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+) -> io::Result<ScanInfo> {
+    let mut scan_info = ScanInfo::default();
+    scan_info.compressed_length = walk_headers(reader, skip, |info| {
+        scan_info.chunk_count += 1;
+        scan_info.decompressed_length += info.original_length as u64;
+        Ok(())
+    })?;
+    Ok(scan_info)
+}
+
+// calls `on_chunk` for every chunk header, skipping payloads; returns the length walked
+fn walk_headers<R: Read>(
+    reader: R,
+    mut skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+    mut on_chunk: impl FnMut(&ChunkInfo) -> io::Result<()>,
+) -> io::Result<u64> {
+    let mut reader = LzfReader::new(reader);
+    while let Some(info) = reader.read_chunk_header()? {
+        skip(&mut reader.inner, info.compressed_length as u64)?;
+        reader.decoder.skip_payload();
+        on_chunk(&info)?;
+    }
+    Ok(reader.decoder.offset)
+}
 
+/// Only what the stream needs is read from `reader`, which is never read past the end of the
+/// last chunk.
 pub struct LzfReader<R> {
     inner: R,
-    buf_compressed: Box<[u8]>,
-    buf_decompressed: Box<[u8]>,
-    pos: usize,
-    cap: usize,
+    decoder: LzfDecoder,
+    chunk_index: Option<BlockIndex>,
+    // position of the start of stream in `inner`, only known once seeking is involved
+    inner_start: Option<u64>,
 }
 
 impl<R: Read> LzfReader<R> {
     pub fn new(reader: R) -> LzfReader<R> {
         LzfReader {
             inner: reader,
-            buf_compressed: vec![0; LZF_BUF_SIZE].into_boxed_slice(),
-            buf_decompressed: vec![0; LZF_BUF_SIZE].into_boxed_slice(),
-            pos: 0,
-            cap: 0,
-        }
-    }
-
-    fn fill_buf_decompressed(&mut self) -> io::Result<()> {
-        assert_eq!(self.pos, self.cap);
-
-        // looking for 'Z', if there is EOF, then we are done
-        let magic_z = match self.inner.read_u8() {
-            Ok(b) => b,
-            Err(err) => {
-                return if err.kind() == ErrorKind::UnexpectedEof {
-                    self.pos = 0;
-                    self.cap = 0;
-                    Ok(())
-                } else {
-                    Err(err)
-                }
-            }
-        };
-        if magic_z != b'Z' {
-            return Err(Error::new(ErrorKind::InvalidData, "wrong lzf magic"));
+            decoder: LzfDecoder::new(),
+            chunk_index: None,
+            inner_start: None,
+        }
+    }
+
+    /// Decodes the next ZV chunk, appending its decompressed contents to `out`, and returns its
+    /// header. `None` means there are no more chunks.
+    ///
+    /// Can't be called while a chunk partially read through `Read` is pending.
+    pub fn next_chunk(&mut self, out: &mut Vec<u8>) -> io::Result<Option<ChunkInfo>> {
+        if !self.decoder.output().is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "lzf chunk is partially read",
+            ));
         }
 
-        let magic_v = self.inner.read_u8()?;
-        if magic_v != b'V' {
-            return Err(Error::new(ErrorKind::InvalidData, "wrong lzf magic"));
+        let info = self.decode_next_chunk()?;
+        let output = self.decoder.output();
+        out.extend_from_slice(output);
+        let len = output.len();
+        self.decoder.consume(len);
+        Ok(info)
+    }
+
+    // feeds the decoder up to the end of next chunk, `None` at the end of stream
+    fn decode_next_chunk(&mut self) -> io::Result<Option<ChunkInfo>> {
+        loop {
+            let len = match self.read_input()? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if let Some(info) = self.decoder.fill_input(len)? {
+                return Ok(Some(info));
+            }
         }
+    }
 
-        let chunk_type = self.inner.read_u8()?;
-        match chunk_type {
-            0 => {
-                // uncompressed chunk
-                let chunk_length = self.inner.read_u16::<BigEndian>()? as usize;
-                let buf_decompressed_capped = &mut self.buf_decompressed[..chunk_length];
-                self.inner.read_exact(buf_decompressed_capped)?;
+    // reads the header of next chunk into the decoder, leaving its payload to the caller, who has
+    // to call `skip_payload` on the decoder after it; `None` at the end of stream
+    fn read_chunk_header(&mut self) -> io::Result<Option<ChunkInfo>> {
+        loop {
+            if let Some(info) = self.decoder.chunk {
+                return Ok(Some(info));
+            }
+            let len = match self.read_input()? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if let Some(info) = self.decoder.fill_input(len)? {
+                // chunk without payload, header is all there is
+                return Ok(Some(info));
+            }
+        }
+    }
 
-                self.pos = 0;
-                self.cap = chunk_length;
+    // reads into what the decoder needs next, never more, `None` at the end of stream
+    fn read_input(&mut self) -> io::Result<Option<usize>> {
+        loop {
+            let buf = self.decoder.input_buf();
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            match self.inner.read(buf) {
+                Ok(0) => {
+                    self.decoder.finish()?;
+                    return Ok(None);
+                }
+                Ok(len) => return Ok(Some(len)),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             }
-            1 => {
-                // compressed chunk
-                let chunk_length = self.inner.read_u16::<BigEndian>()? as usize;
-                let original_length = self.inner.read_u16::<BigEndian>()? as usize;
+        }
+    }
+}
 
-                let buf_compressed_capped = &mut self.buf_compressed[..chunk_length];
-                self.inner.read_exact(buf_compressed_capped)?;
+/// Reads the same streams as `LzfReader`, but decodes chunks on a pool of `threads` worker
+/// threads, with up to `chunks_in_flight` chunks read ahead of the data being consumed. Data
+/// still comes out in stream order.
+pub struct ParallelLzfReader<R> {
+    source: LzfReader<R>,
+    chunks: ParallelBlocks,
+}
+
+impl<R: Read> ParallelLzfReader<R> {
+    pub fn new(
+        reader: R,
+        threads: usize,
+        chunks_in_flight: usize,
+    ) -> io::Result<ParallelLzfReader<R>> {
+        Ok(ParallelLzfReader {
+            source: LzfReader::new(reader),
+            chunks: ParallelBlocks::new(threads, chunks_in_flight)?,
+        })
+    }
+}
 
-                let decompressed_length =
-                    lzf_decompress(buf_compressed_capped, self.buf_decompressed.as_mut())?;
-                assert_eq!(original_length, decompressed_length);
+// reads the next chunk holding data, and prepares its decoding for a worker thread
+fn next_chunk_job<R: Read>(source: &mut LzfReader<R>) -> io::Result<Option<Job>> {
+    loop {
+        let info = match source.read_chunk_header()? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
 
-                self.pos = 0;
-                self.cap = decompressed_length;
+        let mut payload = vec![0; info.compressed_length as usize];
+        source.inner.read_exact(&mut payload)?;
+        source.decoder.skip_payload();
+        // chunk with neither payload nor data has nothing to decode, any other is validated by
+        // `decode_chunk`, like `LzfReader` does
+        if info.compressed_length == 0 && info.original_length == 0 {
+            continue;
+        }
+
+        return Ok(Some(Box::new(move || {
+            if info.chunk_type == CHUNK_TYPE_UNCOMPRESSED {
+                return Ok(payload);
             }
-            _ => {
-                return Err(Error::new(ErrorKind::InvalidData, "unknown lzf chunk type"));
+            let mut buf_decompressed = vec![0; info.original_length as usize];
+            decode_chunk(&info, &payload, &mut buf_decompressed)?;
+            Ok(buf_decompressed)
+        })));
+    }
+}
+
+impl<R: Read> Read for ParallelLzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = {
+            let mut rem = self.fill_buf()?;
+            rem.read(buf)?
+        };
+        self.consume(bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read> BufRead for ParallelLzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let source = &mut self.source;
+        self.chunks.fill_buf(|| next_chunk_job(source))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunks.consume(amt);
+    }
+}
+
+impl<R: Read + Seek> LzfReader<R> {
+    /// Uses `chunk_index` for seeking, instead of building one by walking all chunk headers on
+    /// first seek. It has to be built for the stream as it starts where the reader was created,
+    /// for example by `build_chunk_index` or loaded from a sidecar file.
+    pub fn set_chunk_index(&mut self, chunk_index: BlockIndex) {
+        self.chunk_index = Some(chunk_index);
+    }
+
+    /// Index used for seeking, built by walking chunk headers if it was not set.
+    pub fn chunk_index(&mut self) -> io::Result<&BlockIndex> {
+        let inner_start = self.inner_start()?;
+        if self.chunk_index.is_none() {
+            let pos = self.inner.stream_position()?;
+            self.inner.seek(SeekFrom::Start(inner_start))?;
+            let chunk_index = build_chunk_index(&mut self.inner);
+            self.inner.seek(SeekFrom::Start(pos))?;
+            self.chunk_index = Some(chunk_index?);
+        }
+        Ok(self.chunk_index.as_ref().unwrap())
+    }
+
+    fn inner_start(&mut self) -> io::Result<u64> {
+        if let Some(inner_start) = self.inner_start {
+            return Ok(inner_start);
+        }
+        // nothing is read from inner beyond what the decoder took
+        let inner_start = self
+            .inner
+            .stream_position()?
+            .checked_sub(self.decoder.offset)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "lzf reader position is out of sync with its source",
+                )
+            })?;
+        self.inner_start = Some(inner_start);
+        Ok(inner_start)
+    }
+}
+
+/// Seeking decompresses only the chunk holding the new position, or nothing if it is within the
+/// current one. Positions past the end are allowed, reading there returns no data.
+impl<R: Read + Seek> Seek for LzfReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let decoder = &self.decoder;
+        let current = decoder.decompressed_offset + decoder.pos as u64;
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => current.checked_add_signed(n),
+            SeekFrom::End(n) => self
+                .chunk_index()?
+                .decompressed_length()
+                .checked_add_signed(n),
+        }
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // within the chunk already decompressed
+        let decoder = &mut self.decoder;
+        if target >= decoder.decompressed_offset
+            && target < decoder.decompressed_offset + decoder.cap as u64
+        {
+            decoder.pos = (target - decoder.decompressed_offset) as usize;
+            return Ok(target);
+        }
+
+        let inner_start = self.inner_start()?;
+        let chunk_index = self.chunk_index()?;
+        let (compressed_offset, decompressed_offset) = match chunk_index.find(target) {
+            Some(i) => {
+                let entry = chunk_index.entries()[i];
+                (entry.compressed_offset, entry.decompressed_offset)
             }
+            None => (chunk_index.compressed_length(), target),
         };
+        let past_end = target >= chunk_index.decompressed_length();
 
-        Ok(())
+        self.inner
+            .seek(SeekFrom::Start(inner_start + compressed_offset))?;
+        self.decoder.reset(compressed_offset, decompressed_offset);
+
+        if !past_end {
+            self.fill_buf()?;
+            let decoder = &mut self.decoder;
+            let pos = target - decoder.decompressed_offset;
+            if pos >= decoder.cap as u64 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzf chunk index does not match the stream",
+                ));
+            }
+            decoder.pos = pos as usize;
+        }
+        Ok(target)
     }
 }
 
@@ -100,17 +363,501 @@ impl<R: Read> Read for LzfReader<R> {
 
 impl<R: Read> BufRead for LzfReader<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        // If we've reached the end of our internal buffer then we need to fetch
-        // some more data from the underlying reader.
-        // Branch using `>=` instead of the more correct `==`
-        // to tell the compiler that the pos..cap slice is always valid.
-        if self.pos >= self.cap {
-            self.fill_buf_decompressed()?;
+        // empty chunks are skipped, so that they don't look like the end of stream
+        while self.decoder.output().is_empty() {
+            if self.decode_next_chunk()?.is_none() {
+                break;
+            }
         }
-        Ok(&self.buf_decompressed[self.pos..self.cap])
+        Ok(self.decoder.output())
     }
 
     fn consume(&mut self, amt: usize) {
-        self.pos = cmp::min(self.pos + amt, self.cap);
+        self.decoder.consume(amt);
+    }
+}
+
+/// Writes ZV chunk streams readable by `LzfReader` and compress-lzf's `LZFInputStream`. Data is
+/// cut into chunks of `chunk_size` bytes, each stored compressed, or uncompressed when
+/// compression doesn't make the chunk smaller.
+///
+/// `finish` has to be called to write the last chunk, `flush` only flushes whole chunks.
+pub struct LzfWriter<W: Write> {
+    inner: W,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    buf_encoded: Vec<u8>,
+}
+
+impl<W: Write> LzfWriter<W> {
+    /// `chunk_size` has to be between 1 and `MAX_CHUNK_SIZE`, compress-lzf uses
+    /// `MAX_CHUNK_SIZE`.
+    pub fn new(writer: W, chunk_size: usize) -> io::Result<LzfWriter<W>> {
+        check_chunk_size(chunk_size)?;
+        Ok(LzfWriter {
+            inner: writer,
+            chunk_size,
+            buf: Vec::with_capacity(chunk_size),
+            buf_encoded: Vec::new(),
+        })
+    }
+
+    /// Writes what is left and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.buf_encoded.clear();
+        encode_chunk(&self.buf, &mut self.buf_encoded);
+        self.inner.write_all(&self.buf_encoded)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for LzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.chunk_size {
+            self.write_chunk()?;
+        }
+        let len = buf.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() == self.chunk_size {
+            self.write_chunk()?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Same as `LzfWriter`, but compresses chunks on a pool of `threads` worker threads. Up to
+/// `chunks_in_flight` chunks are queued, after that writing waits for the oldest chunk to be
+/// compressed and written out. Output is identical to that of `LzfWriter`.
+pub struct ParallelLzfWriter<W: Write> {
+    inner: W,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    encoder: ParallelEncoder,
+}
+
+impl<W: Write> ParallelLzfWriter<W> {
+    pub fn new(
+        writer: W,
+        chunk_size: usize,
+        threads: usize,
+        chunks_in_flight: usize,
+    ) -> io::Result<ParallelLzfWriter<W>> {
+        check_chunk_size(chunk_size)?;
+        Ok(ParallelLzfWriter {
+            inner: writer,
+            chunk_size,
+            buf: Vec::with_capacity(chunk_size),
+            encoder: ParallelEncoder::new(threads, chunks_in_flight)?,
+        })
+    }
+
+    /// Writes what is left and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.submit_chunk()?;
+        self.encoder.write_pending(&mut self.inner)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn submit_chunk(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        self.encoder.submit(
+            &mut self.inner,
+            Box::new(move || {
+                let mut buf_encoded = Vec::new();
+                encode_chunk(&chunk, &mut buf_encoded);
+                Ok(buf_encoded)
+            }),
+        )
+    }
+}
+
+impl<W: Write> Write for ParallelLzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.chunk_size {
+            self.submit_chunk()?;
+        }
+        let len = buf.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Waits for all queued chunks to be compressed and written out.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() == self.chunk_size {
+            self.submit_chunk()?;
+        }
+        self.encoder.write_pending(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+/// Decompressing counterpart of `LzfReader` for data that is pushed rather than pulled:
+/// compressed data written to it in fragments of any size is decompressed into `writer`. Partial
+/// headers and chunks are kept until the rest of them arrives. `finish` has to be called to
+/// learn whether the stream was complete.
+pub struct LzfDecoderWriter<W: Write> {
+    inner: W,
+    decoder: LzfDecoder,
+}
+
+impl<W: Write> LzfDecoderWriter<W> {
+    pub fn new(writer: W) -> LzfDecoderWriter<W> {
+        LzfDecoderWriter {
+            inner: writer,
+            decoder: LzfDecoder::new(),
+        }
+    }
+
+    /// Writes out what is left and returns the underlying writer, failing if the stream ended in
+    /// the middle of a chunk.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_output()?;
+        self.decoder.finish()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.decoder.output();
+        if !output.is_empty() {
+            self.inner.write_all(output)?;
+            let len = output.len();
+            self.decoder.consume(len);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for LzfDecoderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the decoder takes nothing while the previous chunk is waiting to be written out
+        self.write_output()?;
+        Ok(self.decoder.feed(buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_output()?;
+        self.inner.flush()
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> io::Result<()> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "lzf chunk size must be between 1B and 64KB - 1",
+        ));
+    }
+    Ok(())
+}
+
+// appends header and payload of a single chunk holding `buf`, at most `MAX_CHUNK_SIZE` long
+fn encode_chunk(buf: &[u8], buf_encoded: &mut Vec<u8>) {
+    let header_start = buf_encoded.len();
+    buf_encoded.extend_from_slice(&[b'Z', b'V', CHUNK_TYPE_COMPRESSED, 0, 0]);
+    buf_encoded.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+    let payload_start = buf_encoded.len();
+    lzf_compress(buf, buf_encoded);
+
+    let compressed_length = buf_encoded.len() - payload_start;
+    // compressed chunk header is 2 bytes longer
+    if compressed_length + 2 < buf.len() {
+        buf_encoded[header_start + 3..header_start + 5]
+            .copy_from_slice(&(compressed_length as u16).to_be_bytes());
+    } else {
+        buf_encoded.truncate(header_start);
+        buf_encoded.extend_from_slice(&[b'Z', b'V', CHUNK_TYPE_UNCOMPRESSED]);
+        buf_encoded.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        buf_encoded.extend_from_slice(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_data::{check_seeks, noise, sample_data};
+
+    const CHUNK_SIZE: usize = 1000;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut writer = LzfWriter::new(Vec::new(), CHUNK_SIZE).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    // stream following some unrelated data
+    fn stream_in_file(data: &[u8], chunk_size: usize) -> Cursor<Vec<u8>> {
+        let mut writer = LzfWriter::new(b"prefix".to_vec(), chunk_size).unwrap();
+        writer.write_all(data).unwrap();
+        let mut cursor = Cursor::new(writer.finish().unwrap());
+        cursor.set_position(6);
+        cursor
+    }
+
+    #[test]
+    fn seek_moves_to_any_position() {
+        let data = sample_data(10 * CHUNK_SIZE + 300);
+        check_seeks(
+            LzfReader::new(stream_in_file(&data, CHUNK_SIZE)),
+            &data,
+            CHUNK_SIZE,
+        );
+    }
+
+    #[test]
+    fn loaded_chunk_index_is_used_for_seeking() {
+        let data = sample_data(10 * CHUNK_SIZE + 300);
+        let chunk_index = build_chunk_index(stream_in_file(&data, CHUNK_SIZE)).unwrap();
+        assert_eq!(chunk_index.entries().len(), 11);
+        assert_eq!(chunk_index.decompressed_length(), data.len() as u64);
+
+        let mut sidecar = Vec::new();
+        chunk_index.write_to(&mut sidecar).unwrap();
+        let loaded = BlockIndex::read_from(&sidecar[..]).unwrap();
+        assert_eq!(loaded, chunk_index);
+
+        let mut reader = LzfReader::new(stream_in_file(&data, CHUNK_SIZE));
+        reader.set_chunk_index(loaded);
+        assert_eq!(reader.chunk_index().unwrap(), &chunk_index);
+        check_seeks(reader, &data, CHUNK_SIZE);
+    }
+
+    #[test]
+    fn mismatched_chunk_index_is_an_error() {
+        // chunks of the indexed stream are twice as long as those of the one read
+        let data = sample_data(10 * CHUNK_SIZE);
+        let chunk_index = build_chunk_index(stream_in_file(&data, 2 * CHUNK_SIZE)).unwrap();
+
+        let mut reader = LzfReader::new(stream_in_file(&data, CHUNK_SIZE));
+        reader.set_chunk_index(chunk_index);
+        let err = reader
+            .seek(SeekFrom::Start(CHUNK_SIZE as u64 + 10))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "lzf chunk index does not match the stream");
+    }
+
+    #[test]
+    fn parallel_reader_matches_reader() {
+        let data = sample_data(40 * CHUNK_SIZE + 123);
+        let mut stream = compress(&data);
+        stream.extend_from_slice(b"ZV\x00\x00\x00");
+        stream.extend_from_slice(&compress(b"abc"));
+
+        let mut expected = Vec::new();
+        LzfReader::new(&stream[..])
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(expected, [&data[..], b"abc"].concat());
+        for (threads, chunks_in_flight) in [(1, 1), (2, 3), (4, 16)] {
+            let mut reader =
+                ParallelLzfReader::new(&stream[..], threads, chunks_in_flight).unwrap();
+            let mut buf_decompressed = Vec::new();
+            reader.read_to_end(&mut buf_decompressed).unwrap();
+            assert_eq!(buf_decompressed, expected);
+        }
+
+        let truncated = &stream[..stream.len() / 2];
+        let mut buf_decompressed = Vec::new();
+        let err = LzfReader::new(truncated)
+            .read_to_end(&mut buf_decompressed)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let mut reader = ParallelLzfReader::new(truncated, 4, 8).unwrap();
+        let err = reader.read_to_end(&mut buf_decompressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = reader.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn parallel_writer_matches_writer() {
+        let data = sample_data(40 * CHUNK_SIZE + 123);
+        let expected = compress(&data);
+        for (threads, chunks_in_flight) in [(1, 1), (2, 3), (4, 16)] {
+            let mut writer =
+                ParallelLzfWriter::new(Vec::new(), CHUNK_SIZE, threads, chunks_in_flight).unwrap();
+            // writes of odd sizes, crossing chunk boundaries
+            for part in data.chunks(777) {
+                writer.write_all(part).unwrap();
+            }
+            assert_eq!(writer.finish().unwrap(), expected);
+        }
+
+        let writer = ParallelLzfWriter::new(Vec::new(), CHUNK_SIZE, 2, 2).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"");
+    }
+
+    #[test]
+    fn decoder_writer_takes_writes_of_any_size() {
+        let data = sample_data(10 * CHUNK_SIZE + 123);
+        let stream = compress(&data);
+
+        let mut writer = LzfDecoderWriter::new(Vec::new());
+        for byte in &stream {
+            writer.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data);
+
+        let mut writer = LzfDecoderWriter::new(Vec::new());
+        writer.write_all(&stream).unwrap();
+        assert_eq!(writer.finish().unwrap(), data);
+
+        let mut writer = LzfDecoderWriter::new(Vec::new());
+        writer.write_all(&stream[..stream.len() / 2]).unwrap();
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn scan_totals_match_writer_output() {
+        let data = sample_data(5 * CHUNK_SIZE + 100);
+        let mut stream = compress(&data);
+        // empty chunk, counted but adding no data
+        stream.extend_from_slice(b"ZV\x00\x00\x00");
+
+        let expected = ScanInfo {
+            chunk_count: 7,
+            compressed_length: stream.len() as u64,
+            decompressed_length: data.len() as u64,
+        };
+        assert_eq!(scan(&stream[..]).unwrap(), expected);
+        assert_eq!(scan_seekable(Cursor::new(&stream)).unwrap(), expected);
+    }
+
+    #[test]
+    fn compressed_chunk_with_empty_original_length_is_rejected() {
+        for stream in [
+            &b"ZV\x01\x00\x02\x00\x00\x00\x41"[..],
+            b"ZV\x01\x00\x00\x00\x05",
+        ] {
+            let mut buf_decompressed = Vec::new();
+            let err = LzfReader::new(stream)
+                .read_to_end(&mut buf_decompressed)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            let mut reader = ParallelLzfReader::new(stream, 2, 4).unwrap();
+            let err = reader.read_to_end(&mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            // failure is not followed by what would look like a clean end of stream
+            let err = reader.read(&mut [0; 16]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        // empty chunks are just skipped by both
+        let stream = b"ZV\x01\x00\x00\x00\x00ZV\x00\x00\x00";
+        let mut buf_decompressed = Vec::new();
+        LzfReader::new(&stream[..])
+            .read_to_end(&mut buf_decompressed)
+            .unwrap();
+        ParallelLzfReader::new(&stream[..], 2, 4)
+            .unwrap()
+            .read_to_end(&mut buf_decompressed)
+            .unwrap();
+        assert!(buf_decompressed.is_empty());
+    }
+
+    // feeds `input` to `decoder` a byte at a time
+    fn feed_bytewise(decoder: &mut LzfDecoder, mut input: &[u8]) -> Vec<u8> {
+        let mut buf_decompressed = Vec::new();
+        while !input.is_empty() {
+            let len = decoder.feed(&input[..1]).unwrap();
+            input = &input[len..];
+            let output = decoder.output();
+            buf_decompressed.extend_from_slice(output);
+            let len = output.len();
+            decoder.consume(len);
+        }
+        buf_decompressed
+    }
+
+    #[test]
+    fn decoder_takes_input_one_byte_at_a_time() {
+        let data = sample_data(40 * CHUNK_SIZE + 123);
+        let stream = compress(&data);
+
+        let mut decoder = LzfDecoder::new();
+        assert_eq!(feed_bytewise(&mut decoder, &stream), data);
+        decoder.finish().unwrap();
+
+        let mut decoder = LzfDecoder::new();
+        feed_bytewise(&mut decoder, &stream[..stream.len() - 1]);
+        let err = decoder.finish().unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn next_chunk_returns_chunk_headers() {
+        let mut data = vec![b'a'; CHUNK_SIZE];
+        data.extend_from_slice(&noise(CHUNK_SIZE / 2));
+        let mut stream = compress(&data);
+        // empty chunk, which writers never produce but readers accept
+        stream.extend_from_slice(b"ZV\x00\x00\x00");
+
+        let mut reader = LzfReader::new(&stream[..]);
+        let mut buf_decompressed = Vec::new();
+        let mut chunks = Vec::new();
+        while let Some(info) = reader.next_chunk(&mut buf_decompressed).unwrap() {
+            chunks.push(info);
+        }
+        assert_eq!(buf_decompressed, data);
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].offset, 0);
+        assert_eq!(chunks[0].chunk_type, CHUNK_TYPE_COMPRESSED);
+        assert_eq!(chunks[0].original_length, CHUNK_SIZE as u16);
+        assert!(chunks[0].compressed_length < 100);
+
+        let offset = COMPRESSED_HEADER_LENGTH + chunks[0].compressed_length as u64;
+        let expected = ChunkInfo {
+            offset,
+            chunk_type: CHUNK_TYPE_UNCOMPRESSED,
+            compressed_length: CHUNK_SIZE as u16 / 2,
+            original_length: CHUNK_SIZE as u16 / 2,
+        };
+        assert_eq!(chunks[1], expected);
+        let expected = ChunkInfo {
+            offset: offset + UNCOMPRESSED_HEADER_LENGTH + CHUNK_SIZE as u64 / 2,
+            chunk_type: CHUNK_TYPE_UNCOMPRESSED,
+            compressed_length: 0,
+            original_length: 0,
+        };
+        assert_eq!(chunks[2], expected);
+    }
+
+    #[test]
+    fn next_chunk_refuses_partially_read_chunk() {
+        let stream = compress(&sample_data(3 * CHUNK_SIZE));
+        let mut reader = LzfReader::new(&stream[..]);
+        reader.read_exact(&mut [0; 10]).unwrap();
+
+        let mut buf_decompressed = Vec::new();
+        let err = reader.next_chunk(&mut buf_decompressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        reader.read_exact(&mut [0; CHUNK_SIZE - 10]).unwrap();
+        let info = reader.next_chunk(&mut buf_decompressed).unwrap().unwrap();
+        assert_eq!(info.original_length, CHUNK_SIZE as u16);
     }
 }
@@ -0,0 +1,92 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crate::async_io::{impl_async_read, PollRead};
+use crate::lzf::LzfDecoder;
+
+/// Async counterpart of `LzfReader`. Implements `AsyncRead` and `AsyncBufRead` of tokio with the
+/// `tokio` feature, and of futures with the `futures-io` feature.
+pub struct AsyncLzfReader<R> {
+    inner: R,
+    decoder: LzfDecoder,
+}
+
+impl<R: Unpin> AsyncLzfReader<R> {
+    pub fn new(reader: R) -> AsyncLzfReader<R> {
+        AsyncLzfReader {
+            inner: reader,
+            decoder: LzfDecoder::new(),
+        }
+    }
+
+    fn poll_fill_buf_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll_read: PollRead<R>,
+    ) -> Poll<io::Result<&[u8]>> {
+        // empty chunks are skipped, so that they don't look like the end of stream
+        while self.decoder.output().is_empty() {
+            let buf = self.decoder.input_buf();
+            if buf.is_empty() {
+                break;
+            }
+            let len = ready!(poll_read(Pin::new(&mut self.inner), cx, buf))?;
+            if len == 0 {
+                self.decoder.finish()?;
+                break;
+            }
+            self.decoder.fill_input(len)?;
+        }
+        Poll::Ready(Ok(self.decoder.output()))
+    }
+
+    fn consume_decompressed(&mut self, amt: usize) {
+        self.decoder.consume(amt);
+    }
+}
+
+impl_async_read!(AsyncLzfReader);
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+
+    use super::*;
+    use crate::async_io::{poll_to_end, Trickle};
+    use crate::lzf::{LzfReader, LzfWriter};
+    use crate::test_data::sample_data;
+
+    fn check_reader(poll_read: PollRead<AsyncLzfReader<Trickle>>) {
+        let data = sample_data(5 * 1000 + 100);
+        let mut writer = LzfWriter::new(Vec::new(), 1000).unwrap();
+        writer.write_all(&data).unwrap();
+        let mut stream = writer.finish().unwrap();
+        // empty chunk, which must not look like the end of stream
+        stream.splice(0..0, *b"ZV\x00\x00\x00");
+
+        let mut expected = Vec::new();
+        LzfReader::new(&stream[..])
+            .read_to_end(&mut expected)
+            .unwrap();
+        let mut reader = AsyncLzfReader::new(Trickle::new(stream.clone()));
+        assert_eq!(poll_to_end(&mut reader, poll_read).unwrap(), expected);
+
+        let truncated = stream[..stream.len() / 2].to_vec();
+        let mut reader = AsyncLzfReader::new(Trickle::new(truncated));
+        let err = poll_to_end(&mut reader, poll_read).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_reader_matches_reader() {
+        check_reader(crate::async_io::tokio_poll_read);
+    }
+
+    #[cfg(feature = "futures-io")]
+    #[test]
+    fn futures_reader_matches_reader() {
+        check_reader(crate::async_io::futures_poll_read);
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::{vec, vec::Vec};
+
+const MIN_MATCH: usize = 3;
+// 3 bits of the control byte plus an extra length byte
+const MAX_MATCH: usize = 8 + 0xFF + 1;
+// 13 bits of distance - 1
+const MAX_DISTANCE: usize = 1 << 13;
+const MAX_LITERAL_RUN: usize = 1 << 5;
+
+const HASH_LOG: u32 = 13;
+
+/// Compresses `buf_decompressed` into raw lzf data appended to `buf_compressed`, readable by
+/// `lzf_decompress` as well as by liblzf's `lzf_decompress`.
+///
+/// Incompressible input grows by one byte per 32, callers storing chunks should fall back to
+/// storing data uncompressed when that doesn't pay off.
+pub fn lzf_compress(buf_decompressed: &[u8], buf_compressed: &mut Vec<u8>) {
+    let len = buf_decompressed.len();
+    let mut anchor = 0;
+    let mut in_pos = 0;
+    let mut hash_table = vec![usize::MAX; 1 << HASH_LOG];
+
+    while in_pos + MIN_MATCH <= len {
+        let sequence = read24be(buf_decompressed, in_pos);
+        let hash_index = hash(sequence);
+        let candidate = hash_table[hash_index];
+        hash_table[hash_index] = in_pos;
+
+        if candidate == usize::MAX
+            || in_pos - candidate > MAX_DISTANCE
+            || read24be(buf_decompressed, candidate) != sequence
+        {
+            in_pos += 1;
+            continue;
+        }
+
+        let max_run_len = MAX_MATCH.min(len - in_pos);
+        let mut run_len = MIN_MATCH;
+        while run_len < max_run_len
+            && buf_decompressed[candidate + run_len] == buf_decompressed[in_pos + run_len]
+        {
+            run_len += 1;
+        }
+
+        write_literals(buf_compressed, &buf_decompressed[anchor..in_pos]);
+        write_back_reference(buf_compressed, in_pos - candidate, run_len);
+
+        // positions inside the match are not hashed, except for the last ones, which are the
+        // most likely to repeat right after it
+        in_pos += run_len;
+        anchor = in_pos;
+        for pos in in_pos.saturating_sub(2)..in_pos {
+            if pos + MIN_MATCH <= len {
+                hash_table[hash(read24be(buf_decompressed, pos))] = pos;
+            }
+        }
+    }
+
+    write_literals(buf_compressed, &buf_decompressed[anchor..]);
+}
+
+fn write_literals(buf_compressed: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(MAX_LITERAL_RUN) {
+        buf_compressed.push((run.len() - 1) as u8);
+        buf_compressed.extend_from_slice(run);
+    }
+}
+
+fn write_back_reference(buf_compressed: &mut Vec<u8>, ref_offset: usize, run_len: usize) {
+    let ref_offset = ref_offset - 1;
+    let run_len = run_len - 2;
+    if run_len < 7 {
+        buf_compressed.push(((run_len << 5) | (ref_offset >> 8)) as u8);
+    } else {
+        buf_compressed.push(((7 << 5) | (ref_offset >> 8)) as u8);
+        buf_compressed.push((run_len - 7) as u8);
+    }
+    buf_compressed.push(ref_offset as u8);
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn read24be(input: &[u8], offset: usize) -> u32 {
+    ((input[offset] as u32) << 16) | ((input[offset + 1] as u32) << 8) | (input[offset + 2] as u32)
+}
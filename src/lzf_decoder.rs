@@ -0,0 +1,268 @@
+use core::{cmp, mem};
+
+use alloc::{boxed::Box, vec};
+
+use crate::error::{self, Error, ErrorKind};
+use crate::lzf_decompress::lzf_decompress;
+
+// LZF format specs says only 2 bytes to specify either of buffer sizes
+const LZF_BUF_SIZE: usize = 64 * 1024;
+
+pub const CHUNK_TYPE_UNCOMPRESSED: u8 = 0;
+pub const CHUNK_TYPE_COMPRESSED: u8 = 1;
+
+// magic, type and lengths
+pub(crate) const UNCOMPRESSED_HEADER_LENGTH: u64 = 2 + 1 + 2;
+pub(crate) const COMPRESSED_HEADER_LENGTH: u64 = 2 + 1 + 2 + 2;
+// magic and chunk type, telling how long the rest of the header is
+const HEADER_START_LENGTH: usize = 2 + 1;
+
+/// Header of a single ZV chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Offset of the chunk in the source, counted from where the reader started.
+    pub offset: u64,
+    /// `CHUNK_TYPE_UNCOMPRESSED` or `CHUNK_TYPE_COMPRESSED`.
+    pub chunk_type: u8,
+    /// Length of data following the header, same as `original_length` for uncompressed chunks.
+    pub compressed_length: u16,
+    pub original_length: u16,
+}
+
+/// Sans-IO decoder of ZV chunk streams: compressed data goes in through `feed` in fragments of
+/// any size, decompressed data comes out of `output` a chunk at a time. It does no IO of its
+/// own, so it can be driven from an event loop or by a non-blocking source without losing
+/// partially received chunks. `LzfReader` is built on it. Needs only `alloc`.
+pub struct LzfDecoder {
+    header: [u8; COMPRESSED_HEADER_LENGTH as usize],
+    header_filled: usize,
+    // how much of the header is needed, known once its chunk type is in
+    header_length: usize,
+    // chunk whose header is in, but not its payload yet
+    pub(crate) chunk: Option<ChunkInfo>,
+    buf_compressed: Box<[u8]>,
+    compressed_filled: usize,
+    buf_decompressed: Box<[u8]>,
+    pub(crate) pos: usize,
+    pub(crate) cap: usize,
+    // length of input taken so far
+    pub(crate) offset: u64,
+    // decompressed offset of the start of `buf_decompressed`
+    pub(crate) decompressed_offset: u64,
+}
+
+impl LzfDecoder {
+    pub fn new() -> LzfDecoder {
+        LzfDecoder {
+            header: [0; COMPRESSED_HEADER_LENGTH as usize],
+            header_filled: 0,
+            header_length: HEADER_START_LENGTH,
+            chunk: None,
+            buf_compressed: vec![0; LZF_BUF_SIZE].into_boxed_slice(),
+            compressed_filled: 0,
+            buf_decompressed: vec![0; LZF_BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            offset: 0,
+            decompressed_offset: 0,
+        }
+    }
+
+    /// Takes as much of `input` as the chunks it completes need, and returns how much that was.
+    /// Nothing is taken while decoded data is waiting in `output`.
+    pub fn feed(&mut self, mut input: &[u8]) -> error::Result<usize> {
+        let mut consumed = 0;
+        loop {
+            let buf = self.input_buf();
+            let len = buf.len().min(input.len());
+            if len == 0 {
+                return Ok(consumed);
+            }
+            buf[..len].copy_from_slice(&input[..len]);
+            input = &input[len..];
+            consumed += len;
+            self.fill_input(len)?;
+        }
+    }
+
+    /// Decompressed data not consumed yet, all of it from a single chunk.
+    pub fn output(&self) -> &[u8] {
+        &self.buf_decompressed[self.pos..self.cap]
+    }
+
+    pub fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+
+    /// Checks that input ended between two chunks, to be called once there is no more input.
+    pub fn finish(&self) -> error::Result<()> {
+        if self.header_filled != 0 || self.chunk.is_some() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "lzf chunk is truncated",
+            ));
+        }
+        Ok(())
+    }
+
+    // space for the input needed next, empty while output is pending
+    pub(crate) fn input_buf(&mut self) -> &mut [u8] {
+        if self.pos < self.cap {
+            return &mut [];
+        }
+        match self.chunk {
+            Some(info) => {
+                &mut self.buf_compressed[self.compressed_filled..info.compressed_length as usize]
+            }
+            None => &mut self.header[self.header_filled..self.header_length],
+        }
+    }
+
+    // takes `len` bytes written to `input_buf`, returns the chunk they completed, if any
+    pub(crate) fn fill_input(&mut self, len: usize) -> error::Result<Option<ChunkInfo>> {
+        self.offset += len as u64;
+
+        let info = match self.chunk {
+            Some(info) => info,
+            None => {
+                self.header_filled += len;
+                // magic is checked right away, so that data following a stream is reported as
+                // such even when it is shorter than a header
+                let magic_len = self.header_filled.min(2);
+                if self.header[..magic_len] != b"ZV"[..magic_len] {
+                    return Err(Error::new(ErrorKind::InvalidData, "wrong lzf magic"));
+                }
+                if self.header_filled == HEADER_START_LENGTH {
+                    self.header_length = chunk_header_length(&self.header)? as usize;
+                }
+                if self.header_filled < self.header_length {
+                    return Ok(None);
+                }
+
+                let header_length = self.header_length;
+                self.header_filled = 0;
+                self.header_length = HEADER_START_LENGTH;
+                let info = parse_chunk_header(
+                    &self.header[..header_length],
+                    self.offset - header_length as u64,
+                );
+                if info.compressed_length == 0 {
+                    self.decode(&info)?;
+                    return Ok(Some(info));
+                }
+                self.compressed_filled = 0;
+                self.chunk = Some(info);
+                return Ok(None);
+            }
+        };
+
+        self.compressed_filled += len;
+        if self.compressed_filled < info.compressed_length as usize {
+            return Ok(None);
+        }
+        self.chunk = None;
+        self.decode(&info)?;
+        Ok(Some(info))
+    }
+
+    // called by readers reading the payload of the pending chunk on their own
+    #[cfg(feature = "std")]
+    pub(crate) fn skip_payload(&mut self) {
+        if let Some(info) = self.chunk.take() {
+            self.offset += info.compressed_length as u64 - self.compressed_filled as u64;
+        }
+    }
+
+    fn decode(&mut self, info: &ChunkInfo) -> error::Result<()> {
+        let compressed_length = info.compressed_length as usize;
+        let original_length = info.original_length as usize;
+        self.decompressed_offset += self.cap as u64;
+        self.pos = 0;
+        self.cap = 0;
+
+        if info.chunk_type == CHUNK_TYPE_UNCOMPRESSED {
+            // payload is the data itself, no need to copy it around
+            mem::swap(&mut self.buf_compressed, &mut self.buf_decompressed);
+        } else {
+            decode_chunk(
+                info,
+                &self.buf_compressed[..compressed_length],
+                &mut self.buf_decompressed[..original_length],
+            )?;
+        }
+
+        self.cap = original_length;
+        Ok(())
+    }
+
+    // continues at `offset` of the stream, which is `decompressed_offset` of decompressed data
+    #[cfg(feature = "std")]
+    pub(crate) fn reset(&mut self, offset: u64, decompressed_offset: u64) {
+        self.header_filled = 0;
+        self.header_length = HEADER_START_LENGTH;
+        self.chunk = None;
+        self.pos = 0;
+        self.cap = 0;
+        self.offset = offset;
+        self.decompressed_offset = decompressed_offset;
+    }
+}
+
+impl Default for LzfDecoder {
+    fn default() -> LzfDecoder {
+        LzfDecoder::new()
+    }
+}
+
+/// Checks magic and chunk type in the first 3 bytes of `header`, and returns the length of the
+/// whole header.
+pub(crate) fn chunk_header_length(header: &[u8]) -> error::Result<u64> {
+    if header[..2] != *b"ZV" {
+        return Err(Error::new(ErrorKind::InvalidData, "wrong lzf magic"));
+    }
+    match header[2] {
+        CHUNK_TYPE_UNCOMPRESSED => Ok(UNCOMPRESSED_HEADER_LENGTH),
+        CHUNK_TYPE_COMPRESSED => Ok(COMPRESSED_HEADER_LENGTH),
+        _ => Err(Error::new(ErrorKind::InvalidData, "unknown lzf chunk type")),
+    }
+}
+
+/// Parses a whole header already checked by `chunk_header_length`, `offset` being where it
+/// starts.
+pub(crate) fn parse_chunk_header(header: &[u8], offset: u64) -> ChunkInfo {
+    let chunk_type = header[2];
+    let compressed_length = u16::from_be_bytes([header[3], header[4]]);
+    let original_length = if chunk_type == CHUNK_TYPE_COMPRESSED {
+        u16::from_be_bytes([header[5], header[6]])
+    } else {
+        compressed_length
+    };
+    ChunkInfo {
+        offset,
+        chunk_type,
+        compressed_length,
+        original_length,
+    }
+}
+
+/// Decodes the payload of a chunk into `buf_decompressed`, which has to be exactly
+/// `original_length` long.
+pub(crate) fn decode_chunk(
+    info: &ChunkInfo,
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<()> {
+    if info.chunk_type == CHUNK_TYPE_UNCOMPRESSED {
+        buf_decompressed.copy_from_slice(buf_compressed);
+        return Ok(());
+    }
+
+    let decompressed_length = lzf_decompress(buf_compressed, buf_decompressed)?;
+    if decompressed_length != buf_decompressed.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lzf decompressed chunk length mismatch",
+        ));
+    }
+    Ok(())
+}
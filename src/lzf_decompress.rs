@@ -1,6 +1,14 @@
-use std::io;
+use crate::error::{self, Error, ErrorKind};
 
-pub fn lzf_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> io::Result<usize> {
+use crate::back_reference::copy_back_reference;
+
+// longest back reference takes 3 bytes and expands into 264 bytes of output
+#[cfg(feature = "std")]
+pub(crate) const LZF_MAX_EXPANSION: usize = 88;
+
+/// Malformed input, or input that does not fit into `buf_decompressed`, results in an
+/// `InvalidData` error rather than a panic.
+pub fn lzf_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> error::Result<usize> {
     let mut in_pos = 0;
     let mut out_pos = 0;
 
@@ -12,6 +20,13 @@ pub fn lzf_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> io:
             // literal run
             let run_len = ctrl + 1;
 
+            if buf_compressed.len() - in_pos < run_len {
+                return Err(truncated());
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+
             buf_decompressed[out_pos..(out_pos + run_len)]
                 .copy_from_slice(&buf_compressed[in_pos..(in_pos + run_len)]);
 
@@ -22,7 +37,7 @@ pub fn lzf_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> io:
             let run_len = match ctrl >> 5 {
                 7 => {
                     // long back reference
-                    let run_len_raw = buf_compressed[in_pos] as usize;
+                    let run_len_raw = *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
                     in_pos += 1;
                     run_len_raw + 9
                 }
@@ -30,27 +45,34 @@ pub fn lzf_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> io:
             };
 
             let ref_offset_msb = (ctrl & 0x1F) << 8;
-            let ref_offset_lsb = buf_compressed[in_pos] as usize;
+            let ref_offset_lsb = *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
             in_pos += 1;
             let ref_offset = ref_offset_msb + ref_offset_lsb + 1;
-            let ref_pos = out_pos - ref_offset;
-
-            if ref_pos + run_len <= out_pos {
-                // non-overlapping
-                let (src, dst) = buf_decompressed.split_at_mut(out_pos);
-                dst[..run_len].copy_from_slice(&src[ref_pos..ref_pos + run_len]);
-                out_pos += run_len;
-            } else {
-                // overlapping
-                let mut ref_pos = ref_pos;
-                for _ in 0..run_len {
-                    buf_decompressed[out_pos] = buf_decompressed[ref_pos];
-                    out_pos += 1;
-                    ref_pos += 1;
-                }
+            if ref_offset > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzf back reference points before start of output",
+                ));
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
             }
+
+            copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+            out_pos += run_len;
         }
     }
 
     Ok(out_pos)
 }
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzf compressed data is truncated")
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzf decompressed data does not fit into output buffer",
+    )
+}
@@ -0,0 +1,758 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+use crate::lzvn_decompress::lzvn_decode;
+
+// block magics, stored as little-endian u32
+const ENDOFSTREAM_BLOCK_MAGIC: u32 = 0x24787662; // bvx$
+const UNCOMPRESSED_BLOCK_MAGIC: u32 = 0x2d787662; // bvx-
+const COMPRESSEDV1_BLOCK_MAGIC: u32 = 0x31787662; // bvx1
+const COMPRESSEDV2_BLOCK_MAGIC: u32 = 0x32787662; // bvx2
+const COMPRESSEDLZVN_BLOCK_MAGIC: u32 = 0x6e787662; // bvxn
+
+const ENCODE_L_SYMBOLS: usize = 20;
+const ENCODE_M_SYMBOLS: usize = 20;
+const ENCODE_D_SYMBOLS: usize = 64;
+const ENCODE_LITERAL_SYMBOLS: usize = 256;
+const ENCODE_L_STATES: usize = 64;
+const ENCODE_M_STATES: usize = 64;
+const ENCODE_D_STATES: usize = 256;
+const ENCODE_LITERAL_STATES: usize = 1024;
+
+const MATCHES_PER_BLOCK: usize = 10000;
+const LITERALS_PER_BLOCK: usize = 4 * MATCHES_PER_BLOCK;
+
+// size of lzfse_compressed_block_header_v1 struct, including its trailing padding
+const V1_HEADER_SIZE: usize = 772;
+// magic, n_raw_bytes and 3 packed u64 fields
+const V2_HEADER_FIXED_SIZE: usize = 32;
+
+const L_EXTRA_BITS: [u8; ENCODE_L_SYMBOLS] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 5, 8];
+const L_BASE_VALUE: [u32; ENCODE_L_SYMBOLS] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 28, 60,
+];
+const M_EXTRA_BITS: [u8; ENCODE_M_SYMBOLS] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 8, 11];
+const M_BASE_VALUE: [u32; ENCODE_M_SYMBOLS] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 24, 56, 312,
+];
+const D_EXTRA_BITS: [u8; ENCODE_D_SYMBOLS] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7,
+    8, 8, 8, 8, 9, 9, 9, 9, 10, 10, 10, 10, 11, 11, 11, 11, 12, 12, 12, 12, 13, 13, 13, 13, 14, 14,
+    14, 14, 15, 15, 15, 15,
+];
+const D_BASE_VALUE: [u32; ENCODE_D_SYMBOLS] = [
+    0, 1, 2, 3, 4, 6, 8, 10, 12, 16, 20, 24, 28, 36, 44, 52, 60, 76, 92, 108, 124, 156, 188, 220,
+    252, 316, 380, 444, 508, 636, 764, 892, 1020, 1276, 1532, 1788, 2044, 2556, 3068, 3580, 4092,
+    5116, 6140, 7164, 8188, 10236, 12284, 14332, 16380, 20476, 24572, 28668, 32764, 40956, 49148,
+    57340, 65532, 81916, 98300, 114684, 131068, 163836, 196604, 229372,
+];
+
+/// goal is to match `lzfse_decode_buffer` from https://github.com/lzfse/lzfse
+///
+/// Handles uncompressed (bvx-), lzvn (bvxn) and fse (bvx1, bvx2) blocks until the end of stream
+/// block (bvx$). Output is limited by the size of `buf_decompressed` and the number of
+/// decompressed bytes is returned.
+pub fn lzfse_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+
+    loop {
+        let magic = read_u32_le(buf_compressed, in_pos)?;
+
+        match magic {
+            ENDOFSTREAM_BLOCK_MAGIC => return Ok(out_pos),
+            UNCOMPRESSED_BLOCK_MAGIC => {
+                let n_raw_bytes = read_u32_le(buf_compressed, in_pos + 4)? as usize;
+                in_pos += 8;
+
+                if buf_compressed.len() - in_pos < n_raw_bytes {
+                    return Err(truncated());
+                }
+                if buf_decompressed.len() - out_pos < n_raw_bytes {
+                    return Err(output_overflow());
+                }
+                buf_decompressed[out_pos..out_pos + n_raw_bytes]
+                    .copy_from_slice(&buf_compressed[in_pos..in_pos + n_raw_bytes]);
+                in_pos += n_raw_bytes;
+                out_pos += n_raw_bytes;
+            }
+            COMPRESSEDLZVN_BLOCK_MAGIC => {
+                let n_raw_bytes = read_u32_le(buf_compressed, in_pos + 4)? as usize;
+                let n_payload_bytes = read_u32_le(buf_compressed, in_pos + 8)? as usize;
+                in_pos += 12;
+
+                if buf_compressed.len() - in_pos < n_payload_bytes {
+                    return Err(truncated());
+                }
+                if buf_decompressed.len() - out_pos < n_raw_bytes {
+                    return Err(output_overflow());
+                }
+                let buf_decompressed_capped = &mut buf_decompressed[..out_pos + n_raw_bytes];
+                let payload = &buf_compressed[in_pos..in_pos + n_payload_bytes];
+                let block_end = lzvn_decode(payload, buf_decompressed_capped, out_pos)?;
+                if block_end != out_pos + n_raw_bytes {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lzfse lzvn block decompressed size mismatch",
+                    ));
+                }
+                in_pos += n_payload_bytes;
+                out_pos += n_raw_bytes;
+            }
+            COMPRESSEDV1_BLOCK_MAGIC | COMPRESSEDV2_BLOCK_MAGIC => {
+                let (header, header_size) = if magic == COMPRESSEDV1_BLOCK_MAGIC {
+                    (parse_header_v1(buf_compressed, in_pos)?, V1_HEADER_SIZE)
+                } else {
+                    parse_header_v2(buf_compressed, in_pos)?
+                };
+                in_pos += header_size;
+                let block_start = in_pos;
+                let block_end = block_start
+                    .checked_add(header.n_literal_payload_bytes)
+                    .and_then(|pos| pos.checked_add(header.n_lmd_payload_bytes))
+                    .filter(|&pos| pos <= buf_compressed.len())
+                    .ok_or_else(truncated)?;
+
+                out_pos = decode_compressed_block(
+                    &header,
+                    &buf_compressed[..block_end],
+                    block_start,
+                    buf_decompressed,
+                    out_pos,
+                )?;
+                in_pos = block_end;
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "wrong lzfse block magic",
+                ))
+            }
+        }
+    }
+}
+
+// contents of lzfse_compressed_block_header_v1, bvx2 headers are unpacked into the same struct
+struct BlockHeader {
+    n_raw_bytes: usize,
+    n_literals: usize,
+    n_matches: usize,
+    n_literal_payload_bytes: usize,
+    n_lmd_payload_bytes: usize,
+    literal_bits: i32,
+    literal_state: [u16; 4],
+    lmd_bits: i32,
+    l_state: u16,
+    m_state: u16,
+    d_state: u16,
+    l_freq: [u16; ENCODE_L_SYMBOLS],
+    m_freq: [u16; ENCODE_M_SYMBOLS],
+    d_freq: [u16; ENCODE_D_SYMBOLS],
+    literal_freq: [u16; ENCODE_LITERAL_SYMBOLS],
+}
+
+impl BlockHeader {
+    fn check(&self) -> error::Result<()> {
+        let states_ok = self
+            .literal_state
+            .iter()
+            .all(|&s| (s as usize) < ENCODE_LITERAL_STATES)
+            && (self.l_state as usize) < ENCODE_L_STATES
+            && (self.m_state as usize) < ENCODE_M_STATES
+            && (self.d_state as usize) < ENCODE_D_STATES;
+        let freq_ok = check_freq(&self.l_freq, ENCODE_L_STATES)
+            && check_freq(&self.m_freq, ENCODE_M_STATES)
+            && check_freq(&self.d_freq, ENCODE_D_STATES)
+            && check_freq(&self.literal_freq, ENCODE_LITERAL_STATES);
+
+        if self.n_literals > LITERALS_PER_BLOCK
+            || self.n_matches > MATCHES_PER_BLOCK
+            || !states_ok
+            || !freq_ok
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid lzfse block header",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn check_freq(freq: &[u16], n_states: usize) -> bool {
+    freq.iter().map(|&f| f as usize).sum::<usize>() <= n_states
+}
+
+fn parse_header_v1(buf: &[u8], pos: usize) -> error::Result<BlockHeader> {
+    if buf.len() - pos < V1_HEADER_SIZE {
+        return Err(truncated());
+    }
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(buf[pos + offset..pos + offset + 4].try_into().unwrap());
+    let u16_at = |offset: usize| u16::from_le_bytes([buf[pos + offset], buf[pos + offset + 1]]);
+
+    let mut header = BlockHeader {
+        n_raw_bytes: u32_at(4) as usize,
+        n_literals: u32_at(12) as usize,
+        n_matches: u32_at(16) as usize,
+        n_literal_payload_bytes: u32_at(20) as usize,
+        n_lmd_payload_bytes: u32_at(24) as usize,
+        literal_bits: u32_at(28) as i32,
+        literal_state: [u16_at(32), u16_at(34), u16_at(36), u16_at(38)],
+        lmd_bits: u32_at(40) as i32,
+        l_state: u16_at(44),
+        m_state: u16_at(46),
+        d_state: u16_at(48),
+        l_freq: [0; ENCODE_L_SYMBOLS],
+        m_freq: [0; ENCODE_M_SYMBOLS],
+        d_freq: [0; ENCODE_D_SYMBOLS],
+        literal_freq: [0; ENCODE_LITERAL_SYMBOLS],
+    };
+
+    let mut offset = 50;
+    for freq in [
+        &mut header.l_freq[..],
+        &mut header.m_freq[..],
+        &mut header.d_freq[..],
+        &mut header.literal_freq[..],
+    ] {
+        for f in freq.iter_mut() {
+            *f = u16_at(offset);
+            offset += 2;
+        }
+    }
+
+    header.check()?;
+    Ok(header)
+}
+
+// returns header and its size, which for bvx2 depends on how well frequency tables packed
+fn parse_header_v2(buf: &[u8], pos: usize) -> error::Result<(BlockHeader, usize)> {
+    if buf.len() - pos < V2_HEADER_FIXED_SIZE {
+        return Err(truncated());
+    }
+    let u64_at =
+        |offset: usize| u64::from_le_bytes(buf[pos + offset..pos + offset + 8].try_into().unwrap());
+    let v0 = u64_at(8);
+    let v1 = u64_at(16);
+    let v2 = u64_at(24);
+    let field = |v: u64, offset: u32, nbits: u32| ((v >> offset) & ((1 << nbits) - 1)) as usize;
+
+    let mut header = BlockHeader {
+        n_raw_bytes: read_u32_le(buf, pos + 4)? as usize,
+        n_literals: field(v0, 0, 20),
+        n_matches: field(v0, 40, 20),
+        n_literal_payload_bytes: field(v0, 20, 20),
+        n_lmd_payload_bytes: field(v1, 40, 20),
+        literal_bits: field(v0, 60, 3) as i32 - 7,
+        literal_state: [
+            field(v1, 0, 10) as u16,
+            field(v1, 10, 10) as u16,
+            field(v1, 20, 10) as u16,
+            field(v1, 30, 10) as u16,
+        ],
+        lmd_bits: field(v1, 60, 3) as i32 - 7,
+        l_state: field(v2, 32, 10) as u16,
+        m_state: field(v2, 42, 10) as u16,
+        d_state: field(v2, 52, 10) as u16,
+        l_freq: [0; ENCODE_L_SYMBOLS],
+        m_freq: [0; ENCODE_M_SYMBOLS],
+        d_freq: [0; ENCODE_D_SYMBOLS],
+        literal_freq: [0; ENCODE_LITERAL_SYMBOLS],
+    };
+
+    let header_size = field(v2, 0, 32);
+    if header_size < V2_HEADER_FIXED_SIZE || buf.len() - pos < header_size {
+        return Err(truncated());
+    }
+
+    // frequency tables are omitted altogether if header ends right after fixed fields
+    let mut freq_pos = pos + V2_HEADER_FIXED_SIZE;
+    let freq_end = pos + header_size;
+    if freq_pos != freq_end {
+        let mut accum = 0_u32;
+        let mut accum_nbits = 0;
+        for freq in [
+            &mut header.l_freq[..],
+            &mut header.m_freq[..],
+            &mut header.d_freq[..],
+            &mut header.literal_freq[..],
+        ] {
+            for f in freq.iter_mut() {
+                // refill accum one byte at a time, until we reach end of header or accum is full
+                while freq_pos < freq_end && accum_nbits + 8 <= 32 {
+                    accum |= (buf[freq_pos] as u32) << accum_nbits;
+                    accum_nbits += 8;
+                    freq_pos += 1;
+                }
+
+                let (value, nbits) = decode_freq_value(accum);
+                if nbits > accum_nbits {
+                    return Err(invalid_header());
+                }
+                *f = value;
+                accum >>= nbits;
+                accum_nbits -= nbits;
+            }
+        }
+
+        // we need to end up exactly at the end of header, with less than 8 bits in accumulator
+        if accum_nbits >= 8 || freq_pos != freq_end {
+            return Err(invalid_header());
+        }
+    }
+
+    header.check()?;
+    Ok((header, header_size))
+}
+
+// variable length code of a single frequency value, returns value and its length in bits
+fn decode_freq_value(bits: u32) -> (u16, u32) {
+    const FREQ_NBITS_TABLE: [u8; 32] = [
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14, 2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3,
+        2, 14,
+    ];
+    const FREQ_VALUE_TABLE: [u8; 32] = [
+        0, 2, 1, 4, 0, 3, 1, 0, 0, 2, 1, 5, 0, 3, 1, 0, 0, 2, 1, 6, 0, 3, 1, 0, 0, 2, 1, 7, 0, 3,
+        1, 0,
+    ];
+
+    let b = (bits & 31) as usize;
+    match FREQ_NBITS_TABLE[b] {
+        8 => (8 + ((bits >> 4) & 0xF) as u16, 8),
+        14 => (24 + ((bits >> 4) & 0x3FF) as u16, 14),
+        nbits => (FREQ_VALUE_TABLE[b] as u16, nbits as u32),
+    }
+}
+
+fn decode_compressed_block(
+    header: &BlockHeader,
+    buf_compressed: &[u8],
+    block_start: usize,
+    buf_decompressed: &mut [u8],
+    mut out_pos: usize,
+) -> error::Result<usize> {
+    if buf_decompressed.len() - out_pos < header.n_raw_bytes {
+        return Err(output_overflow());
+    }
+    let block_out_end = out_pos + header.n_raw_bytes;
+
+    // literals are fse coded in 4 interleaved streams, read backwards from the end of payload
+    let literal_decoder = build_decoder_table(ENCODE_LITERAL_STATES, &header.literal_freq);
+    let literal_payload_end = block_start + header.n_literal_payload_bytes;
+    let mut literal_stream =
+        BackwardBitReader::new(&buf_compressed[..literal_payload_end], header.literal_bits)?;
+
+    let mut literals = vec![0_u8; (header.n_literals + 3) & !3];
+    let mut literal_state = header.literal_state;
+    for chunk in literals.chunks_mut(4) {
+        literal_stream.flush()?;
+        for (literal, state) in chunk.iter_mut().zip(literal_state.iter_mut()) {
+            let entry = literal_decoder
+                .get(*state as usize)
+                .ok_or_else(invalid_state)?;
+            *state = (entry.delta as u32 + literal_stream.pull(entry.k)?) as u16;
+            *literal = entry.symbol;
+        }
+    }
+
+    // then literal lengths, match lengths and distances in their own backward stream
+    let l_decoder = build_value_decoder_table(
+        ENCODE_L_STATES,
+        &header.l_freq,
+        &L_EXTRA_BITS,
+        &L_BASE_VALUE,
+    );
+    let m_decoder = build_value_decoder_table(
+        ENCODE_M_STATES,
+        &header.m_freq,
+        &M_EXTRA_BITS,
+        &M_BASE_VALUE,
+    );
+    let d_decoder = build_value_decoder_table(
+        ENCODE_D_STATES,
+        &header.d_freq,
+        &D_EXTRA_BITS,
+        &D_BASE_VALUE,
+    );
+    let mut lmd_stream = BackwardBitReader::new(buf_compressed, header.lmd_bits)?;
+
+    let mut l_state = header.l_state;
+    let mut m_state = header.m_state;
+    let mut d_state = header.d_state;
+    let mut distance = 0;
+    let mut literal_pos = 0;
+
+    for _ in 0..header.n_matches {
+        lmd_stream.flush()?;
+        let literal_len = decode_value(&mut l_state, &l_decoder, &mut lmd_stream)?;
+        let run_len = decode_value(&mut m_state, &m_decoder, &mut lmd_stream)?;
+        let new_distance = decode_value(&mut d_state, &d_decoder, &mut lmd_stream)?;
+        if new_distance != 0 {
+            distance = new_distance;
+        }
+
+        if literals.len() - literal_pos < literal_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lzfse block runs out of literals",
+            ));
+        }
+        if block_out_end - out_pos < literal_len + run_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lzfse block decompressed size mismatch",
+            ));
+        }
+
+        buf_decompressed[out_pos..out_pos + literal_len]
+            .copy_from_slice(&literals[literal_pos..literal_pos + literal_len]);
+        literal_pos += literal_len;
+        out_pos += literal_len;
+
+        if run_len > 0 {
+            if distance == 0 || distance > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzfse match distance points outside of output",
+                ));
+            }
+            copy_back_reference(buf_decompressed, out_pos, distance, run_len);
+            out_pos += run_len;
+        }
+    }
+
+    if out_pos != block_out_end {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lzfse block decompressed size mismatch",
+        ));
+    }
+
+    Ok(out_pos)
+}
+
+#[derive(Clone, Copy, Default)]
+struct DecoderEntry {
+    k: u32,
+    symbol: u8,
+    delta: i32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ValueDecoderEntry {
+    total_bits: u32,
+    value_bits: u32,
+    delta: i32,
+    vbase: u32,
+}
+
+// state ranges each symbol gets, shared by fse_init_decoder_table and its value variant:
+// for every state of the symbol calls `f(k, delta)`
+fn for_each_state(n_states: usize, f: usize, mut entry: impl FnMut(u32, i32)) {
+    let n_clz = (n_states as u32).leading_zeros();
+    // shift needed to ensure n_states <= (f << k) < 2 * n_states
+    let k = (f as u32).leading_zeros() - n_clz;
+    let j0 = ((2 * n_states) >> k) - f;
+    for j in 0..f {
+        if j < j0 {
+            entry(k, (((f + j) << k) - n_states) as i32);
+        } else {
+            entry(k - 1, ((j - j0) << (k - 1)) as i32);
+        }
+    }
+}
+
+fn build_decoder_table(n_states: usize, freq: &[u16]) -> Vec<DecoderEntry> {
+    let mut table = Vec::with_capacity(n_states);
+    for (symbol, &f) in freq.iter().enumerate() {
+        if f != 0 {
+            for_each_state(n_states, f as usize, |k, delta| {
+                table.push(DecoderEntry {
+                    k,
+                    symbol: symbol as u8,
+                    delta,
+                })
+            });
+        }
+    }
+    table
+}
+
+fn build_value_decoder_table(
+    n_states: usize,
+    freq: &[u16],
+    extra_bits: &[u8],
+    base_value: &[u32],
+) -> Vec<ValueDecoderEntry> {
+    let mut table = Vec::with_capacity(n_states);
+    for (symbol, &f) in freq.iter().enumerate() {
+        if f != 0 {
+            let value_bits = extra_bits[symbol] as u32;
+            for_each_state(n_states, f as usize, |k, delta| {
+                table.push(ValueDecoderEntry {
+                    total_bits: k + value_bits,
+                    value_bits,
+                    delta,
+                    vbase: base_value[symbol],
+                })
+            });
+        }
+    }
+    table
+}
+
+fn decode_value(
+    state: &mut u16,
+    table: &[ValueDecoderEntry],
+    stream: &mut BackwardBitReader,
+) -> error::Result<usize> {
+    let entry = table.get(*state as usize).ok_or_else(invalid_state)?;
+    let state_and_value_bits = stream.pull(entry.total_bits)?;
+    *state = (entry.delta as u32 + (state_and_value_bits >> entry.value_bits)) as u16;
+    let value_mask = (1 << entry.value_bits) - 1;
+    Ok((entry.vbase + (state_and_value_bits & value_mask)) as usize)
+}
+
+// matches fse_in_stream64: bits are pulled from the top of the accumulator, which is refilled
+// with bytes located before the ones already read, all the way down to the start of input
+struct BackwardBitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    accum: u64,
+    accum_nbits: u32,
+}
+
+impl<'a> BackwardBitReader<'a> {
+    // `buf` ends at the end of the stream, `n` is in -7..=0 and tells how many bits of the last
+    // byte are padding
+    fn new(buf: &'a [u8], n: i32) -> error::Result<BackwardBitReader<'a>> {
+        if !(-7..=0).contains(&n) {
+            return Err(invalid_header());
+        }
+        let init_bytes = if n != 0 { 8 } else { 7 };
+        if buf.len() < init_bytes {
+            return Err(truncated());
+        }
+        let pos = buf.len() - init_bytes;
+        let accum = read_le(&buf[pos..]);
+        let accum_nbits = (init_bytes as i32 * 8 + n) as u32;
+
+        // encoder should have zeroed the padding bits
+        if accum_nbits < 64 && accum >> accum_nbits != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "lzfse bit stream padding is not zero",
+            ));
+        }
+
+        Ok(BackwardBitReader {
+            buf,
+            pos,
+            accum,
+            accum_nbits,
+        })
+    }
+
+    // brings the number of bits in accumulator into 56..=63
+    fn flush(&mut self) -> error::Result<()> {
+        let nbits = (63 - self.accum_nbits) & !7;
+        let nbytes = (nbits / 8) as usize;
+        if nbytes == 0 {
+            return Ok(());
+        }
+        if self.pos < nbytes {
+            return Err(truncated());
+        }
+        self.pos -= nbytes;
+        let incoming = read_le(&self.buf[self.pos..self.pos + nbytes]);
+        self.accum = (self.accum << nbits) | incoming;
+        self.accum_nbits += nbits;
+        Ok(())
+    }
+
+    fn pull(&mut self, n: u32) -> error::Result<u32> {
+        if n > self.accum_nbits {
+            return Err(truncated());
+        }
+        self.accum_nbits -= n;
+        let result = self.accum >> self.accum_nbits;
+        self.accum &= (1_u64 << self.accum_nbits) - 1;
+        Ok(result as u32)
+    }
+}
+
+// little-endian value of up to 8 bytes
+fn read_le(buf: &[u8]) -> u64 {
+    buf.iter()
+        .take(8)
+        .rev()
+        .fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn read_u32_le(buf: &[u8], pos: usize) -> error::Result<u32> {
+    match buf.get(pos..pos + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(truncated()),
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzfse compressed data is truncated")
+}
+
+fn invalid_header() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid lzfse block header")
+}
+
+fn invalid_state() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzfse fse state is out of range")
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzfse decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNCOMPRESSED_BLOCK: &[u8] = b"bvx-\x04\x00\x00\x00wxyz";
+    // literal "abc", then a match of 6 at distance 3
+    const LZVN_BLOCK: &[u8] =
+        b"bvxn\x09\x00\x00\x00\x0e\x00\x00\x00\xe3abc\x18\x03\x06\x00\x00\x00\
+        \x00\x00\x00\x00";
+    // "hello hello hello world"
+    const V2_BLOCK: &[u8] = b"bvx2\x17\x00\x00\x00\x0b\x00\x80\x00\x00\x02\x00\x00\x6e\xdd\x24\x6a\
+        \x9a\x08\x00\x60\x91\x00\x00\x00\x20\x80\x00\x08\x00\x3c\x02\x8f\x00\x00\x00\x00\x8f\x00\x00\
+        \x00\xf0\x08\x00\x00\x8f\x06\xc0\xa3\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x00\xdf\x03\x00\x00\x00\x00\x00\x00\x00\xf0\x3d\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\xdf\xc3\xf7\x00\xdf\x03\xf0\xeb\xc0\x4b\x02\xdf\x03\xc0\xf7\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x5c\x0b\x48\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00";
+    const END_OF_STREAM: &[u8] = b"bvx$";
+    const DATA: &[u8] = b"wxyzabcabcabchello hello hello world";
+
+    fn stream(compressed_block: &[u8]) -> Vec<u8> {
+        [
+            UNCOMPRESSED_BLOCK,
+            LZVN_BLOCK,
+            compressed_block,
+            END_OF_STREAM,
+        ]
+        .concat()
+    }
+
+    // `V2_BLOCK` with the same header in bvx1 layout
+    fn v1_block() -> Vec<u8> {
+        let (header, header_size) = parse_header_v2(V2_BLOCK, 0).unwrap();
+        let mut block = COMPRESSEDV1_BLOCK_MAGIC.to_le_bytes().to_vec();
+        for n in [
+            header.n_raw_bytes,
+            header.n_literal_payload_bytes + header.n_lmd_payload_bytes,
+            header.n_literals,
+            header.n_matches,
+            header.n_literal_payload_bytes,
+            header.n_lmd_payload_bytes,
+            header.literal_bits as usize,
+        ] {
+            block.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        for state in header.literal_state {
+            block.extend_from_slice(&state.to_le_bytes());
+        }
+        block.extend_from_slice(&header.lmd_bits.to_le_bytes());
+        let freqs = [
+            &header.l_freq[..],
+            &header.m_freq,
+            &header.d_freq,
+            &header.literal_freq,
+        ];
+        let values = [header.l_state, header.m_state, header.d_state]
+            .into_iter()
+            .chain(freqs.into_iter().flatten().copied());
+        for value in values {
+            block.extend_from_slice(&value.to_le_bytes());
+        }
+        block.extend_from_slice(&[0; 2]);
+        assert_eq!(block.len(), V1_HEADER_SIZE);
+        block.extend_from_slice(&V2_BLOCK[header_size..]);
+        block
+    }
+
+    #[test]
+    fn blocks_of_every_kind_are_decoded() {
+        for buf_compressed in [stream(V2_BLOCK), stream(&v1_block())] {
+            let mut buf_decompressed = [0_u8; 64];
+            let len = lzfse_decompress(&buf_compressed, &mut buf_decompressed).unwrap();
+            assert_eq!(&buf_decompressed[..len], DATA);
+
+            // output buffer exactly as long as the data, and one byte short
+            let mut buf_decompressed = [0_u8; DATA.len()];
+            let len = lzfse_decompress(&buf_compressed, &mut buf_decompressed).unwrap();
+            assert_eq!(len, DATA.len());
+            let err = lzfse_decompress(&buf_compressed, &mut buf_decompressed[1..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn truncated_streams_are_errors() {
+        let mut buf_decompressed = [0_u8; 64];
+        for buf_compressed in [stream(V2_BLOCK), stream(&v1_block())] {
+            for len in 0..buf_compressed.len() {
+                let err =
+                    lzfse_decompress(&buf_compressed[..len], &mut buf_decompressed).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::InvalidData, "{len}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_streams_are_errors() {
+        let mut buf_decompressed = [0_u8; 64];
+        let buf_compressed = stream(V2_BLOCK);
+        let v2_start = UNCOMPRESSED_BLOCK.len() + LZVN_BLOCK.len();
+
+        let mut wrong_magic = buf_compressed.clone();
+        wrong_magic[3] = b'?';
+        // lzvn block decoding into less than its raw size
+        let mut lzvn_size_mismatch = buf_compressed.clone();
+        lzvn_size_mismatch[UNCOMPRESSED_BLOCK.len() + 4] = 10;
+        // L state past the number of L states
+        let mut state_out_of_range = buf_compressed.clone();
+        state_out_of_range[v2_start + 28] = 0xFF;
+        let mut v1_freq_over_states = stream(&v1_block());
+        v1_freq_over_states[v2_start + 50..v2_start + 52].copy_from_slice(&[0xFF, 0x00]);
+
+        for buf_compressed in [
+            wrong_magic,
+            lzvn_size_mismatch,
+            state_out_of_range,
+            v1_freq_over_states,
+        ] {
+            let err = lzfse_decompress(&buf_compressed, &mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        // whatever single byte is off, decoding does not panic
+        for pos in 0..buf_compressed.len() {
+            for bit in 0..8 {
+                let mut corrupt = buf_compressed.clone();
+                corrupt[pos] ^= 1 << bit;
+                let _ = lzfse_decompress(&corrupt, &mut buf_decompressed);
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+const MATCH_BITS: u32 = 6;
+const MATCH_MIN: usize = 3;
+const OFFSET_MASK: usize = (1 << (16 - MATCH_BITS)) - 1;
+
+/// goal is to match `lzjb_decompress` from ZFS:
+/// https://github.com/openzfs/zfs/blob/master/module/zfs/lzjb.c
+///
+/// Same as in ZFS, `buf_decompressed` is always filled completely, since its size is the logical
+/// size of the block, and the compressed buffer may be padded past the end of compressed data.
+pub fn lzjb_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let mut copy_map = 0_u8;
+    let mut copy_mask = 0_u8;
+
+    while out_pos < buf_decompressed.len() {
+        // each copy map byte describes the next 8 items, starting from the lowest bit
+        if copy_mask == 0 {
+            copy_mask = 1;
+            copy_map = *buf_compressed.get(in_pos).ok_or_else(truncated)?;
+            in_pos += 1;
+        }
+
+        if copy_map & copy_mask == 0 {
+            // literal byte
+            buf_decompressed[out_pos] = *buf_compressed.get(in_pos).ok_or_else(truncated)?;
+            in_pos += 1;
+            out_pos += 1;
+        } else {
+            // back reference, 6 bits of length and 10 bits of offset
+            if buf_compressed.len() - in_pos < 2 {
+                return Err(truncated());
+            }
+            let run_len = (buf_compressed[in_pos] >> (8 - MATCH_BITS)) as usize + MATCH_MIN;
+            let ref_offset = (((buf_compressed[in_pos] as usize) << 8)
+                | buf_compressed[in_pos + 1] as usize)
+                & OFFSET_MASK;
+            in_pos += 2;
+
+            if ref_offset == 0 || ref_offset > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzjb back reference points outside of output",
+                ));
+            }
+            // same as ZFS, a match running past the end of output is cut
+            let run_len = run_len.min(buf_decompressed.len() - out_pos);
+
+            copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+            out_pos += run_len;
+        }
+
+        copy_mask <<= 1;
+    }
+
+    Ok(out_pos)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzjb compressed data is truncated")
+}
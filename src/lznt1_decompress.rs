@@ -0,0 +1,244 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// every chunk takes at least 3 bytes and expands into at most 4096 bytes of output, padding
+// included
+#[cfg(feature = "std")]
+pub(crate) const LZNT1_MAX_EXPANSION: usize = 1366;
+
+const CHUNK_SIZE: usize = 4096;
+const CHUNK_SIGNATURE: u16 = 0x3000;
+const CHUNK_SIGNATURE_MASK: u16 = 0x7000;
+const CHUNK_COMPRESSED_FLAG: u16 = 0x8000;
+const CHUNK_SIZE_MASK: u16 = 0x0FFF;
+
+/// goal is to match `RtlDecompressBuffer` with `COMPRESSION_FORMAT_LZNT1`, as described in
+/// https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-xca/
+///
+/// Input is a sequence of chunks, each with a 2-byte header, ending at the end of input or at a
+/// zero header. Chunks that decompress to less than 4KB and are followed by another chunk are
+/// padded with zeros, same as NTFS does.
+pub fn lznt1_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+
+    while buf_compressed.len() - in_pos >= 2 {
+        let header = u16::from_le_bytes([buf_compressed[in_pos], buf_compressed[in_pos + 1]]);
+        if header == 0 {
+            break;
+        }
+        in_pos += 2;
+
+        if header & CHUNK_SIGNATURE_MASK != CHUNK_SIGNATURE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "wrong lznt1 chunk signature",
+            ));
+        }
+
+        let chunk_len = (header & CHUNK_SIZE_MASK) as usize + 1;
+        if buf_compressed.len() - in_pos < chunk_len {
+            return Err(truncated());
+        }
+        let chunk = &buf_compressed[in_pos..in_pos + chunk_len];
+        in_pos += chunk_len;
+
+        let chunk_out_len = if header & CHUNK_COMPRESSED_FLAG != 0 {
+            let buf_decompressed_capped = if buf_decompressed.len() - out_pos > CHUNK_SIZE {
+                &mut buf_decompressed[..out_pos + CHUNK_SIZE]
+            } else {
+                &mut buf_decompressed[..]
+            };
+            decompress_chunk(chunk, buf_decompressed_capped, out_pos)?
+        } else {
+            if buf_decompressed.len() - out_pos < chunk_len {
+                return Err(output_overflow());
+            }
+            buf_decompressed[out_pos..out_pos + chunk_len].copy_from_slice(chunk);
+            chunk_len
+        };
+        out_pos += chunk_out_len;
+
+        let has_next_chunk =
+            buf_compressed.len() - in_pos >= 2 && buf_compressed[in_pos..in_pos + 2] != [0, 0];
+        if has_next_chunk && chunk_out_len < CHUNK_SIZE {
+            let padding = CHUNK_SIZE - chunk_out_len;
+            if buf_decompressed.len() - out_pos < padding {
+                return Err(output_overflow());
+            }
+            buf_decompressed[out_pos..out_pos + padding].fill(0);
+            out_pos += padding;
+        }
+    }
+
+    Ok(out_pos)
+}
+
+// decompresses a single chunk into `buf_decompressed` starting at `chunk_start`,
+// returns decompressed length of the chunk
+fn decompress_chunk(
+    chunk: &[u8],
+    buf_decompressed: &mut [u8],
+    chunk_start: usize,
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = chunk_start;
+
+    while in_pos < chunk.len() {
+        let flags = chunk[in_pos];
+        in_pos += 1;
+
+        for bit in 0..8 {
+            if in_pos >= chunk.len() {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                // literal
+                if out_pos >= buf_decompressed.len() {
+                    return Err(output_overflow());
+                }
+                buf_decompressed[out_pos] = chunk[in_pos];
+                in_pos += 1;
+                out_pos += 1;
+            } else {
+                // back reference, split between offset and length depends on position in chunk
+                if chunk.len() - in_pos < 2 {
+                    return Err(truncated());
+                }
+                let token = u16::from_le_bytes([chunk[in_pos], chunk[in_pos + 1]]) as usize;
+                in_pos += 2;
+
+                let chunk_pos = out_pos - chunk_start;
+                if chunk_pos == 0 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lznt1 back reference at start of chunk",
+                    ));
+                }
+                let mut length_bits = 12;
+                let mut i = chunk_pos - 1;
+                while i >= 0x10 {
+                    length_bits -= 1;
+                    i >>= 1;
+                }
+
+                let ref_offset = (token >> length_bits) + 1;
+                let run_len = (token & ((1 << length_bits) - 1)) + 3;
+                if ref_offset > chunk_pos {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lznt1 back reference points before start of chunk",
+                    ));
+                }
+                if buf_decompressed.len() - out_pos < run_len {
+                    return Err(output_overflow());
+                }
+
+                copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+                out_pos += run_len;
+            }
+        }
+    }
+
+    Ok(out_pos - chunk_start)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lznt1 compressed data is truncated")
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lznt1 decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two compressed chunks, the first one full and with back references of various offsets
+    // and lengths, the second one with a back reference overlapping its output
+    const STREAM: &[u8; 38] = b"\x14\xb0\xd0abcd\x9c?e?\x00?\x00\x07?\x00?\x00?\x00ce\
+        \x0c\xb0\x00the end,\x02 \x04\x80";
+
+    const DATA_LEN: usize = CHUNK_SIZE + 16;
+
+    // "abcd" 1000 times, "abce" 24 times, then "the end, the end"
+    fn data() -> [u8; DATA_LEN] {
+        let mut data = [0; DATA_LEN];
+        for (i, byte) in data[..CHUNK_SIZE].iter_mut().enumerate() {
+            *byte = if i >= 4000 && i % 4 == 3 {
+                b'e'
+            } else {
+                b"abcd"[i % 4]
+            };
+        }
+        data[CHUNK_SIZE..].copy_from_slice(b"the end, the end");
+        data
+    }
+
+    #[test]
+    fn chunks_are_decompressed() {
+        let data = data();
+        let mut buf_decompressed = [0; DATA_LEN + 10];
+        let len = lznt1_decompress(STREAM, &mut buf_decompressed).unwrap();
+        assert_eq!(buf_decompressed[..len], data);
+
+        // a zero header ends the stream
+        let mut buf_compressed = [0; 45];
+        buf_compressed[..38].copy_from_slice(STREAM);
+        buf_compressed[40..].copy_from_slice(b"trail");
+        let len = lznt1_decompress(&buf_compressed, &mut buf_decompressed).unwrap();
+        assert_eq!(buf_decompressed[..len], data);
+    }
+
+    #[test]
+    fn short_chunks_followed_by_another_are_padded() {
+        // an uncompressed chunk and a compressed one
+        let buf_compressed = b"\x02\x30abc\x03\xb0\x00wxy";
+        let mut buf_decompressed = [0xFF; CHUNK_SIZE + 10];
+        let len = lznt1_decompress(buf_compressed, &mut buf_decompressed).unwrap();
+        assert_eq!(len, CHUNK_SIZE + 3);
+        assert_eq!(buf_decompressed[..3], *b"abc");
+        assert!(buf_decompressed[3..CHUNK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(buf_decompressed[CHUNK_SIZE..len], *b"wxy");
+    }
+
+    #[test]
+    fn malformed_streams_are_errors() {
+        let mut buf_decompressed = [0; DATA_LEN];
+
+        let err = lznt1_decompress(&STREAM[..STREAM.len() - 1], &mut buf_decompressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = lznt1_decompress(STREAM, &mut buf_decompressed[..DATA_LEN - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let invalid: [&[u8]; 3] = [
+            // wrong signature
+            b"\x02\x20abc",
+            // back reference at start of chunk, and before it
+            b"\x02\xb0\x01\x00\x00",
+            b"\x03\xb0\x02a\x00\x10",
+        ];
+        for buf_compressed in invalid {
+            let err = lznt1_decompress(buf_compressed, &mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{buf_compressed:?}");
+        }
+
+        // no single bit off makes decompression panic
+        for pos in 0..STREAM.len() {
+            for bit in 0..8 {
+                let mut corrupt = *STREAM;
+                corrupt[pos] ^= 1 << bit;
+                let _ = lznt1_decompress(&corrupt, &mut buf_decompressed);
+            }
+        }
+    }
+}
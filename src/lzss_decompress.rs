@@ -0,0 +1,363 @@
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// LZSS as popularized by Haruhiko Okumura's lzss.c, and its many descendants in firmware images
+// and game archives. Items are grouped by 8 behind a flag byte telling literals from matches,
+// the variants differ in flag bit order, match encoding and the window matches refer to.
+
+pub const NINTENDO_LZ10_TYPE: u8 = 0x10;
+pub const NINTENDO_LZ11_TYPE: u8 = 0x11;
+pub const YAZ0_MAGIC: &[u8; 4] = b"Yaz0";
+
+// type and 24-bit little-endian size
+#[cfg(feature = "alloc")]
+const NINTENDO_LZ_HEADER_SIZE: usize = 4;
+// magic, 32-bit big-endian size and 8 reserved bytes
+#[cfg(feature = "alloc")]
+const YAZ0_HEADER_SIZE: usize = 16;
+
+/// Order in which the bits of a flag byte are consumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagBitOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+/// Encoding of a match, all of them store a length and where to copy from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchFormat {
+    /// 2 bytes: 12-bit absolute ring buffer position made of the first byte and the high nibble
+    /// of the second one, and length - 3 in its low nibble. Needs a `RingBuffer`.
+    Okumura,
+    /// 2 bytes: length - 3 in the high nibble, then 12-bit distance - 1, big-endian.
+    Lz10,
+    /// 2 to 4 bytes: like `Lz10` but with length - 1 in the high nibble, which when it is 0 or 1
+    /// instead marks an 8-bit (length - 17) or 16-bit (length - 273) length before the distance.
+    Lz11,
+    /// 2 or 3 bytes: length - 2 in the high nibble, then 12-bit distance - 1, big-endian. Zero
+    /// high nibble means a third byte holds length - 18.
+    Yaz0,
+}
+
+/// Window that is there before any output is produced, as a ring buffer of `size` bytes
+/// (a power of two) written from `start` on. Bytes before `start` are filled with `fill` and the
+/// rest are zero. Without one, matches can only refer to bytes already output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingBuffer {
+    pub size: usize,
+    pub fill: u8,
+    pub start: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzssParams {
+    pub flag_bit_order: FlagBitOrder,
+    /// Whether a set flag bit marks a literal, rather than a match.
+    pub literal_when_set: bool,
+    pub match_format: MatchFormat,
+    pub ring_buffer: Option<RingBuffer>,
+}
+
+impl LzssParams {
+    /// lzss.c by Haruhiko Okumura: 4KB ring buffer written from 4078, with spaces before that
+    /// and zeros, as `text_buf` is static, after it.
+    pub const OKUMURA: LzssParams = LzssParams {
+        flag_bit_order: FlagBitOrder::LsbFirst,
+        literal_when_set: true,
+        match_format: MatchFormat::Okumura,
+        ring_buffer: Some(RingBuffer {
+            size: 4096,
+            fill: b' ',
+            start: 4096 - 18,
+        }),
+    };
+
+    /// Nintendo LZ10, as decoded by the GBA and DS BIOS, without its 4 byte header.
+    pub const NINTENDO_LZ10: LzssParams = LzssParams {
+        flag_bit_order: FlagBitOrder::MsbFirst,
+        literal_when_set: false,
+        match_format: MatchFormat::Lz10,
+        ring_buffer: None,
+    };
+
+    /// Nintendo LZ11 of DS games, without its 4 byte header.
+    pub const NINTENDO_LZ11: LzssParams = LzssParams {
+        flag_bit_order: FlagBitOrder::MsbFirst,
+        literal_when_set: false,
+        match_format: MatchFormat::Lz11,
+        ring_buffer: None,
+    };
+
+    /// Yaz0 of GameCube, Wii and Switch games, without its 16 byte header.
+    pub const YAZ0: LzssParams = LzssParams {
+        flag_bit_order: FlagBitOrder::MsbFirst,
+        literal_when_set: true,
+        match_format: MatchFormat::Yaz0,
+        ring_buffer: None,
+    };
+}
+
+/// Decoding stops once `buf_decompressed` is full, or when input ends between two items.
+/// The number of decompressed bytes is returned.
+pub fn lzss_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+    params: &LzssParams,
+) -> error::Result<usize> {
+    if let Some(ring_buffer) = params.ring_buffer {
+        if !ring_buffer.size.is_power_of_two() || ring_buffer.start >= ring_buffer.size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "lzss ring buffer size must be a power of two larger than its start",
+            ));
+        }
+    }
+
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let mut flags = 0_u8;
+    let mut flag_count = 0;
+
+    while out_pos < buf_decompressed.len() {
+        if flag_count == 0 {
+            match buf_compressed.get(in_pos) {
+                Some(&b) => flags = b,
+                None => break,
+            }
+            in_pos += 1;
+            flag_count = 8;
+        }
+        let flag_set = match params.flag_bit_order {
+            FlagBitOrder::LsbFirst => {
+                let bit = flags & 0x01;
+                flags >>= 1;
+                bit != 0
+            }
+            FlagBitOrder::MsbFirst => {
+                let bit = flags & 0x80;
+                flags <<= 1;
+                bit != 0
+            }
+        };
+        flag_count -= 1;
+
+        if in_pos == buf_compressed.len() {
+            break;
+        }
+
+        if flag_set == params.literal_when_set {
+            // literal
+            buf_decompressed[out_pos] = buf_compressed[in_pos];
+            in_pos += 1;
+            out_pos += 1;
+            continue;
+        }
+
+        // back reference
+        let b0 = read_u8(buf_compressed, &mut in_pos)? as usize;
+        let b1 = read_u8(buf_compressed, &mut in_pos)? as usize;
+        let (ref_offset, run_len) = match params.match_format {
+            MatchFormat::Okumura => {
+                let ring_buffer = params.ring_buffer.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        "lzss okumura matches need a ring buffer",
+                    )
+                })?;
+                // distance from where the next byte goes in the ring buffer, a position equal
+                // to it refers to the oldest byte there
+                let ref_pos = b0 | ((b1 & 0xF0) << 4);
+                let write_pos = (ring_buffer.start + out_pos) & (ring_buffer.size - 1);
+                let ref_offset = write_pos.wrapping_sub(ref_pos) & (ring_buffer.size - 1);
+                let ref_offset = if ref_offset == 0 {
+                    ring_buffer.size
+                } else {
+                    ref_offset
+                };
+                (ref_offset, (b1 & 0x0F) + 3)
+            }
+            MatchFormat::Lz10 => ((((b0 & 0x0F) << 8) | b1) + 1, (b0 >> 4) + 3),
+            MatchFormat::Lz11 => match b0 >> 4 {
+                0 => {
+                    let b2 = read_u8(buf_compressed, &mut in_pos)? as usize;
+                    let run_len = (((b0 & 0x0F) << 4) | (b1 >> 4)) + 0x11;
+                    ((((b1 & 0x0F) << 8) | b2) + 1, run_len)
+                }
+                1 => {
+                    let b2 = read_u8(buf_compressed, &mut in_pos)? as usize;
+                    let b3 = read_u8(buf_compressed, &mut in_pos)? as usize;
+                    let run_len = (((b0 & 0x0F) << 12) | (b1 << 4) | (b2 >> 4)) + 0x111;
+                    ((((b2 & 0x0F) << 8) | b3) + 1, run_len)
+                }
+                n => ((((b0 & 0x0F) << 8) | b1) + 1, n + 1),
+            },
+            MatchFormat::Yaz0 => {
+                let run_len = match b0 >> 4 {
+                    0 => read_u8(buf_compressed, &mut in_pos)? as usize + 0x12,
+                    n => n + 2,
+                };
+                ((((b0 & 0x0F) << 8) | b1) + 1, run_len)
+            }
+        };
+
+        if buf_decompressed.len() - out_pos < run_len {
+            return Err(output_overflow());
+        }
+
+        // part of the match preceding the output comes from the initial ring buffer contents
+        let mut fill_len = 0;
+        if ref_offset > out_pos {
+            match params.ring_buffer {
+                Some(ring_buffer) if ref_offset <= ring_buffer.size => {
+                    fill_len = run_len.min(ref_offset - out_pos);
+                    let buf_fill = &mut buf_decompressed[out_pos..out_pos + fill_len];
+                    for (i, byte) in buf_fill.iter_mut().enumerate() {
+                        let ring_pos = (ring_buffer.start + ring_buffer.size + out_pos + i
+                            - ref_offset)
+                            & (ring_buffer.size - 1);
+                        *byte = if ring_pos < ring_buffer.start {
+                            ring_buffer.fill
+                        } else {
+                            0
+                        };
+                    }
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lzss back reference points before start of output",
+                    ))
+                }
+            }
+        }
+        if fill_len < run_len {
+            copy_back_reference(
+                buf_decompressed,
+                out_pos + fill_len,
+                ref_offset,
+                run_len - fill_len,
+            );
+        }
+        out_pos += run_len;
+    }
+
+    Ok(out_pos)
+}
+
+/// Decompresses Nintendo LZ10 or LZ11 data, including the header with type and size.
+#[cfg(feature = "alloc")]
+pub fn nintendo_lz_decompress(buf_compressed: &[u8]) -> error::Result<Vec<u8>> {
+    let header = buf_compressed
+        .get(..NINTENDO_LZ_HEADER_SIZE)
+        .ok_or_else(truncated)?;
+    let (params, max_expansion) = match header[0] {
+        // 2 bytes and a flag bit expand into up to 18 bytes
+        NINTENDO_LZ10_TYPE => (LzssParams::NINTENDO_LZ10, 9),
+        // 4 bytes and a flag bit expand into up to 65808 bytes
+        NINTENDO_LZ11_TYPE => (LzssParams::NINTENDO_LZ11, 16452),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "unknown nintendo lz compression type",
+            ))
+        }
+    };
+
+    let mut data_pos = NINTENDO_LZ_HEADER_SIZE;
+    let mut decompressed_length = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+    if decompressed_length == 0 && header[0] == NINTENDO_LZ11_TYPE {
+        // sizes from 16MB on follow the header as a 32-bit value
+        let bytes = buf_compressed
+            .get(data_pos..data_pos + 4)
+            .ok_or_else(truncated)?;
+        decompressed_length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        data_pos += 4;
+    }
+
+    decompress_with_length(
+        &buf_compressed[data_pos..],
+        decompressed_length,
+        max_expansion,
+        &params,
+    )
+}
+
+/// Decompresses a whole Yaz0 file, header included.
+#[cfg(feature = "alloc")]
+pub fn yaz0_decompress(buf_compressed: &[u8]) -> error::Result<Vec<u8>> {
+    let header = buf_compressed
+        .get(..YAZ0_HEADER_SIZE)
+        .ok_or_else(truncated)?;
+    if &header[..4] != YAZ0_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "wrong yaz0 magic"));
+    }
+    let decompressed_length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+
+    // 3 bytes and a flag bit expand into up to 273 bytes
+    decompress_with_length(
+        &buf_compressed[YAZ0_HEADER_SIZE..],
+        decompressed_length as usize,
+        91,
+        &LzssParams::YAZ0,
+    )
+}
+
+// decompresses data of a format whose header records the decompressed length
+#[cfg(feature = "alloc")]
+fn decompress_with_length(
+    buf_compressed: &[u8],
+    decompressed_length: usize,
+    max_expansion: usize,
+    params: &LzssParams,
+) -> error::Result<Vec<u8>> {
+    // check before allocating, so that a corrupt size can't make us allocate up to 4GB
+    if decompressed_length / max_expansion > buf_compressed.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "lzss decompressed size is too large for compressed data",
+        ));
+    }
+
+    let mut buf_decompressed = vec![0; decompressed_length];
+    if lzss_decompress(buf_compressed, &mut buf_decompressed, params)? != decompressed_length {
+        return Err(truncated());
+    }
+    Ok(buf_decompressed)
+}
+
+fn read_u8(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u8> {
+    let byte = *buf_compressed.get(*in_pos).ok_or_else(truncated)?;
+    *in_pos += 1;
+    Ok(byte)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzss compressed data is truncated")
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzss decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn okumura_window_is_zero_after_spaces() {
+        // match of 4 bytes at 4094, wrapping around to the start of the window, then a literal
+        let buf_compressed = [0b10, 0xFE, 0xF1, b'x'];
+        let mut buf_decompressed = [0xAA; 5];
+        let len =
+            lzss_decompress(&buf_compressed, &mut buf_decompressed, &LzssParams::OKUMURA).unwrap();
+        assert_eq!(len, 5);
+        assert_eq!(&buf_decompressed, b"\0\0  x");
+    }
+}
@@ -0,0 +1,178 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+/// goal is to match `lzvn_decode` from https://github.com/lzfse/lzfse/blob/master/src/lzvn_decode_base.c
+///
+/// Decoding stops at the end of stream opcode or when input is exhausted, whichever comes
+/// first, and the number of decompressed bytes is returned.
+pub fn lzvn_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> error::Result<usize> {
+    lzvn_decode(buf_compressed, buf_decompressed, 0)
+}
+
+/// Same as `lzvn_decompress`, but starts writing at `out_pos`, allowing matches to reference
+/// anything already in `buf_decompressed`. Returns the position after the last written byte.
+pub(crate) fn lzvn_decode(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+    mut out_pos: usize,
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut prev_distance = 0;
+
+    while in_pos < buf_compressed.len() {
+        let opc = buf_compressed[in_pos];
+
+        // literal count, match length and match distance of the opcode,
+        // distance of 0 means that the opcode has no match
+        let (opc_len, literal_len, run_len, distance) = match opc {
+            // end of stream, followed by 7 bytes of padding
+            0x06 => return Ok(out_pos),
+            // nop
+            0x0E | 0x16 => (1, 0, 0, 0),
+            // undefined
+            0x1E | 0x26 | 0x2E | 0x36 | 0x3E | 0x70..=0x7F | 0xD0..=0xDF => {
+                return Err(Error::new(ErrorKind::InvalidData, "undefined lzvn opcode"))
+            }
+            // medium distance: 101LLMMM DDDDDDMM DDDDDDDD
+            0xA0..=0xBF => {
+                let opc23 = read_u16_le(buf_compressed, in_pos + 1)? as usize;
+                let literal_len = ((opc >> 3) & 0x03) as usize;
+                let run_len = ((((opc & 0x07) as usize) << 2) | (opc23 & 0x03)) + 3;
+                (3, literal_len, run_len, opc23 >> 2)
+            }
+            // large literal: 11100000 LLLLLLLL
+            0xE0 => (2, read_u8(buf_compressed, in_pos + 1)? as usize + 16, 0, 0),
+            // small literal: 1110LLLL
+            0xE1..=0xEF => (1, (opc & 0x0F) as usize, 0, 0),
+            // large match: 11110000 MMMMMMMM
+            0xF0 => {
+                let run_len = read_u8(buf_compressed, in_pos + 1)? as usize + 16;
+                (2, 0, run_len, prev_distance)
+            }
+            // small match: 1111MMMM
+            0xF1..=0xFF => (1, 0, (opc & 0x0F) as usize, prev_distance),
+            _ => {
+                let literal_len = (opc >> 6) as usize;
+                let run_len = ((opc >> 3) & 0x07) as usize + 3;
+                match opc & 0x07 {
+                    // previous distance: LLMMM110
+                    6 => (1, literal_len, run_len, prev_distance),
+                    // large distance: LLMMM111 DDDDDDDD DDDDDDDD
+                    7 => {
+                        let distance = read_u16_le(buf_compressed, in_pos + 1)? as usize;
+                        (3, literal_len, run_len, distance)
+                    }
+                    // small distance: LLMMMDDD DDDDDDDD
+                    _ => {
+                        let distance = (((opc & 0x07) as usize) << 8)
+                            | read_u8(buf_compressed, in_pos + 1)? as usize;
+                        (2, literal_len, run_len, distance)
+                    }
+                }
+            }
+        };
+        in_pos += opc_len;
+
+        if literal_len > 0 {
+            if buf_compressed.len() - in_pos < literal_len {
+                return Err(truncated());
+            }
+            if buf_decompressed.len() - out_pos < literal_len {
+                return Err(output_overflow());
+            }
+            buf_decompressed[out_pos..out_pos + literal_len]
+                .copy_from_slice(&buf_compressed[in_pos..in_pos + literal_len]);
+            in_pos += literal_len;
+            out_pos += literal_len;
+        }
+
+        if run_len > 0 {
+            if distance == 0 || distance > out_pos {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzvn match distance points outside of output",
+                ));
+            }
+            if buf_decompressed.len() - out_pos < run_len {
+                return Err(output_overflow());
+            }
+            copy_back_reference(buf_decompressed, out_pos, distance, run_len);
+            out_pos += run_len;
+            prev_distance = distance;
+        }
+    }
+
+    Ok(out_pos)
+}
+
+fn read_u8(buf_compressed: &[u8], in_pos: usize) -> error::Result<u8> {
+    buf_compressed.get(in_pos).copied().ok_or_else(truncated)
+}
+
+fn read_u16_le(buf_compressed: &[u8], in_pos: usize) -> error::Result<u16> {
+    Ok(
+        read_u8(buf_compressed, in_pos)? as u16
+            | (read_u8(buf_compressed, in_pos + 1)? as u16) << 8,
+    )
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzvn compressed data is truncated")
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzvn decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // large literal, small distance match, nop, previous distance match with a literal, large
+    // and small matches at previous distance, medium distance match, end of stream
+    const STREAM: &[u8] = b"\xe0\x04abcdefghijklmnopqrst\x10\x14\x0e\x46x\xf0\x01\xf3\xa0\x65\x00\
+        \x06\x00\x00\x00\x00\x00\x00\x00";
+    const DATA: &[u8] = b"abcdefghijklmnopqrstabcdexghijklmnopqrstabcdexghiexgh";
+
+    #[test]
+    fn opcodes_are_decoded() {
+        let mut buf_decompressed = [0_u8; 64];
+        let len = lzvn_decompress(STREAM, &mut buf_decompressed).unwrap();
+        assert_eq!(&buf_decompressed[..len], DATA);
+
+        // input may end without end of stream opcode
+        let len = lzvn_decompress(&STREAM[..STREAM.len() - 8], &mut buf_decompressed).unwrap();
+        assert_eq!(&buf_decompressed[..len], DATA);
+    }
+
+    #[test]
+    fn malformed_streams_are_errors() {
+        let mut buf_decompressed = [0_u8; 64];
+        let invalid: [&[u8]; 5] = [
+            // cut within the literal, within the medium distance opcode
+            &STREAM[..10],
+            &STREAM[..STREAM.len() - 9],
+            // undefined opcode
+            b"\xe3abc\x1e",
+            // match reaching before start of output, match with no distance yet
+            b"\xe3abc\x08\x04",
+            b"\xe3abc\xf3",
+        ];
+        for buf_compressed in invalid {
+            let err = lzvn_decompress(buf_compressed, &mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{buf_compressed:?}");
+        }
+
+        let err = lzvn_decompress(STREAM, &mut buf_decompressed[..DATA.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // no prefix of a stream makes decoding panic
+        for len in 0..STREAM.len() {
+            let _ = lzvn_decompress(&STREAM[..len], &mut buf_decompressed);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+use crate::huffman::Huffman;
+
+const MAX_CODE_LENGTH: usize = 16;
+const MIN_MATCH: usize = 2;
+const NUM_CHARS: usize = 256;
+const NUM_PRIMARY_LENGTHS: usize = 7;
+const NUM_SECONDARY_LENGTHS: usize = 249;
+const PRETREE_NUM_ELEMENTS: usize = 20;
+const ALIGNED_NUM_ELEMENTS: usize = 8;
+const MAX_POSITION_SLOTS: usize = 50;
+const MAINTREE_MAX_SYMBOLS: usize = NUM_CHARS + MAX_POSITION_SLOTS * 8;
+// runs of code lengths may overshoot the end of a table, same as in lzxd
+const LENGTH_TABLE_SAFETY: usize = 64;
+
+const BLOCKTYPE_VERBATIM: u32 = 1;
+const BLOCKTYPE_ALIGNED: u32 = 2;
+const BLOCKTYPE_UNCOMPRESSED: u32 = 3;
+
+const FRAME_SIZE: usize = 32768;
+// e8 translation is only applied to the first 1GB of output
+const E8_MAX_FRAMES: usize = 32768;
+// e8 translation leaves last 10 bytes of every frame alone
+const E8_FRAME_TAIL: usize = 10;
+
+const EXTRA_BITS: [u8; 51] = make_extra_bits();
+const POSITION_BASE: [u32; 51] = make_position_base();
+
+const fn make_extra_bits() -> [u8; 51] {
+    let mut extra_bits = [0; 51];
+    let mut i = 0;
+    let mut j = 0;
+    while i < 51 {
+        extra_bits[i] = j;
+        if i + 1 < 51 {
+            extra_bits[i + 1] = j;
+        }
+        if i != 0 && j < 17 {
+            j += 1;
+        }
+        i += 2;
+    }
+    extra_bits
+}
+
+const fn make_position_base() -> [u32; 51] {
+    let mut position_base = [0; 51];
+    let mut i = 0;
+    let mut j = 0;
+    while i < 51 {
+        position_base[i] = j;
+        j += 1 << EXTRA_BITS[i];
+        i += 1;
+    }
+    position_base
+}
+
+/// goal is to match `lzxd_decompress` from https://github.com/kyz/libmspack/blob/master/libmspack/mspack/lzxd.c
+/// as used for LZX compressed CAB folders, without reset intervals or LZX DELTA reference data.
+///
+/// `window_bits` is between 15 and 21. The stream is decoded in 32KB frames until
+/// `buf_decompressed` is full, and its size is returned. Output is E8 translated if the stream
+/// asks for it.
+pub fn lzx_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+    window_bits: u32,
+) -> error::Result<usize> {
+    let position_slots = match window_bits {
+        15..=19 => (window_bits << 1) as usize,
+        20 => 42,
+        21 => 50,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "lzx window bits must be between 15 and 21",
+            ))
+        }
+    };
+    let main_symbols = NUM_CHARS + position_slots * 8;
+
+    let mut bits = BitReader::new(buf_compressed);
+    let mut main_lengths = [0_u8; MAINTREE_MAX_SYMBOLS + LENGTH_TABLE_SAFETY];
+    let mut length_lengths = [0_u8; NUM_SECONDARY_LENGTHS + LENGTH_TABLE_SAFETY];
+    let mut main_code = None;
+    let mut length_code = None;
+    let mut aligned_code = None;
+
+    let mut block_type = 0;
+    let mut block_length = 0;
+    let mut block_remaining = 0;
+    let mut r = [1_usize; 3];
+    let mut intel_started = false;
+    let mut first_e8_frame = None;
+
+    // stream header: whether e8 translation is used and with what file size
+    let intel_filesize = if bits.read(1)? == 1 {
+        let high = bits.read(16)?;
+        let low = bits.read(16)?;
+        (high << 16) | low
+    } else {
+        0
+    };
+
+    let out_len = buf_decompressed.len();
+    let mut out_pos = 0;
+    let mut frame = 0;
+
+    while frame * FRAME_SIZE < out_len {
+        let frame_end = out_len.min((frame + 1) * FRAME_SIZE);
+
+        while out_pos < frame_end {
+            if block_remaining == 0 {
+                // uncompressed blocks of odd length are followed by a padding byte
+                if block_type == BLOCKTYPE_UNCOMPRESSED && block_length & 1 == 1 {
+                    bits.skip_byte();
+                }
+
+                block_type = bits.read(3)?;
+                let high = bits.read(16)? as usize;
+                let low = bits.read(8)? as usize;
+                block_length = (high << 8) | low;
+                block_remaining = block_length;
+
+                match block_type {
+                    BLOCKTYPE_VERBATIM | BLOCKTYPE_ALIGNED => {
+                        if block_type == BLOCKTYPE_ALIGNED {
+                            let mut aligned_lengths = [0_u8; ALIGNED_NUM_ELEMENTS];
+                            for length in aligned_lengths.iter_mut() {
+                                *length = bits.read(3)? as u8;
+                            }
+                            aligned_code = Some(Huffman::new(&aligned_lengths, MAX_CODE_LENGTH)?);
+                        }
+
+                        read_lengths(&mut bits, &mut main_lengths, 0, NUM_CHARS)?;
+                        read_lengths(&mut bits, &mut main_lengths, NUM_CHARS, main_symbols)?;
+                        main_code = Some(Huffman::new(
+                            &main_lengths[..main_symbols],
+                            MAX_CODE_LENGTH,
+                        )?);
+                        if main_lengths[0xE8] != 0 {
+                            intel_started = true;
+                        }
+
+                        read_lengths(&mut bits, &mut length_lengths, 0, NUM_SECONDARY_LENGTHS)?;
+                        let length_lengths = &length_lengths[..NUM_SECONDARY_LENGTHS];
+                        // length tree is allowed to be empty if there are no long matches
+                        length_code = if length_lengths.iter().all(|&length| length == 0) {
+                            None
+                        } else {
+                            Some(Huffman::new(length_lengths, MAX_CODE_LENGTH)?)
+                        };
+                    }
+                    BLOCKTYPE_UNCOMPRESSED => {
+                        // because we can't assume otherwise
+                        intel_started = true;
+                        bits.align_for_uncompressed()?;
+                        let header = bits.read_bytes(12)?;
+                        for (i, r) in r.iter_mut().enumerate() {
+                            let bytes = &header[i * 4..i * 4 + 4];
+                            *r = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                                as usize;
+                        }
+                    }
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "invalid lzx block type")),
+                }
+            }
+
+            // decode up to the end of block or frame, whichever comes first,
+            // the last match is allowed to run into the next frame
+            let this_run = block_remaining.min(frame_end - out_pos);
+            block_remaining -= this_run;
+            let run_end = out_pos + this_run;
+
+            if block_type == BLOCKTYPE_UNCOMPRESSED {
+                let data = bits.read_bytes(this_run)?;
+                buf_decompressed[out_pos..run_end].copy_from_slice(data);
+                out_pos = run_end;
+                continue;
+            }
+
+            let main_code = main_code.as_ref().ok_or_else(no_tree)?;
+            while out_pos < run_end {
+                let main_element = bits.decode(main_code)? as usize;
+                if main_element < NUM_CHARS {
+                    buf_decompressed[out_pos] = main_element as u8;
+                    out_pos += 1;
+                    continue;
+                }
+
+                let main_element = main_element - NUM_CHARS;
+                let mut run_len = main_element & NUM_PRIMARY_LENGTHS;
+                if run_len == NUM_PRIMARY_LENGTHS {
+                    let length_code = length_code.as_ref().ok_or_else(no_tree)?;
+                    run_len += bits.decode(length_code)? as usize;
+                }
+                run_len += MIN_MATCH;
+
+                let slot = main_element >> 3;
+                let ref_offset = match slot {
+                    0 => r[0],
+                    1 => {
+                        r.swap(0, 1);
+                        r[0]
+                    }
+                    2 => {
+                        r.swap(0, 2);
+                        r[0]
+                    }
+                    _ => {
+                        let extra = EXTRA_BITS[slot] as u32;
+                        let base = POSITION_BASE[slot] as usize - 2;
+                        let ref_offset = if block_type == BLOCKTYPE_ALIGNED && extra >= 3 {
+                            let aligned_code = aligned_code.as_ref().ok_or_else(no_tree)?;
+                            let verbatim_bits = bits.read(extra - 3)? as usize;
+                            let aligned_bits = bits.decode(aligned_code)? as usize;
+                            base + (verbatim_bits << 3) + aligned_bits
+                        } else if extra > 0 {
+                            base + bits.read(extra)? as usize
+                        } else {
+                            1
+                        };
+                        r[2] = r[1];
+                        r[1] = r[0];
+                        r[0] = ref_offset;
+                        ref_offset
+                    }
+                };
+
+                if ref_offset > out_pos {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lzx back reference points before start of output",
+                    ));
+                }
+                if out_len - out_pos < run_len {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "lzx decompressed data does not fit into output buffer",
+                    ));
+                }
+                copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+                out_pos += run_len;
+            }
+
+            // did the final match overrun our desired run?
+            let overrun = out_pos - run_end;
+            if overrun > block_remaining {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "lzx match runs past the end of block",
+                ));
+            }
+            block_remaining -= overrun;
+        }
+
+        if intel_started && first_e8_frame.is_none() {
+            first_e8_frame = Some(frame);
+        }
+        bits.align_to_word()?;
+        frame += 1;
+    }
+
+    // translation only touches output, matches have to see untranslated data,
+    // so it is done once everything is decoded
+    if let Some(first_e8_frame) = first_e8_frame.filter(|_| intel_filesize != 0) {
+        for frame in first_e8_frame..frame.min(E8_MAX_FRAMES) {
+            let frame_start = frame * FRAME_SIZE;
+            let frame_end = out_len.min(frame_start + FRAME_SIZE);
+            if frame_end - frame_start > E8_FRAME_TAIL {
+                e8_translate(
+                    &mut buf_decompressed[frame_start..frame_end],
+                    frame_start as i32,
+                    intel_filesize as i32,
+                );
+            }
+        }
+    }
+
+    Ok(out_pos)
+}
+
+// reads code lengths for symbols `first..last`, coded as differences from their previous values
+fn read_lengths(
+    bits: &mut BitReader,
+    lengths: &mut [u8],
+    first: usize,
+    last: usize,
+) -> error::Result<()> {
+    let mut pretree_lengths = [0_u8; PRETREE_NUM_ELEMENTS];
+    for length in pretree_lengths.iter_mut() {
+        *length = bits.read(4)? as u8;
+    }
+    let pretree = Huffman::new(&pretree_lengths, MAX_CODE_LENGTH)?;
+
+    let mut x = first;
+    while x < last {
+        let (repeat, length) = match bits.decode(&pretree)? {
+            17 => (bits.read(4)? as usize + 4, 0),
+            18 => (bits.read(5)? as usize + 20, 0),
+            19 => {
+                let repeat = bits.read(1)? as usize + 4;
+                let delta = bits.decode(&pretree)?;
+                if delta > 16 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid lzx code length",
+                    ));
+                }
+                let previous = *lengths.get(x).ok_or_else(lengths_overrun)?;
+                (repeat, (previous + 17 - delta as u8) % 17)
+            }
+            delta => {
+                let previous = *lengths.get(x).ok_or_else(lengths_overrun)?;
+                (1, (previous + 17 - delta as u8) % 17)
+            }
+        };
+
+        if lengths.len() - x < repeat {
+            return Err(lengths_overrun());
+        }
+        lengths[x..x + repeat].fill(length);
+        x += repeat;
+    }
+
+    Ok(())
+}
+
+fn e8_translate(frame: &mut [u8], frame_offset: i32, intel_filesize: i32) {
+    let data_end = frame.len() - E8_FRAME_TAIL;
+    let mut current_pos = frame_offset;
+    let mut i = 0;
+
+    while i < data_end {
+        if frame[i] != 0xE8 {
+            i += 1;
+            current_pos += 1;
+            continue;
+        }
+        i += 1;
+
+        let abs_offset = i32::from_le_bytes([frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]);
+        if abs_offset >= -current_pos && abs_offset < intel_filesize {
+            let rel_offset = if abs_offset >= 0 {
+                abs_offset - current_pos
+            } else {
+                abs_offset + intel_filesize
+            };
+            frame[i..i + 4].copy_from_slice(&rel_offset.to_le_bytes());
+        }
+        i += 4;
+        current_pos += 5;
+    }
+}
+
+// bits come from little-endian 16-bit words, most significant bit first
+struct BitReader<'a> {
+    buf: &'a [u8],
+    in_pos: usize,
+    bit_buf: u64,
+    bits_left: u32,
+    // zero words fed past the end of input, decoding is allowed to peek a little past it
+    padding_words: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            buf,
+            in_pos: 0,
+            bit_buf: 0,
+            bits_left: 0,
+            padding_words: 0,
+        }
+    }
+
+    fn ensure(&mut self, n: u32) -> error::Result<()> {
+        while self.bits_left < n {
+            let word = match self.buf.get(self.in_pos..self.in_pos + 2) {
+                Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+                None if self.in_pos < self.buf.len() => self.buf[self.in_pos] as u16,
+                None => {
+                    self.padding_words += 1;
+                    if self.padding_words > 2 {
+                        return Err(truncated());
+                    }
+                    0
+                }
+            };
+            self.in_pos += 2;
+            self.bit_buf |= (word as u64) << (48 - self.bits_left);
+            self.bits_left += 16;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, n: u32) {
+        self.bit_buf <<= n;
+        self.bits_left -= n;
+    }
+
+    fn read(&mut self, n: u32) -> error::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.ensure(n)?;
+        let value = (self.bit_buf >> (64 - n)) as u32;
+        self.remove(n);
+        Ok(value)
+    }
+
+    fn decode(&mut self, code: &Huffman) -> error::Result<u16> {
+        code.decode(|| self.read(1))
+    }
+
+    // done at the end of every frame
+    fn align_to_word(&mut self) -> error::Result<()> {
+        if self.bits_left > 0 {
+            self.ensure(16)?;
+        }
+        self.remove(self.bits_left & 15);
+        Ok(())
+    }
+
+    // skips 1 to 16 bits to get to a word boundary, so that whole bytes can be read
+    fn align_for_uncompressed(&mut self) -> error::Result<()> {
+        self.ensure(16)?;
+        if self.bits_left > 16 {
+            self.in_pos -= 2;
+        }
+        self.bits_left = 0;
+        self.bit_buf = 0;
+        Ok(())
+    }
+
+    fn skip_byte(&mut self) {
+        self.in_pos += 1;
+    }
+
+    fn read_bytes(&mut self, n: usize) -> error::Result<&'a [u8]> {
+        let bytes = self
+            .buf
+            .get(self.in_pos..self.in_pos + n)
+            .ok_or_else(truncated)?;
+        self.in_pos += n;
+        Ok(bytes)
+    }
+}
+
+fn no_tree() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzx symbol needs a huffman tree the block does not have",
+    )
+}
+
+fn lengths_overrun() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "lzx code lengths run past the end of table",
+    )
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "lzx compressed data is truncated")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // e8 translation with a file size of 344, an uncompressed block of 21 bytes, and a verbatim
+    // block with the rest
+    const STREAM: &[u8] = b"\x00\x80\xac\x00\x00\x30\x50\x01\x01\x00\x00\x00\x01\x00\x00\x00\x01\
+        \x00\x00\x00\x55\x8b\xec\xe8\x67\x00\x00\x00\x90\x90\x90\x90\x90\x90\x90\x90\xe8\xec\xff\xff\
+        \xff\x00\x00\x20\x28\x08\x00\x00\x00\x00\x04\x00\x00\x68\x19\x44\x07\x62\x17\x8b\x07\xfb\xb6\
+        \x64\x84\x5c\x16\x53\x10\x28\x00\xc0\x00\x00\x00\x00\x80\x00\x84\x00\xb1\x35\xc2\x9f\x7d\xce\
+        \xc3\xf7\x00\x20\x00\x00\x00\x00\x03\x00\x10\x30\x6b\xad\xfb\xbe\xa5\xef\x55\xa3\x81\x0c\x79\
+        \x8f\x09\x4c\xf6\x1f\x5e\xc4\xd5\x7a\x10\xf9";
+    // calls to relative 100, -20 and 3000, stored in the stream as absolute 103, -20 and 3058
+    const DATA: &[u8] = b"\x55\x8b\xec\xe8\x64\x00\x00\x00\x90\x90\x90\x90\x90\x90\x90\x90\xe8\xec\
+        \xff\xff\xffcall me maybe, call me maybe, \xe8\xb8\x0b\x00\x00\xc3\xc3\xc3\xc3call me maybe!\
+        \x90\x90\x90\x90\x90\x90\x90\x90\x90\x90\x90\x90";
+
+    #[test]
+    fn uncompressed_and_verbatim_blocks_are_e8_translated() {
+        let mut buf_decompressed = [0_u8; DATA.len()];
+        let len = lzx_decompress(STREAM, &mut buf_decompressed, 16).unwrap();
+        assert_eq!(len, DATA.len());
+        assert_eq!(&buf_decompressed, DATA);
+    }
+
+    #[test]
+    fn malformed_streams_are_errors() {
+        let mut buf_decompressed = [0_u8; DATA.len()];
+        for window_bits in [14, 22] {
+            let err = lzx_decompress(STREAM, &mut buf_decompressed, window_bits).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+
+        // the end of input is padded with a few zero bits, same as in lzxd, so only the last
+        // bytes of the verbatim block may be missing
+        for len in 0..STREAM.len() - 5 {
+            let err = lzx_decompress(&STREAM[..len], &mut buf_decompressed, 16).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{len}");
+        }
+
+        // no single bit off makes decoding panic
+        for pos in 0..STREAM.len() {
+            for bit in 0..8 {
+                let mut corrupt = STREAM.to_vec();
+                corrupt[pos] ^= 1 << bit;
+                let _ = lzx_decompress(&corrupt, &mut buf_decompressed, 16);
+            }
+        }
+    }
+}
@@ -0,0 +1,608 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use paku::fastlz::FastLzFrameReader;
+use paku::h2_lzf::H2LzfReader;
+use paku::inspect::{inspect_lz4_jblock, inspect_lzf, InspectedBlock, Inspection};
+use paku::lz4_jblock::{
+    Lz4JBlockReader, Lz4JBlockWriter, COMPRESSION_METHOD_LZ4, COMPRESSION_METHOD_RAW,
+    DEFAULT_BLOCK_SIZE,
+};
+use paku::lzf::{
+    LzfReader, LzfWriter, CHUNK_TYPE_COMPRESSED, CHUNK_TYPE_UNCOMPRESSED, MAX_CHUNK_SIZE,
+};
+use paku::mam::read_mam_default;
+use paku::mozlz4::{read_mozlz4, write_mozlz4};
+
+// Command line front end: streams data through the readers and writers of the library, between
+// files and stdin/stdout. Arguments are parsed by hand to keep the crate free of dependencies.
+
+const USAGE: &str = "\
+usage: paku decompress [options] [INPUT]
+       paku compress [options] [INPUT]
+       paku inspect [options] [INPUT]
+
+inspect lists the blocks of lz4-block or lzf INPUT with whatever is wrong with them, and exits
+with status 1 if anything is.
+
+INPUT defaults to stdin, `-` is stdin as well.
+
+options:
+  -f, --format FORMAT        format of compressed data, guessed from the extension of INPUT when
+                             decompressing or inspecting and of OUTPUT when compressing if
+                             not given
+  -o, --output OUTPUT        where to write, stdout by default; removed again if anything
+                             fails, so that no partial output is left behind
+      --no-checksum          don't verify checksums (lz4-block, fastlz)
+      --stop-after-empty-block
+                             stop at the first empty block and ignore what follows (lz4-block)
+      --block-size SIZE      size of blocks to compress data in (lz4-block, lzf)
+      --json                 print inspection as JSON
+  -h, --help                 print this help
+
+formats:
+  lzf        ZV chunk streams of compress-lzf (.lzf)
+  lz4-block  lz4-java LZ4BlockOutputStream streams (.lz4)
+  mozlz4     Firefox mozLz4 files (.jsonlz4, .mozlz4, .baklz4)
+  fastlz     Netty FastLzFrameEncoder streams, decompression only
+  h2-lzf     H2 database LZFOutputStream streams, decompression only
+  mam        Windows MAM containers, decompression only
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Lzf,
+    Lz4Block,
+    MozLz4,
+    FastLz,
+    H2Lzf,
+    Mam,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "lzf" => Some(Format::Lzf),
+            "lz4-block" => Some(Format::Lz4Block),
+            "mozlz4" => Some(Format::MozLz4),
+            "fastlz" => Some(Format::FastLz),
+            "h2-lzf" => Some(Format::H2Lzf),
+            "mam" => Some(Format::Mam),
+            _ => None,
+        }
+    }
+
+    fn from_extension(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "lzf" => Some(Format::Lzf),
+            "lz4" => Some(Format::Lz4Block),
+            "jsonlz4" | "mozlz4" | "baklz4" => Some(Format::MozLz4),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Lzf => "lzf",
+            Format::Lz4Block => "lz4-block",
+            Format::MozLz4 => "mozlz4",
+            Format::FastLz => "fastlz",
+            Format::H2Lzf => "h2-lzf",
+            Format::Mam => "mam",
+        }
+    }
+
+    fn can_compress(self) -> bool {
+        matches!(self, Format::Lzf | Format::Lz4Block | Format::MozLz4)
+    }
+
+    fn can_inspect(self) -> bool {
+        matches!(self, Format::Lzf | Format::Lz4Block)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Decompress,
+    Compress,
+    Inspect,
+}
+
+#[derive(Debug)]
+struct Options {
+    command: Command,
+    format: Format,
+    // `None` for stdin and stdout
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    check_checksum: bool,
+    stop_after_empty_block: bool,
+    block_size: Option<usize>,
+    json: bool,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args_os().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("paku: {}\nsee `paku --help` for usage", message);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("paku: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// `None` when help was asked for
+fn parse_args(args: impl Iterator<Item = OsString>) -> Result<Option<Options>, String> {
+    let mut args = args;
+    let command = match args.next() {
+        None => return Err("missing command".to_string()),
+        Some(arg) => match arg.to_str() {
+            Some("decompress") => Command::Decompress,
+            Some("compress") => Command::Compress,
+            Some("inspect") => Command::Inspect,
+            Some("-h" | "--help" | "help") => return Ok(None),
+            _ => return Err(format!("unknown command {}", arg.to_string_lossy())),
+        },
+    };
+
+    let mut format = None;
+    let mut input = None;
+    let mut output = None;
+    let mut check_checksum = true;
+    let mut stop_after_empty_block = false;
+    let mut block_size = None;
+    let mut json = false;
+    let mut only_paths = false;
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.to_str() {
+            Some(flag) if !only_paths && flag.starts_with('-') && flag != "-" => flag,
+            _ => {
+                if input.is_some() {
+                    return Err(format!("unexpected argument {}", arg.to_string_lossy()));
+                }
+                input = Some(arg);
+                continue;
+            }
+        };
+
+        // `--flag=value` is accepted as well as `--flag value`
+        let (flag, inline_value) = match flag.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(OsString::from(value))),
+            _ => (flag, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+
+        match flag {
+            "-f" | "--format" => {
+                let name = value()?;
+                let name = name.to_string_lossy();
+                format = Some(
+                    Format::from_name(&name).ok_or_else(|| format!("unknown format {}", name))?,
+                );
+            }
+            "-o" | "--output" => output = Some(value()?),
+            "--no-checksum" => check_checksum = false,
+            "--stop-after-empty-block" => stop_after_empty_block = true,
+            "--block-size" => {
+                let size = value()?;
+                let size = size.to_string_lossy();
+                block_size = Some(
+                    size.parse()
+                        .map_err(|_| format!("invalid block size {}", size))?,
+                );
+            }
+            "--json" => json = true,
+            "-h" | "--help" => return Ok(None),
+            "--" => only_paths = true,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+
+    // `-` stands for stdin and stdout
+    let input = input.filter(|path| path != "-").map(PathBuf::from);
+    let output = output.filter(|path| path != "-").map(PathBuf::from);
+
+    let compressed_path = match command {
+        Command::Decompress | Command::Inspect => &input,
+        Command::Compress => &output,
+    };
+    let format = match format {
+        Some(format) => format,
+        None => compressed_path
+            .as_deref()
+            .and_then(Format::from_extension)
+            .ok_or("can't tell the format from the file name, use --format")?,
+    };
+    if command == Command::Compress && !format.can_compress() {
+        return Err(format!("{} can only be decompressed", format.name()));
+    }
+    if command == Command::Inspect && !format.can_inspect() {
+        return Err(format!("{} can't be inspected", format.name()));
+    }
+
+    Ok(Some(Options {
+        command,
+        format,
+        input,
+        output,
+        check_checksum,
+        stop_after_empty_block,
+        block_size,
+        json,
+    }))
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let input_name = display_name(&options.input, "stdin");
+    let output_name = display_name(&options.output, "stdout");
+
+    let input: Box<dyn Read> = match &options.input {
+        Some(path) => Box::new(File::open(path).map_err(|err| format!("{}: {}", input_name, err))?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) => {
+            Box::new(File::create(path).map_err(|err| format!("{}: {}", output_name, err))?)
+        }
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let result = process(options, &input_name, &output_name, input, output);
+    if result.is_err() {
+        if let Some(path) = &options.output {
+            // best effort, the original error is what matters
+            let _ = fs::remove_file(path);
+        }
+    }
+    let anomaly_count = result?;
+
+    if anomaly_count > 0 {
+        return Err(format!("{}: found {} anomalies", input_name, anomaly_count));
+    }
+    Ok(())
+}
+
+// returns the number of anomalies found by inspection, output is closed on return
+fn process(
+    options: &Options,
+    input_name: &str,
+    output_name: &str,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+) -> Result<usize, String> {
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+
+    let result = match options.command {
+        Command::Decompress => decompress(options, &mut input, &mut output).map(|()| 0),
+        Command::Compress => compress(options, &mut input, &mut output).map(|()| 0),
+        Command::Inspect => inspect(options, &mut input, &mut output),
+    };
+    let action = match options.command {
+        Command::Decompress => "decompressing",
+        Command::Compress => "compressing",
+        Command::Inspect => "inspecting",
+    };
+    let anomaly_count = result.map_err(|err| {
+        format!(
+            "{} {} {}: {}",
+            action,
+            options.format.name(),
+            input_name,
+            err
+        )
+    })?;
+    output
+        .flush()
+        .map_err(|err| format!("{}: {}", output_name, err))?;
+    Ok(anomaly_count)
+}
+
+fn decompress<R: Read, W: Write>(options: &Options, input: R, mut output: W) -> io::Result<()> {
+    match options.format {
+        Format::Lzf => {
+            io::copy(&mut LzfReader::new(input), &mut output)?;
+        }
+        Format::Lz4Block => {
+            let mut reader = Lz4JBlockReader::new(
+                input,
+                options.stop_after_empty_block,
+                options.check_checksum,
+            );
+            io::copy(&mut reader, &mut output)?;
+        }
+        Format::MozLz4 => output.write_all(&read_mozlz4(input)?)?,
+        Format::FastLz => {
+            let mut reader = FastLzFrameReader::new(input, options.check_checksum);
+            io::copy(&mut reader, &mut output)?;
+        }
+        Format::H2Lzf => {
+            io::copy(&mut H2LzfReader::new(input), &mut output)?;
+        }
+        Format::Mam => output.write_all(&read_mam_default(input)?)?,
+    }
+    Ok(())
+}
+
+fn compress<R: Read, W: Write>(options: &Options, mut input: R, output: W) -> io::Result<()> {
+    match options.format {
+        Format::Lzf => {
+            let chunk_size = options.block_size.unwrap_or(MAX_CHUNK_SIZE);
+            let mut writer = LzfWriter::new(output, chunk_size)?;
+            io::copy(&mut input, &mut writer)?;
+            writer.finish()?;
+        }
+        Format::Lz4Block => {
+            let block_size = options.block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+            let mut writer = Lz4JBlockWriter::new(output, block_size)?;
+            io::copy(&mut input, &mut writer)?;
+            writer.finish()?;
+        }
+        Format::MozLz4 => {
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf)?;
+            write_mozlz4(output, &buf)?;
+        }
+        // rejected while parsing arguments
+        Format::FastLz | Format::H2Lzf | Format::Mam => unreachable!(),
+    }
+    Ok(())
+}
+
+// returns the number of anomalies found
+fn inspect<R: Read, W: Write>(options: &Options, input: R, mut output: W) -> io::Result<usize> {
+    let inspection = match options.format {
+        Format::Lz4Block => inspect_lz4_jblock(input)?,
+        Format::Lzf => inspect_lzf(input)?,
+        // rejected while parsing arguments
+        Format::MozLz4 | Format::FastLz | Format::H2Lzf | Format::Mam => unreachable!(),
+    };
+    if options.json {
+        inspection.write_json(&mut output)?;
+    } else {
+        write_inspection(options.format, &inspection, &mut output)?;
+    }
+    Ok(inspection.anomaly_count())
+}
+
+fn write_inspection<W: Write>(
+    format: Format,
+    inspection: &Inspection,
+    mut output: W,
+) -> io::Result<()> {
+    writeln!(
+        output,
+        "{:>12}  {:<8}  {:<12}  {:>5}  {:>10}  {:>10}  {:>10}  {:>10}  anomalies",
+        "offset", "magic", "method", "level", "compressed", "original", "stored", "computed"
+    )?;
+    for block in &inspection.blocks {
+        writeln!(
+            output,
+            "{:>12}  {:<8}  {:<12}  {:>5}  {:>10}  {:>10}  {:>10}  {:>10}  {}",
+            block.offset,
+            escape_magic(&block.magic),
+            method_name(format, block),
+            or_dash(block.level),
+            or_dash(block.compressed_length),
+            or_dash(block.original_length),
+            or_dash(
+                block
+                    .stored_checksum
+                    .map(|checksum| format!("{:#09x}", checksum))
+            ),
+            or_dash(
+                block
+                    .computed_checksum
+                    .map(|checksum| format!("{:#09x}", checksum))
+            ),
+            block
+                .anomalies
+                .iter()
+                .map(|anomaly| anomaly.name())
+                .collect::<Vec<_>>()
+                .join(" "),
+        )?;
+    }
+    writeln!(
+        output,
+        "{} blocks, {} bytes, {} anomalies",
+        inspection.blocks.len(),
+        inspection.length,
+        inspection.anomaly_count()
+    )
+}
+
+fn method_name(format: Format, block: &InspectedBlock) -> String {
+    match (format, block.method) {
+        (_, None) => "-".to_string(),
+        (Format::Lz4Block, Some(COMPRESSION_METHOD_RAW)) => "raw".to_string(),
+        (Format::Lz4Block, Some(COMPRESSION_METHOD_LZ4)) => "lz4".to_string(),
+        (Format::Lzf, Some(CHUNK_TYPE_UNCOMPRESSED)) => "uncompressed".to_string(),
+        (Format::Lzf, Some(CHUNK_TYPE_COMPRESSED)) => "compressed".to_string(),
+        (_, Some(method)) => format!("{:#04x}", method),
+    }
+}
+
+// printable ASCII as is, anything else as an escape
+fn escape_magic(magic: &[u8]) -> String {
+    magic.escape_ascii().to_string()
+}
+
+fn or_dash<T: ToString>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn display_name(path: &Option<PathBuf>, default: &str) -> String {
+    match path {
+        Some(path) => path.display().to_string(),
+        None => default.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(OsString::from))
+    }
+
+    fn path(path: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(path))
+    }
+
+    #[test]
+    fn values_follow_flags_or_equal_signs() {
+        let options = parse(&[
+            "compress",
+            "--format=lz4-block",
+            "--block-size=4096",
+            "--output=out",
+            "-f",
+            "lzf",
+            "--no-checksum",
+            "in",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.command, Command::Compress);
+        assert_eq!(options.format, Format::Lzf);
+        assert_eq!(options.block_size, Some(4096));
+        assert_eq!((options.input, options.output), (path("in"), path("out")));
+        assert!(!options.check_checksum);
+
+        // short flags take no `=`
+        let err = parse(&["decompress", "-f=lzf", "in"]).unwrap_err();
+        assert_eq!(err, "unknown option -f=lzf");
+        let err = parse(&["decompress", "in.lzf", "--output"]).unwrap_err();
+        assert_eq!(err, "--output needs a value");
+        let err = parse(&["decompress", "--block-size=big", "in.lzf"]).unwrap_err();
+        assert_eq!(err, "invalid block size big");
+        assert!(parse(&["decompress", "in.lzf", "--help"])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn arguments_after_double_dash_are_paths() {
+        let options = parse(&["inspect", "--json", "--", "--stats.lz4"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.format, Format::Lz4Block);
+        assert_eq!(options.input, path("--stats.lz4"));
+        assert!(options.json);
+
+        let err = parse(&["inspect", "--", "a.lz4", "--json"]).unwrap_err();
+        assert_eq!(err, "unexpected argument --json");
+    }
+
+    #[test]
+    fn dash_is_stdin_and_stdout() {
+        let options = parse(&["compress", "-f", "lzf", "-o", "-", "-"])
+            .unwrap()
+            .unwrap();
+        assert_eq!((options.input, options.output), (None, None));
+
+        let err = parse(&["decompress", "-"]).unwrap_err();
+        assert_eq!(
+            err,
+            "can't tell the format from the file name, use --format"
+        );
+    }
+
+    #[test]
+    fn format_is_told_by_extension_of_compressed_file() {
+        let cases = [
+            (&["decompress", "a.lzf"][..], Format::Lzf),
+            (&["decompress", "a.lz4", "-o", "b.lzf"], Format::Lz4Block),
+            (&["inspect", "a.lzf"], Format::Lzf),
+            (&["compress", "a.lz4", "-o", "b.jsonlz4"], Format::MozLz4),
+            (&["compress", "-o", "b.baklz4"], Format::MozLz4),
+            (&["decompress", "-f", "mam", "a.lz4"], Format::Mam),
+        ];
+        for (args, format) in cases {
+            let options = parse(args).unwrap().unwrap();
+            assert_eq!(options.format, format, "{args:?}");
+        }
+
+        // extension of the input says nothing about compressing it
+        let err = parse(&["compress", "a.lz4"]).unwrap_err();
+        assert_eq!(
+            err,
+            "can't tell the format from the file name, use --format"
+        );
+    }
+
+    #[test]
+    fn formats_are_rejected_for_commands_they_lack() {
+        let cases = [
+            (
+                &["compress", "-f", "fastlz"][..],
+                "fastlz can only be decompressed",
+            ),
+            (
+                &["compress", "-f", "h2-lzf"],
+                "h2-lzf can only be decompressed",
+            ),
+            (&["compress", "-f", "mam"], "mam can only be decompressed"),
+            (&["inspect", "a.jsonlz4"], "mozlz4 can't be inspected"),
+            (&["inspect", "-f", "mam"], "mam can't be inspected"),
+            (&["decompress", "-f", "zip"], "unknown format zip"),
+        ];
+        for (args, message) in cases {
+            assert_eq!(parse(args).unwrap_err(), message, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn output_is_removed_on_failure() {
+        let dir = env::temp_dir().join(format!("paku-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.lzf");
+        let output = dir.join("out");
+        fs::write(&input, b"ZV\x00\x00\x05abc").unwrap();
+        fs::write(&output, b"previous").unwrap();
+
+        let options = parse(&[
+            "decompress",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .unwrap()
+        .unwrap();
+        let err = run(&options).unwrap_err();
+        assert!(err.starts_with("decompressing lzf"), "{err}");
+        assert!(!output.exists());
+
+        fs::write(&input, b"ZV\x00\x00\x03abc").unwrap();
+        run(&options).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"abc");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
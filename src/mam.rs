@@ -0,0 +1,178 @@
+use std::io::{self, Error, ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::crc32::Crc32;
+use crate::lznt1_decompress::{lznt1_decompress, LZNT1_MAX_EXPANSION};
+use crate::xpress_decompress::{xpress_decompress, xpress_huffman_decompress};
+
+// Compressed container used by Windows 10+ prefetch files and `RtlCompressBuffer` users:
+// "MAM" signature, a byte with the compression format in the low nibble and a checksum flag in
+// the high bit, little-endian u32 decompressed size, optional CRC-32, and the compressed data.
+// The checksum covers the whole file with the checksum field itself zeroed.
+
+pub const MAM_SIGNATURE: &[u8; 3] = b"MAM";
+
+const COMPRESSION_FORMAT_LZNT1: u8 = 2;
+const COMPRESSION_FORMAT_XPRESS: u8 = 3;
+const COMPRESSION_FORMAT_XPRESS_HUFF: u8 = 4;
+const CHECKSUM_FLAG: u8 = 0x80;
+
+/// Limit used by `read_mam_default`, prefetch files are nowhere near it.
+pub const DEFAULT_MAX_DECOMPRESSED_LENGTH: usize = 256 * 1024 * 1024;
+
+/// Reads a whole MAM file and returns its decompressed contents, refusing to allocate more
+/// than `max_decompressed_length` bytes no matter what the (possibly corrupt) header says.
+pub fn read_mam<R: Read>(mut reader: R, max_decompressed_length: usize) -> io::Result<Vec<u8>> {
+    let mut header = [0_u8; 8];
+    reader.read_exact(&mut header)?;
+    if &header[..3] != MAM_SIGNATURE {
+        return Err(Error::new(ErrorKind::InvalidData, "wrong mam signature"));
+    }
+
+    let format = header[3] & 0x0F;
+    let has_checksum = header[3] & CHECKSUM_FLAG != 0;
+    let original_length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+    let expected_checksum = if has_checksum {
+        Some(reader.read_u32::<LittleEndian>()?)
+    } else {
+        None
+    };
+
+    let mut buf_compressed = Vec::new();
+    reader.read_to_end(&mut buf_compressed)?;
+
+    if let Some(expected_checksum) = expected_checksum {
+        let mut crc32 = Crc32::new();
+        crc32.update(&header);
+        crc32.update(&[0; 4]);
+        crc32.update(&buf_compressed);
+        if crc32.digest() != expected_checksum {
+            return Err(Error::new(ErrorKind::InvalidData, "mam checksum mismatch"));
+        }
+    }
+
+    if original_length > max_decompressed_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "mam decompressed size is over the allowed maximum",
+        ));
+    }
+
+    let decompress = match format {
+        COMPRESSION_FORMAT_LZNT1 => lznt1_decompress,
+        COMPRESSION_FORMAT_XPRESS => xpress_decompress,
+        COMPRESSION_FORMAT_XPRESS_HUFF => xpress_huffman_decompress,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "unsupported mam compression format",
+            ))
+        }
+    };
+
+    // xpress match lengths take up to 6 bytes and reach 4GB, so only lznt1 output is bounded
+    if format == COMPRESSION_FORMAT_LZNT1
+        && original_length / LZNT1_MAX_EXPANSION > buf_compressed.len()
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "mam decompressed size is too large for the compressed data",
+        ));
+    }
+
+    let mut buf_decompressed = vec![0; original_length];
+    let decompressed_length = decompress(&buf_compressed, &mut buf_decompressed)?;
+    if decompressed_length != original_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "mam decompressed size mismatch",
+        ));
+    }
+
+    Ok(buf_decompressed)
+}
+
+/// Same as `read_mam` with `DEFAULT_MAX_DECOMPRESSED_LENGTH` as the limit.
+pub fn read_mam_default<R: Read>(reader: R) -> io::Result<Vec<u8>> {
+    read_mam(reader, DEFAULT_MAX_DECOMPRESSED_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"mam header, mam header, mam data";
+    // lznt1 without a checksum, and plain xpress with one
+    const LZNT1_MAM: &[u8] = b"MAM\x02\x20\x00\x00\x00\x14\xb0\x00mam head\x10er, \x0d\xb0dat\x00a";
+    const XPRESS_MAM: &[u8] =
+        b"MAM\x83\x20\x00\x00\x00*\xca\xf3\x08\xff\x7f\x08\x00mam header, _\x00\x06data";
+
+    #[test]
+    fn files_with_and_without_checksum_are_read() {
+        assert_eq!(read_mam_default(LZNT1_MAM).unwrap(), DATA);
+        assert_eq!(read_mam_default(XPRESS_MAM).unwrap(), DATA);
+    }
+
+    #[test]
+    fn checksum_is_verified() {
+        for pos in [3, 4, 8, XPRESS_MAM.len() - 1] {
+            let mut corrupt = XPRESS_MAM.to_vec();
+            corrupt[pos] ^= 0x40;
+            let err = read_mam_default(&corrupt[..]).unwrap_err();
+            assert_eq!(err.to_string(), "mam checksum mismatch", "{pos}");
+        }
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let with_header = |header: &[u8]| [header, &LZNT1_MAM[8..]].concat();
+        let invalid = [
+            (
+                with_header(b"MAN\x02\x20\x00\x00\x00"),
+                ErrorKind::InvalidData,
+            ),
+            (
+                with_header(b"MAM\x05\x20\x00\x00\x00"),
+                ErrorKind::Unsupported,
+            ),
+            // sizes that do not match the data
+            (
+                with_header(b"MAM\x02\x1f\x00\x00\x00"),
+                ErrorKind::InvalidData,
+            ),
+            (
+                with_header(b"MAM\x02\x21\x00\x00\x00"),
+                ErrorKind::InvalidData,
+            ),
+            (
+                LZNT1_MAM[..LZNT1_MAM.len() - 1].to_vec(),
+                ErrorKind::InvalidData,
+            ),
+            (LZNT1_MAM[..6].to_vec(), ErrorKind::UnexpectedEof),
+            (XPRESS_MAM[..10].to_vec(), ErrorKind::UnexpectedEof),
+        ];
+        for (file, kind) in invalid {
+            let err = read_mam_default(&file[..]).unwrap_err();
+            assert_eq!(err.kind(), kind, "{file:?}");
+        }
+    }
+
+    #[test]
+    fn sizes_are_checked_before_allocating() {
+        let err = read_mam(LZNT1_MAM, DATA.len() - 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mam decompressed size is over the allowed maximum"
+        );
+
+        // far more than 23 bytes of lznt1 expand into
+        let file = [b"MAM\x02\xff\xff\xff\xff", &LZNT1_MAM[8..]].concat();
+        let err = read_mam(&file[..], usize::MAX).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mam decompressed size is too large for the compressed data"
+        );
+    }
+}
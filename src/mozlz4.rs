@@ -0,0 +1,60 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::lz4_jblock_compress::lz4_jblock_compress;
+use crate::lz4_jblock_decompress::{lz4_jblock_decompress, LZ4_MAX_EXPANSION};
+
+/// Firefox mozLz4 files (`.jsonlz4`, `.mozlz4`, `.baklz4`), as written by `mozilla::Compression`:
+/// "mozLz40\0" magic, little-endian u32 decompressed size, and a single raw lz4 block.
+pub const MOZLZ4_MAGIC: &[u8; 8] = b"mozLz40\0";
+
+/// Reads a whole mozLz4 file and returns its decompressed contents.
+pub fn read_mozlz4<R: Read>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut magic = [0_u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MOZLZ4_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "wrong mozlz4 magic"));
+    }
+
+    let original_length = reader.read_u32::<LittleEndian>()? as usize;
+
+    let mut buf_compressed = Vec::new();
+    reader.read_to_end(&mut buf_compressed)?;
+
+    // check before allocating, so that a corrupt size can't make us allocate up to 4GB
+    if original_length / LZ4_MAX_EXPANSION > buf_compressed.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "mozlz4 decompressed size is too large for compressed data",
+        ));
+    }
+
+    let mut buf_decompressed = vec![0; original_length];
+    let decompressed_length = lz4_jblock_decompress(&buf_compressed, &mut buf_decompressed)?;
+    if decompressed_length != original_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "mozlz4 decompressed size mismatch",
+        ));
+    }
+
+    Ok(buf_decompressed)
+}
+
+/// Writes `buf` as a complete mozLz4 file.
+pub fn write_mozlz4<W: Write>(mut writer: W, buf: &[u8]) -> io::Result<()> {
+    let original_length = u32::try_from(buf.len()).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "mozlz4 can't store more than 4GB of data",
+        )
+    })?;
+
+    let mut buf_compressed = Vec::with_capacity(buf.len() / 2);
+    lz4_jblock_compress(buf, &mut buf_compressed);
+
+    writer.write_all(MOZLZ4_MAGIC)?;
+    writer.write_u32::<LittleEndian>(original_length)?;
+    writer.write_all(&buf_compressed)
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Machinery shared by parallel readers and writers of formats made of independent blocks: the
+// caller's thread hands blocks to a pool of worker threads for decoding or encoding, while a
+// queue of result receivers keeps processed blocks in stream order.
+
+/// Decodes or encodes a single block.
+pub(crate) type Job = Box<dyn FnOnce() -> io::Result<Vec<u8>> + Send>;
+
+type Task = (Job, Sender<io::Result<Vec<u8>>>);
+
+struct WorkerPool {
+    task_sender: Option<Sender<Task>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    fn new(threads: usize) -> io::Result<WorkerPool> {
+        let (task_sender, task_receiver) = mpsc::channel::<Task>();
+        let task_receiver = Arc::new(Mutex::new(task_receiver));
+
+        let mut workers = Vec::with_capacity(threads);
+        for i in 0..threads {
+            let task_receiver = Arc::clone(&task_receiver);
+            let worker = thread::Builder::new()
+                .name(format!("paku-worker-{}", i))
+                .spawn(move || loop {
+                    // lock is released before running the task, so workers decode in parallel
+                    let task = match task_receiver.lock() {
+                        Ok(task_receiver) => task_receiver.recv(),
+                        Err(_) => return,
+                    };
+                    match task {
+                        // reader might be gone already, then nobody needs the result
+                        Ok((job, result_sender)) => {
+                            let _ = result_sender.send(job());
+                        }
+                        Err(_) => return,
+                    }
+                })?;
+            workers.push(worker);
+        }
+
+        Ok(WorkerPool {
+            task_sender: Some(task_sender),
+            workers,
+        })
+    }
+
+    fn submit(&self, job: Job) -> Receiver<io::Result<Vec<u8>>> {
+        let (result_sender, result_receiver) = mpsc::channel();
+        if let Some(task_sender) = &self.task_sender {
+            // workers only stop once the sender is dropped, so this can't fail
+            let _ = task_sender.send((job, result_sender));
+        }
+        result_receiver
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // closing the channel makes workers stop once they are done with queued tasks
+        self.task_sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Decoded blocks in stream order, with at most `blocks_in_flight` of them read ahead.
+pub(crate) struct ParallelBlocks {
+    pool: WorkerPool,
+    pending: VecDeque<Receiver<io::Result<Vec<u8>>>>,
+    blocks_in_flight: usize,
+    source_done: bool,
+    // kind and message of the error the stream failed with, returned again on later calls
+    failure: Option<(ErrorKind, String)>,
+    buf_decompressed: Vec<u8>,
+    pos: usize,
+}
+
+impl ParallelBlocks {
+    pub(crate) fn new(threads: usize, blocks_in_flight: usize) -> io::Result<ParallelBlocks> {
+        if threads == 0 || blocks_in_flight == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "parallel reader needs at least one thread and one block in flight",
+            ));
+        }
+        Ok(ParallelBlocks {
+            pool: WorkerPool::new(threads)?,
+            pending: VecDeque::with_capacity(blocks_in_flight),
+            blocks_in_flight,
+            source_done: false,
+            failure: None,
+            buf_decompressed: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Returns decoded data not consumed yet, waiting for the next block if there is none.
+    /// `next_job` reads the next raw block from the source, `None` meaning end of stream.
+    pub(crate) fn fill_buf(
+        &mut self,
+        mut next_job: impl FnMut() -> io::Result<Option<Job>>,
+    ) -> io::Result<&[u8]> {
+        while self.pos >= self.buf_decompressed.len() {
+            if let Some((kind, message)) = &self.failure {
+                return Err(Error::new(*kind, message.clone()));
+            }
+
+            while !self.source_done && self.pending.len() < self.blocks_in_flight {
+                match next_job() {
+                    Ok(Some(job)) => self.pending.push_back(self.pool.submit(job)),
+                    Ok(None) => self.source_done = true,
+                    Err(err) => {
+                        // reported once the blocks before it are consumed
+                        let (result_sender, result_receiver) = mpsc::channel();
+                        let _ = result_sender.send(Err(err));
+                        self.pending.push_back(result_receiver);
+                        self.source_done = true;
+                    }
+                }
+            }
+
+            let result_receiver = match self.pending.pop_front() {
+                Some(result_receiver) => result_receiver,
+                None => return Ok(&[]),
+            };
+            let result = result_receiver.recv().map_err(|_| worker_panicked());
+            match result.and_then(|result| result) {
+                Ok(buf_decompressed) => {
+                    self.buf_decompressed = buf_decompressed;
+                    self.pos = 0;
+                }
+                Err(err) => {
+                    // blocks after a failed one are of no use, and must not be mistaken for
+                    // the rest of the stream
+                    self.pending.clear();
+                    self.source_done = true;
+                    self.failure = Some((err.kind(), err.to_string()));
+                    return Err(err);
+                }
+            }
+        }
+        Ok(&self.buf_decompressed[self.pos..])
+    }
+
+    pub(crate) fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf_decompressed.len());
+    }
+}
+
+/// Encoded blocks waiting to be written in stream order, at most `blocks_in_flight` of them.
+pub(crate) struct ParallelEncoder {
+    pool: WorkerPool,
+    pending: VecDeque<Receiver<io::Result<Vec<u8>>>>,
+    blocks_in_flight: usize,
+}
+
+impl ParallelEncoder {
+    pub(crate) fn new(threads: usize, blocks_in_flight: usize) -> io::Result<ParallelEncoder> {
+        if threads == 0 || blocks_in_flight == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "parallel writer needs at least one thread and one block in flight",
+            ));
+        }
+        Ok(ParallelEncoder {
+            pool: WorkerPool::new(threads)?,
+            pending: VecDeque::with_capacity(blocks_in_flight),
+            blocks_in_flight,
+        })
+    }
+
+    /// Queues encoding of the next block, first waiting for the oldest ones and writing them
+    /// out while too many are in flight.
+    pub(crate) fn submit<W: Write>(&mut self, writer: &mut W, job: Job) -> io::Result<()> {
+        while self.pending.len() >= self.blocks_in_flight {
+            self.write_next(writer)?;
+        }
+        self.pending.push_back(self.pool.submit(job));
+        Ok(())
+    }
+
+    /// Waits for all queued blocks and writes them out.
+    pub(crate) fn write_pending<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            self.write_next(writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_next<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if let Some(result_receiver) = self.pending.pop_front() {
+            let buf_encoded = result_receiver.recv().map_err(|_| worker_panicked())??;
+            writer.write_all(&buf_encoded)?;
+        }
+        Ok(())
+    }
+}
+
+fn worker_panicked() -> Error {
+    Error::other("parallel worker thread panicked")
+}
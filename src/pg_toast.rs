@@ -0,0 +1,141 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::lz4_jblock_decompress::{lz4_jblock_decompress, LZ4_MAX_EXPANSION};
+use crate::pglz_decompress::{pglz_decompress, PGLZ_MAX_EXPANSION};
+
+// Compressed varlena datums, as found in PostgreSQL heap pages and TOAST tables, see
+// https://github.com/postgres/postgres/blob/master/src/include/varatt.h
+// Only little-endian layout is supported, which is what x86 and arm servers write.
+
+// va_tcinfo and va_extinfo keep the raw size in the low 30 bits and the method in the top 2
+const VARLENA_EXTSIZE_BITS: u32 = 30;
+const VARLENA_EXTSIZE_MASK: u32 = (1 << VARLENA_EXTSIZE_BITS) - 1;
+
+const TOAST_PGLZ_COMPRESSION_ID: u32 = 0;
+const TOAST_LZ4_COMPRESSION_ID: u32 = 1;
+
+const VARHDRSZ: usize = 4;
+// `va_tcinfo` of a compressed varlena: raw size and compression method
+const VARLENA_TCINFO_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastCompressionMethod {
+    Pglz,
+    Lz4,
+}
+
+/// Compression header of a compressed datum, stored in `va_tcinfo`.
+/// Before PostgreSQL 14 the top bits were always zero, so old data reads as pglz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToastCompressionInfo {
+    pub method: ToastCompressionMethod,
+    pub raw_size: usize,
+}
+
+impl ToastCompressionInfo {
+    pub fn parse(tcinfo: u32) -> error::Result<ToastCompressionInfo> {
+        let method = match tcinfo >> VARLENA_EXTSIZE_BITS {
+            TOAST_PGLZ_COMPRESSION_ID => ToastCompressionMethod::Pglz,
+            TOAST_LZ4_COMPRESSION_ID => ToastCompressionMethod::Lz4,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "unknown toast compression method",
+                ))
+            }
+        };
+        Ok(ToastCompressionInfo {
+            method,
+            raw_size: (tcinfo & VARLENA_EXTSIZE_MASK) as usize,
+        })
+    }
+}
+
+/// Decompresses an inline compressed varlena datum, starting with its 4 byte header.
+/// Anything after the size recorded in the header is ignored.
+pub fn varlena_decompress(datum: &[u8]) -> error::Result<Vec<u8>> {
+    let header = read_u32_le(datum)?;
+
+    match header & 0x03 {
+        0x02 => {}
+        0x00 => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "varlena is not compressed",
+            ))
+        }
+        _ if header & 0xFF == 0x01 => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "varlena is an external toast pointer, its chunks have to be fetched first",
+            ))
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "varlena with short header is never compressed",
+            ))
+        }
+    }
+
+    let size = (header >> 2) as usize;
+    if size < VARHDRSZ + VARLENA_TCINFO_SIZE || size > datum.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "varlena size does not match datum length",
+        ));
+    }
+
+    toast_decompress(&datum[VARHDRSZ..size])
+}
+
+/// Decompresses the data of a compressed datum without its varlena header, starting with
+/// `va_tcinfo`. This is what chunks of an externally stored compressed value add up to.
+pub fn toast_decompress(data: &[u8]) -> error::Result<Vec<u8>> {
+    let info = ToastCompressionInfo::parse(read_u32_le(data)?)?;
+    let buf_compressed = &data[VARLENA_TCINFO_SIZE..];
+
+    match info.method {
+        ToastCompressionMethod::Pglz => {
+            if info.raw_size / PGLZ_MAX_EXPANSION > buf_compressed.len() {
+                return Err(too_large());
+            }
+            let mut buf_decompressed = vec![0; info.raw_size];
+            let decompressed_length = pglz_decompress(buf_compressed, &mut buf_decompressed)?;
+            if decompressed_length != info.raw_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "pglz decompressed size mismatch",
+                ));
+            }
+            Ok(buf_decompressed)
+        }
+        ToastCompressionMethod::Lz4 => {
+            if info.raw_size / LZ4_MAX_EXPANSION > buf_compressed.len() {
+                return Err(too_large());
+            }
+            let mut buf_decompressed = vec![0; info.raw_size];
+            lz4_jblock_decompress(buf_compressed, &mut buf_decompressed)?;
+            Ok(buf_decompressed)
+        }
+    }
+}
+
+fn read_u32_le(buf: &[u8]) -> error::Result<u32> {
+    match buf {
+        [b0, b1, b2, b3, ..] => Ok(u32::from_le_bytes([*b0, *b1, *b2, *b3])),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "toast compression header is truncated",
+        )),
+    }
+}
+
+fn too_large() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "toast raw size is too large for compressed data",
+    )
+}
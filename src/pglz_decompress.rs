@@ -0,0 +1,71 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// longest back reference takes 3 bytes and expands into 273 bytes of output
+#[cfg(feature = "alloc")]
+pub(crate) const PGLZ_MAX_EXPANSION: usize = 91;
+
+/// goal is to match `pglz_decompress` from PostgreSQL:
+/// https://github.com/postgres/postgres/blob/master/src/common/pg_lzcompress.c
+///
+/// Decoding stops when either input is exhausted or `buf_decompressed` is full, the number of
+/// decompressed bytes is returned. PostgreSQL always knows the raw size of a datum, so callers
+/// that want to check for complete decompression should compare it with that size.
+pub fn pglz_decompress(buf_compressed: &[u8], buf_decompressed: &mut [u8]) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+
+    while in_pos < buf_compressed.len() && out_pos < buf_decompressed.len() {
+        // each control byte describes the next 8 items, starting from the lowest bit
+        let mut ctrl = buf_compressed[in_pos];
+        in_pos += 1;
+
+        for _ in 0..8 {
+            if in_pos >= buf_compressed.len() || out_pos >= buf_decompressed.len() {
+                break;
+            }
+
+            if ctrl & 1 == 0 {
+                // literal byte
+                buf_decompressed[out_pos] = buf_compressed[in_pos];
+                in_pos += 1;
+                out_pos += 1;
+            } else {
+                // back reference, 4 bits of length and 12 bits of offset,
+                // with length of 18 meaning that next byte adds to it
+                if buf_compressed.len() - in_pos < 2 {
+                    return Err(truncated());
+                }
+                let mut run_len = (buf_compressed[in_pos] & 0x0F) as usize + 3;
+                let ref_offset = (((buf_compressed[in_pos] & 0xF0) as usize) << 4)
+                    | buf_compressed[in_pos + 1] as usize;
+                in_pos += 2;
+                if run_len == 18 {
+                    run_len += *buf_compressed.get(in_pos).ok_or_else(truncated)? as usize;
+                    in_pos += 1;
+                }
+
+                if ref_offset == 0 || ref_offset > out_pos {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "pglz back reference points outside of output",
+                    ));
+                }
+                // same as PostgreSQL, a match running past the end of output is silently cut
+                let run_len = run_len.min(buf_decompressed.len() - out_pos);
+
+                copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+                out_pos += run_len;
+            }
+
+            ctrl >>= 1;
+        }
+    }
+
+    Ok(out_pos)
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "pglz compressed data is truncated")
+}
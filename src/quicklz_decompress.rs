@@ -0,0 +1,388 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+
+// QuickLZ 1.5.0, see http://www.quicklz.com/
+// Every packet starts with a flags byte followed by compressed and decompressed sizes, both
+// either single bytes or 32-bit little-endian. Data is a sequence of 32-bit control words, each
+// one flagging the next 31 items as literals (0) or back references (1) starting from its
+// lowest bit, with the top bit set as an end marker.
+
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_LONG_HEADER: u8 = 0x02;
+const SHORT_HEADER_SIZE: usize = 3;
+const LONG_HEADER_SIZE: usize = 9;
+
+const CONTROL_WORD_SIZE: usize = 4;
+const CONTROL_WORD_END: u32 = 1 << 31;
+
+// the last bytes of a packet are always literals
+const UNCONDITIONAL_MATCHLEN: usize = 6;
+const UNCOMPRESSED_END: usize = 4;
+
+// level 1 back references point to the last position with the same hash of its first 3 bytes
+const LEVEL_1_HASH_VALUES: usize = 4096;
+
+/// Returns the size of the packet at the start of `buf_compressed`, header included.
+pub fn quicklz_size_compressed(buf_compressed: &[u8]) -> error::Result<usize> {
+    Ok(read_header(buf_compressed)?.size_compressed)
+}
+
+/// Returns the decompressed size of the packet at the start of `buf_compressed`.
+pub fn quicklz_size_decompressed(buf_compressed: &[u8]) -> error::Result<usize> {
+    Ok(read_header(buf_compressed)?.size_decompressed)
+}
+
+/// goal is to match `qlz_decompress` from QuickLZ 1.5.0 for packets compressed with
+/// `QLZ_COMPRESSION_LEVEL` 1 or 3 and no streaming buffer.
+///
+/// `buf_compressed` must start with a packet and may extend past its end. Output has the size
+/// stored in the header, which is returned.
+pub fn quicklz_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let header = read_header(buf_compressed)?;
+    if header.streaming_buffer != 0 {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "quicklz streaming buffer is not supported",
+        ));
+    }
+    if buf_compressed.len() < header.size_compressed {
+        return Err(truncated());
+    }
+    if buf_decompressed.len() < header.size_decompressed {
+        return Err(output_overflow());
+    }
+    let buf_compressed = &buf_compressed[header.size_header..header.size_compressed];
+    let buf_decompressed = &mut buf_decompressed[..header.size_decompressed];
+
+    if header.flags & FLAG_COMPRESSED == 0 {
+        if buf_compressed.len() != buf_decompressed.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "quicklz uncompressed packet size mismatch",
+            ));
+        }
+        buf_decompressed.copy_from_slice(buf_compressed);
+        return Ok(buf_decompressed.len());
+    }
+
+    match header.level {
+        1 | 3 => decompress_core(buf_compressed, buf_decompressed, header.level),
+        2 => Err(Error::new(
+            ErrorKind::Unsupported,
+            "quicklz compression level 2 is not supported",
+        )),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unknown quicklz compression level",
+        )),
+    }
+}
+
+struct Header {
+    flags: u8,
+    level: u8,
+    streaming_buffer: u8,
+    size_header: usize,
+    size_compressed: usize,
+    size_decompressed: usize,
+}
+
+fn read_header(buf_compressed: &[u8]) -> error::Result<Header> {
+    let flags = *buf_compressed.first().ok_or_else(truncated)?;
+    let (size_header, size_compressed, size_decompressed) = if flags & FLAG_LONG_HEADER != 0 {
+        let bytes = buf_compressed
+            .get(..LONG_HEADER_SIZE)
+            .ok_or_else(truncated)?;
+        (
+            LONG_HEADER_SIZE,
+            u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize,
+            u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) as usize,
+        )
+    } else {
+        let bytes = buf_compressed
+            .get(..SHORT_HEADER_SIZE)
+            .ok_or_else(truncated)?;
+        (SHORT_HEADER_SIZE, bytes[1] as usize, bytes[2] as usize)
+    };
+    if size_compressed < size_header {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "quicklz compressed size is smaller than its header",
+        ));
+    }
+
+    Ok(Header {
+        flags,
+        level: (flags >> 2) & 0x03,
+        streaming_buffer: (flags >> 4) & 0x03,
+        size_header,
+        size_compressed,
+        size_decompressed,
+    })
+}
+
+fn decompress_core(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+    level: u8,
+) -> error::Result<usize> {
+    let size = buf_decompressed.len();
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let mut control_word = 1_u32;
+
+    // level 1 state, positions are only hashed once their 3 bytes are decompressed
+    let mut hash_table = if level == 1 {
+        vec![usize::MAX; LEVEL_1_HASH_VALUES]
+    } else {
+        Vec::new()
+    };
+    let mut next_to_hash = 0;
+
+    loop {
+        if control_word == 1 {
+            control_word = read_u32_le(buf_compressed, in_pos)? | CONTROL_WORD_END;
+            in_pos += CONTROL_WORD_SIZE;
+        }
+
+        if control_word & 1 == 1 {
+            // back reference
+            control_word >>= 1;
+            let fetch = peek_u32_le(buf_compressed, in_pos);
+
+            let (ref_offset, run_len, item_len) = if level == 1 {
+                let hash = ((fetch >> 4) & 0xFFF) as usize;
+                let (run_len, item_len) = if fetch & 0x0F != 0 {
+                    ((fetch & 0x0F) as usize + 2, 2)
+                } else {
+                    ((fetch >> 16) as u8 as usize, 3)
+                };
+                // the match start gets hashed right after the copy, so its 3 bytes must be there
+                if run_len < 3 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "quicklz match is shorter than 3 bytes",
+                    ));
+                }
+                let ref_pos = hash_table[hash];
+                if ref_pos >= out_pos {
+                    return Err(bad_reference());
+                }
+                (out_pos - ref_pos, run_len, item_len)
+            } else if fetch & 0x03 == 0 {
+                (((fetch & 0xFF) >> 2) as usize, 3, 1)
+            } else if fetch & 0x02 == 0 {
+                (((fetch & 0xFFFF) >> 2) as usize, 3, 2)
+            } else if fetch & 0x01 == 0 {
+                (
+                    ((fetch & 0xFFFF) >> 6) as usize,
+                    ((fetch >> 2) & 0x0F) as usize + 3,
+                    2,
+                )
+            } else if fetch & 0x7F != 3 {
+                (
+                    ((fetch >> 7) & 0x1FFFF) as usize,
+                    ((fetch >> 2) & 0x1F) as usize + 2,
+                    3,
+                )
+            } else {
+                (
+                    (fetch >> 15) as usize,
+                    ((fetch >> 7) & 0xFF) as usize + 3,
+                    4,
+                )
+            };
+
+            if buf_compressed.len() - in_pos < item_len {
+                return Err(truncated());
+            }
+            in_pos += item_len;
+
+            if ref_offset == 0 || ref_offset > out_pos {
+                return Err(bad_reference());
+            }
+            if size - out_pos < run_len {
+                return Err(output_overflow());
+            }
+            copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+
+            if level == 1 {
+                // positions inside the match are skipped, as they are by the compressor
+                hash_up_to(
+                    &mut hash_table,
+                    buf_decompressed,
+                    &mut next_to_hash,
+                    out_pos,
+                );
+                next_to_hash = out_pos + run_len;
+            }
+            out_pos += run_len;
+        } else if out_pos + UNCONDITIONAL_MATCHLEN + UNCOMPRESSED_END + 1 < size {
+            // literal
+            control_word >>= 1;
+            buf_decompressed[out_pos] = *buf_compressed.get(in_pos).ok_or_else(truncated)?;
+            in_pos += 1;
+            out_pos += 1;
+
+            if level == 1 && out_pos >= 3 {
+                hash_up_to(
+                    &mut hash_table,
+                    buf_decompressed,
+                    &mut next_to_hash,
+                    out_pos - 3,
+                );
+            }
+        } else {
+            // rest of the packet is literals, their control bits are not checked
+            while out_pos < size {
+                if control_word == 1 {
+                    in_pos += CONTROL_WORD_SIZE;
+                    control_word = CONTROL_WORD_END;
+                }
+                buf_decompressed[out_pos] = *buf_compressed.get(in_pos).ok_or_else(truncated)?;
+                in_pos += 1;
+                out_pos += 1;
+                control_word >>= 1;
+            }
+            return Ok(size);
+        }
+    }
+}
+
+// hashes all positions from `next_to_hash` to `last` inclusive
+fn hash_up_to(hash_table: &mut [usize], buf: &[u8], next_to_hash: &mut usize, last: usize) {
+    while *next_to_hash <= last {
+        let pos = *next_to_hash;
+        let fetch = u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], 0]);
+        hash_table[(((fetch >> 12) ^ fetch) as usize) & (LEVEL_1_HASH_VALUES - 1)] = pos;
+        *next_to_hash += 1;
+    }
+}
+
+fn read_u32_le(buf_compressed: &[u8], in_pos: usize) -> error::Result<u32> {
+    let bytes = buf_compressed
+        .get(in_pos..in_pos + 4)
+        .ok_or_else(truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// up to 4 bytes, zero padded at the end of input; callers check how many were actually used
+fn peek_u32_le(buf_compressed: &[u8], in_pos: usize) -> u32 {
+    let mut bytes = [0_u8; 4];
+    let available = &buf_compressed[in_pos.min(buf_compressed.len())..];
+    let len = available.len().min(4);
+    bytes[..len].copy_from_slice(&available[..len]);
+    u32::from_le_bytes(bytes)
+}
+
+fn bad_reference() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "quicklz back reference points outside of output",
+    )
+}
+
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "quicklz compressed data is truncated",
+    )
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "quicklz decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT_DATA: &[u8] = b"the quick brown fox, the quick brown fox, the quick brown dog";
+    const LEVEL_1_SHORT: &[u8] =
+        b"\x45\x23\x3d\x00\x00\x20\x80the quick brown fox, \x20\xe2\x24 dog";
+    const LEVEL_3_SHORT: &[u8] =
+        b"\x4d\x24\x3d\x00\x00\x20\x80the quick brown fox, \x83\x90\x0a\x00 dog";
+    const LEVEL_1_LONG: &[u8] = b"\x47\x48\x00\x00\x00\x2c\x01\x00\x00\x00\x00\x00\x80\
+        abababababababababababababababa\x00\x00\x02\x80babababab\
+        quicklz \x60\x3e\xf8quic";
+    const LEVEL_3_LONG: &[u8] = b"\x4f\x23\x00\x00\x00\x2c\x01\x00\x00\x04\x08\x00\x80ab\x83\x11\
+        \x01\x00quicklz \x83\x7a\x04\x00quic";
+    const UNCOMPRESSED: &[u8] = b"\x44\x10\x0dstored packet";
+
+    // 300 bytes, which takes a long header
+    fn long_data() -> Vec<u8> {
+        let mut data = [b"ab".repeat(20), b"quicklz ".repeat(40)].concat();
+        data.truncate(300);
+        data
+    }
+
+    fn packets() -> [(&'static [u8], Vec<u8>); 5] {
+        [
+            (LEVEL_1_SHORT, SHORT_DATA.to_vec()),
+            (LEVEL_3_SHORT, SHORT_DATA.to_vec()),
+            (LEVEL_1_LONG, long_data()),
+            (LEVEL_3_LONG, long_data()),
+            (UNCOMPRESSED, b"stored packet".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn packets_are_decompressed() {
+        for (packet, data) in packets() {
+            assert_eq!(quicklz_size_compressed(packet).unwrap(), packet.len());
+            assert_eq!(quicklz_size_decompressed(packet).unwrap(), data.len());
+
+            // followed by the next packet
+            let buf_compressed = [packet, UNCOMPRESSED].concat();
+            let mut buf_decompressed = vec![0; data.len() + 10];
+            let len = quicklz_decompress(&buf_compressed, &mut buf_decompressed).unwrap();
+            assert_eq!(buf_decompressed[..len], data);
+        }
+    }
+
+    #[test]
+    fn malformed_packets_are_errors() {
+        let mut buf_decompressed = [0_u8; 512];
+        for (packet, data) in packets() {
+            for len in 0..packet.len() {
+                let err = quicklz_decompress(&packet[..len], &mut buf_decompressed).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::InvalidData, "{packet:?} {len}");
+            }
+            let err =
+                quicklz_decompress(packet, &mut buf_decompressed[..data.len() - 1]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            // no single bit off makes decompression panic
+            for pos in 0..packet.len() {
+                for bit in 0..8 {
+                    let mut corrupt = packet.to_vec();
+                    corrupt[pos] ^= 1 << bit;
+                    let _ = quicklz_decompress(&corrupt, &mut buf_decompressed);
+                }
+            }
+        }
+
+        let invalid: [(&[u8], ErrorKind); 4] = [
+            // level 2, and a streaming buffer
+            (b"\x49\x10\x0dstored packet", ErrorKind::Unsupported),
+            (b"\x55\x10\x0dstored packet", ErrorKind::Unsupported),
+            // uncompressed packet whose sizes differ
+            (b"\x44\x10\x0cstored packet", ErrorKind::InvalidData),
+            // compressed size not even covering the header
+            (b"\x44\x02\x00", ErrorKind::InvalidData),
+        ];
+        for (packet, kind) in invalid {
+            let err = quicklz_decompress(packet, &mut buf_decompressed).unwrap_err();
+            assert_eq!(err.kind(), kind, "{packet:?}");
+        }
+    }
+}
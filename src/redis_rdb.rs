@@ -0,0 +1,135 @@
+use std::io::{self, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+
+use crate::lzf_decompress::{lzf_decompress, LZF_MAX_EXPANSION};
+
+// Helpers for reading strings out of Redis RDB dumps, see `rdbLoadLen` and
+// `rdbGenericLoadStringObject` in https://github.com/redis/redis/blob/unstable/src/rdb.c
+// Redis stores large strings as raw lzf payloads with no "ZV" framing around them.
+
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+const RDB_ENCVAL: u8 = 3;
+
+pub const RDB_ENC_INT8: u8 = 0;
+pub const RDB_ENC_INT16: u8 = 1;
+pub const RDB_ENC_INT32: u8 = 2;
+pub const RDB_ENC_LZF: u8 = 3;
+
+/// Result of parsing Redis length encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdbLength {
+    /// Plain length.
+    Length(u64),
+    /// Special encoding of the value that follows, one of the `RDB_ENC_*` constants.
+    Encoded(u8),
+}
+
+pub fn read_rdb_length<R: Read>(reader: &mut R) -> io::Result<RdbLength> {
+    let first = reader.read_u8()?;
+    let length = match first >> 6 {
+        RDB_6BITLEN => (first & 0x3F) as u64,
+        RDB_14BITLEN => (((first & 0x3F) as u64) << 8) | reader.read_u8()? as u64,
+        RDB_ENCVAL => return Ok(RdbLength::Encoded(first & 0x3F)),
+        _ => match first {
+            RDB_32BITLEN => reader.read_u32::<BigEndian>()? as u64,
+            RDB_64BITLEN => reader.read_u64::<BigEndian>()?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "unknown rdb length encoding",
+                ))
+            }
+        },
+    };
+    Ok(RdbLength::Length(length))
+}
+
+/// Reads a length that is not allowed to use special encoding, such as the size of a set.
+pub fn read_rdb_plain_length<R: Read>(reader: &mut R) -> io::Result<u64> {
+    match read_rdb_length(reader)? {
+        RdbLength::Length(length) => Ok(length),
+        RdbLength::Encoded(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unexpected rdb special encoding",
+        )),
+    }
+}
+
+/// Reads a string object, such as a key or a string value, in any of its encodings.
+/// Integer encoded strings are returned in their decimal representation, same as Redis does.
+pub fn read_rdb_string<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    match read_rdb_length(reader)? {
+        RdbLength::Length(length) => {
+            let length = usize::try_from(length).map_err(|_| too_large())?;
+            let mut buf = Vec::new();
+            reader.take(length as u64).read_to_end(&mut buf)?;
+            if buf.len() != length {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "rdb string is truncated",
+                ));
+            }
+            Ok(buf)
+        }
+        RdbLength::Encoded(RDB_ENC_INT8) => Ok(reader.read_i8()?.to_string().into_bytes()),
+        RdbLength::Encoded(RDB_ENC_INT16) => {
+            Ok(reader.read_i16::<LittleEndian>()?.to_string().into_bytes())
+        }
+        RdbLength::Encoded(RDB_ENC_INT32) => {
+            Ok(reader.read_i32::<LittleEndian>()?.to_string().into_bytes())
+        }
+        RdbLength::Encoded(RDB_ENC_LZF) => read_rdb_lzf_payload(reader),
+        RdbLength::Encoded(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unknown rdb string encoding",
+        )),
+    }
+}
+
+/// Reads the part of an lzf compressed string that follows its 0xC3 encoding byte:
+/// compressed length, uncompressed length and the raw lzf payload.
+pub fn read_rdb_lzf_payload<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let compressed_length = read_rdb_plain_length(reader)?;
+    let original_length = read_rdb_plain_length(reader)?;
+
+    let compressed_length = usize::try_from(compressed_length).map_err(|_| too_large())?;
+    let original_length = usize::try_from(original_length).map_err(|_| too_large())?;
+
+    // check before allocating, so that a corrupt length can't make us allocate a lot of memory
+    if original_length / LZF_MAX_EXPANSION > compressed_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "rdb lzf uncompressed length is too large for compressed data",
+        ));
+    }
+
+    let mut buf_compressed = Vec::new();
+    reader
+        .take(compressed_length as u64)
+        .read_to_end(&mut buf_compressed)?;
+    if buf_compressed.len() != compressed_length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "rdb lzf string is truncated",
+        ));
+    }
+
+    let mut buf_decompressed = vec![0; original_length];
+    let decompressed_length = lzf_decompress(&buf_compressed, &mut buf_decompressed)?;
+    if decompressed_length != original_length {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "rdb lzf uncompressed length mismatch",
+        ));
+    }
+
+    Ok(buf_decompressed)
+}
+
+fn too_large() -> Error {
+    Error::new(ErrorKind::InvalidData, "rdb length is too large")
+}
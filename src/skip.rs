@@ -0,0 +1,34 @@
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+
+/// Skips exactly `n` bytes by reading and discarding them.
+pub(crate) fn skip_by_reading<R: Read>(reader: &mut R, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.by_ref().take(n), &mut io::sink())?;
+    if skipped != n {
+        return Err(unexpected_eof());
+    }
+    Ok(())
+}
+
+/// Skips exactly `n` bytes by seeking, `stream_len` is used to detect skipping past the end.
+pub(crate) fn skip_by_seeking<R: Seek>(reader: &mut R, n: u64, stream_len: u64) -> io::Result<()> {
+    let n = i64::try_from(n).map_err(|_| unexpected_eof())?;
+    if reader.seek(SeekFrom::Current(n))? > stream_len {
+        return Err(unexpected_eof());
+    }
+    Ok(())
+}
+
+/// Length of the whole stream, leaving its position unchanged.
+pub(crate) fn stream_len<R: Seek>(reader: &mut R) -> io::Result<u64> {
+    let pos = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(pos))?;
+    Ok(len)
+}
+
+fn unexpected_eof() -> Error {
+    Error::new(
+        ErrorKind::UnexpectedEof,
+        "stream ends in the middle of a block",
+    )
+}
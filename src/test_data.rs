@@ -0,0 +1,86 @@
+use alloc::vec::Vec;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+/// `len` bytes of runs of repeated text, which compress, mixed with noise, which does not, so
+/// that streams made of it have both compressed and stored blocks.
+pub(crate) fn sample_data(len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    let mut seed = 12345_u32;
+    let mut next = || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        seed
+    };
+    while data.len() < len {
+        let n = next();
+        if n >> 30 == 0 {
+            for _ in 0..n % 2000 {
+                data.push((next() >> 24) as u8);
+            }
+        } else {
+            data.extend_from_slice(&b"lorem ipsum dolor sit amet "[..(n % 27) as usize]);
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+/// `len` bytes that do not compress.
+pub(crate) fn noise(len: usize) -> Vec<u8> {
+    let mut seed = 54321_u32;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 24) as u8
+        })
+        .collect()
+}
+
+/// Seeks `reader` over decompressed `data` made of blocks of `block_size`, to each side of block
+/// boundaries, within the current block, to the end and past it, checking what is read there.
+pub(crate) fn check_seeks<R: Read + Seek>(mut reader: R, data: &[u8], block_size: usize) {
+    let len = data.len() as u64;
+    let mut targets = Vec::new();
+    for boundary in (0..data.len()).step_by(block_size) {
+        targets.extend([boundary.saturating_sub(1), boundary, boundary + 1]);
+    }
+    // backwards too, so that earlier blocks are decompressed again
+    targets.extend([data.len() - 1, 3 * block_size / 2, 2]);
+
+    let mut buf = [0_u8; 16];
+    for target in targets {
+        assert_eq!(
+            reader.seek(SeekFrom::Start(target as u64)).unwrap(),
+            target as u64
+        );
+        let n = reader.read(&mut buf).unwrap();
+        assert!(n > 0, "{target}");
+        assert_eq!(buf[..n], data[target..target + n], "{target}");
+    }
+
+    // within the block just read, relative to the current position and to the end
+    reader.seek(SeekFrom::Start(2)).unwrap();
+    reader.read_exact(&mut buf[..4]).unwrap();
+    assert_eq!(reader.seek(SeekFrom::Current(-3)).unwrap(), 3);
+    reader.read_exact(&mut buf[..4]).unwrap();
+    assert_eq!(buf[..4], data[3..7]);
+    assert_eq!(reader.seek(SeekFrom::End(-5)).unwrap(), len - 5);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, data[data.len() - 5..]);
+
+    // at and past the end there is nothing to read, but reading still works after seeking back
+    for target in [len, len + 1000] {
+        assert_eq!(reader.seek(SeekFrom::Start(target)).unwrap(), target);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+    assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), len);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    let err = reader
+        .seek(SeekFrom::Current(-(len as i64) - 1))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    let mut all = Vec::new();
+    reader.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+}
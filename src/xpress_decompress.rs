@@ -0,0 +1,304 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::back_reference::copy_back_reference;
+use crate::huffman::Huffman;
+
+// Plain LZ77 and LZ77+Huffman variants of Xpress, see sections 2.2 and 2.4 of
+// https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-xca/
+
+const HUFFMAN_SYMBOLS: usize = 512;
+const HUFFMAN_TABLE_SIZE: usize = HUFFMAN_SYMBOLS / 2;
+const HUFFMAN_MAX_CODE_LENGTH: usize = 15;
+const HUFFMAN_BLOCK_SIZE: usize = 65536;
+const HUFFMAN_END_OF_STREAM: u16 = 256;
+
+/// goal is to match `RtlDecompressBuffer` with `COMPRESSION_FORMAT_XPRESS`
+///
+/// Decoding stops when input is exhausted, the number of decompressed bytes is returned.
+pub fn xpress_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let mut flags = 0_u32;
+    let mut flag_count = 0;
+    // position of the byte whose upper half holds the next shared length nibble, 0 if none
+    let mut last_length_half_byte = 0;
+
+    loop {
+        if flag_count == 0 {
+            if in_pos == buf_compressed.len() {
+                return Ok(out_pos);
+            }
+            flags = read_u32_le(buf_compressed, &mut in_pos)?;
+            flag_count = 32;
+        }
+        flag_count -= 1;
+
+        if in_pos == buf_compressed.len() {
+            return Ok(out_pos);
+        }
+
+        if flags & (1 << flag_count) == 0 {
+            // literal
+            if out_pos >= buf_decompressed.len() {
+                return Err(output_overflow());
+            }
+            buf_decompressed[out_pos] = buf_compressed[in_pos];
+            in_pos += 1;
+            out_pos += 1;
+        } else {
+            // back reference
+            let match_bytes = read_u16_le(buf_compressed, &mut in_pos)? as usize;
+            let ref_offset = (match_bytes >> 3) + 1;
+            let mut run_len = match_bytes & 0x07;
+
+            if run_len == 7 {
+                if last_length_half_byte == 0 {
+                    last_length_half_byte = in_pos;
+                    run_len = (read_u8(buf_compressed, &mut in_pos)? & 0x0F) as usize;
+                } else {
+                    run_len = (buf_compressed[last_length_half_byte] >> 4) as usize;
+                    last_length_half_byte = 0;
+                }
+
+                if run_len == 15 {
+                    run_len = read_u8(buf_compressed, &mut in_pos)? as usize;
+                    if run_len == 255 {
+                        run_len = read_long_length(buf_compressed, &mut in_pos, 15 + 7)?;
+                    }
+                    run_len += 15;
+                }
+                run_len += 7;
+            }
+            run_len += 3;
+
+            copy_match(buf_decompressed, out_pos, ref_offset, run_len)?;
+            out_pos += run_len;
+        }
+    }
+}
+
+/// goal is to match `RtlDecompressBuffer` with `COMPRESSION_FORMAT_XPRESS_HUFF`
+///
+/// Decoding is driven by the expected output size, as the format has no reliable end marker:
+/// it stops once `buf_decompressed` is full, or earlier at an end of stream symbol that
+/// coincides with the end of input. The number of decompressed bytes is returned.
+pub fn xpress_huffman_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+
+    while out_pos < buf_decompressed.len() {
+        // every block starts with 512 4-bit code lengths, low nibble first
+        if buf_compressed.len() - in_pos < HUFFMAN_TABLE_SIZE {
+            return Err(truncated());
+        }
+        let mut lengths = [0_u8; HUFFMAN_SYMBOLS];
+        for (i, &byte) in buf_compressed[in_pos..in_pos + HUFFMAN_TABLE_SIZE]
+            .iter()
+            .enumerate()
+        {
+            lengths[2 * i] = byte & 0x0F;
+            lengths[2 * i + 1] = byte >> 4;
+        }
+        in_pos += HUFFMAN_TABLE_SIZE;
+        let huffman = Huffman::new(&lengths, HUFFMAN_MAX_CODE_LENGTH)?;
+
+        let mut bits = HuffmanBitReader::new(buf_compressed, &mut in_pos)?;
+        let block_end = out_pos + HUFFMAN_BLOCK_SIZE;
+
+        while out_pos < block_end && out_pos < buf_decompressed.len() {
+            let symbol = bits.decode_symbol(&huffman, buf_compressed, &mut in_pos)?;
+
+            if symbol < 256 {
+                buf_decompressed[out_pos] = symbol as u8;
+                out_pos += 1;
+                continue;
+            }
+            if symbol == HUFFMAN_END_OF_STREAM && in_pos == buf_compressed.len() {
+                return Ok(out_pos);
+            }
+
+            let symbol = (symbol - 256) as usize;
+            let offset_bits = (symbol >> 4) as u32;
+            let mut run_len = symbol & 0x0F;
+            if run_len == 15 {
+                run_len = read_u8(buf_compressed, &mut in_pos)? as usize;
+                if run_len == 255 {
+                    run_len = read_long_length(buf_compressed, &mut in_pos, 15)?;
+                }
+                run_len += 15;
+            }
+            run_len += 3;
+
+            let ref_offset = (bits.pull(offset_bits, buf_compressed, &mut in_pos)? as usize)
+                + (1 << offset_bits);
+
+            copy_match(buf_decompressed, out_pos, ref_offset, run_len)?;
+            out_pos += run_len;
+        }
+    }
+
+    Ok(out_pos)
+}
+
+// matches NextBits and ExtraBitCount of the spec: 32-bit window with the next code bits on top,
+// refilled with 16-bit little-endian words taken from the same input as the extra length bytes
+struct HuffmanBitReader {
+    next_bits: u32,
+    extra_bit_count: i32,
+}
+
+impl HuffmanBitReader {
+    fn new(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<HuffmanBitReader> {
+        let high = read_u16_le(buf_compressed, in_pos)? as u32;
+        let low = read_u16_le(buf_compressed, in_pos)? as u32;
+        Ok(HuffmanBitReader {
+            next_bits: (high << 16) | low,
+            extra_bit_count: 16,
+        })
+    }
+
+    fn decode_symbol(
+        &mut self,
+        huffman: &Huffman,
+        buf_compressed: &[u8],
+        in_pos: &mut usize,
+    ) -> error::Result<u16> {
+        // codes are at most 15 bits long, so they are always available in next_bits
+        let next_15_bits = self.next_bits >> (32 - 15);
+        let mut code_len = 0;
+        let symbol = huffman.decode(|| {
+            code_len += 1;
+            Ok((next_15_bits >> (15 - code_len)) & 1)
+        })?;
+        self.consume(code_len, buf_compressed, in_pos)?;
+        Ok(symbol)
+    }
+
+    fn pull(&mut self, n: u32, buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.next_bits >> (32 - n);
+        self.consume(n, buf_compressed, in_pos)?;
+        Ok(value)
+    }
+
+    fn consume(&mut self, n: u32, buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<()> {
+        // n is at most 15, while at least 16 bits are always loaded
+        self.next_bits <<= n;
+        self.extra_bit_count -= n as i32;
+        if self.extra_bit_count < 0 {
+            // the final word of input may be missing, spec treats out of range reads as zeros
+            let word = if buf_compressed.len() - *in_pos >= 2 {
+                read_u16_le(buf_compressed, in_pos)? as u32
+            } else if *in_pos == buf_compressed.len() {
+                0
+            } else {
+                return Err(truncated());
+            };
+            self.next_bits |= word << (-self.extra_bit_count);
+            self.extra_bit_count += 16;
+        }
+        Ok(())
+    }
+}
+
+// 2-byte length following a 255 byte, or 4-byte length if the 2-byte one is 0;
+// `bias` is the part of length already covered by the shorter encodings
+fn read_long_length(
+    buf_compressed: &[u8],
+    in_pos: &mut usize,
+    bias: usize,
+) -> error::Result<usize> {
+    let mut run_len = read_u16_le(buf_compressed, in_pos)? as usize;
+    if run_len == 0 {
+        run_len = read_u32_le(buf_compressed, in_pos)? as usize;
+    }
+    if run_len < bias {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "xpress match length is too short for its encoding",
+        ));
+    }
+    Ok(run_len - bias)
+}
+
+fn copy_match(
+    buf_decompressed: &mut [u8],
+    out_pos: usize,
+    ref_offset: usize,
+    run_len: usize,
+) -> error::Result<()> {
+    if ref_offset > out_pos {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "xpress back reference points before start of output",
+        ));
+    }
+    if buf_decompressed.len() - out_pos < run_len {
+        return Err(output_overflow());
+    }
+    copy_back_reference(buf_decompressed, out_pos, ref_offset, run_len);
+    Ok(())
+}
+
+fn read_u8(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u8> {
+    let byte = *buf_compressed.get(*in_pos).ok_or_else(truncated)?;
+    *in_pos += 1;
+    Ok(byte)
+}
+
+fn read_u16_le(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u16> {
+    let bytes = buf_compressed
+        .get(*in_pos..*in_pos + 2)
+        .ok_or_else(truncated)?;
+    *in_pos += 2;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32_le(buf_compressed: &[u8], in_pos: &mut usize) -> error::Result<u32> {
+    let bytes = buf_compressed
+        .get(*in_pos..*in_pos + 4)
+        .ok_or_else(truncated)?;
+    *in_pos += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn truncated() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "xpress compressed data is truncated",
+    )
+}
+
+fn output_overflow() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "xpress decompressed data does not fit into output buffer",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn bits_outside_of_incomplete_code_are_rejected() {
+        // only symbol 0 has a code, so a run of 1 bits never reaches a symbol
+        let mut buf_compressed = vec![0_u8; HUFFMAN_TABLE_SIZE];
+        buf_compressed[0] = 0x01;
+        buf_compressed.extend_from_slice(&[0xFF; 8]);
+
+        let mut buf_decompressed = [0_u8; 16];
+        let err = xpress_huffman_decompress(&buf_compressed, &mut buf_decompressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
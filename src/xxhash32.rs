@@ -9,7 +9,7 @@ pub struct XXHash32 {
     v2: u32,
     v3: u32,
     v4: u32,
-    buf: Box<[u8]>,
+    buf: [u8; 16],
     buf_used: usize,
     did_at_least_one_full_round: bool,
     total_len_mod_32_bit: u32,
@@ -22,7 +22,7 @@ impl XXHash32 {
             v2: seed.wrapping_add(PRIME32_2),
             v3: seed,
             v4: seed.wrapping_sub(PRIME32_1),
-            buf: vec![0; 16].into_boxed_slice(),
+            buf: [0; 16],
             buf_used: 0,
             did_at_least_one_full_round: false,
             total_len_mod_32_bit: 0,
@@ -0,0 +1,39 @@
+use crate::error::{self, Error, ErrorKind};
+
+use crate::lz4_jblock_decompress::lz4_jblock_decompress;
+
+const SIZE_PREFIX_LEN: usize = 4;
+
+/// goal is to match `lz4_decompress_zfs` from ZFS:
+/// https://github.com/openzfs/zfs/blob/master/module/zfs/lz4_zfs.c
+///
+/// ZFS stores a big-endian u32 with the length of the raw lz4 block in front of it, because the
+/// physical block is padded up to the sector size. `buf_compressed` is the whole physical block,
+/// and `buf_decompressed` has to be the logical size of the block, which is filled exactly.
+pub fn zfs_lz4_decompress(
+    buf_compressed: &[u8],
+    buf_decompressed: &mut [u8],
+) -> error::Result<usize> {
+    if buf_compressed.len() < SIZE_PREFIX_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "zfs lz4 size prefix is truncated",
+        ));
+    }
+
+    let (size_prefix, buf_compressed) = buf_compressed.split_at(SIZE_PREFIX_LEN);
+    let compressed_length = u32::from_be_bytes([
+        size_prefix[0],
+        size_prefix[1],
+        size_prefix[2],
+        size_prefix[3],
+    ]) as usize;
+    if compressed_length > buf_compressed.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "zfs lz4 compressed length is larger than the block",
+        ));
+    }
+
+    lz4_jblock_decompress(&buf_compressed[..compressed_length], buf_decompressed)
+}
@@ -0,0 +1,624 @@
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::crc32::Crc32;
+use crate::error;
+use crate::inflate::inflate;
+
+// Zip archives as described in https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+// Entries are located through the central directory at the end of archive, their data is
+// preceded by a local header and may be followed by a data descriptor.
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_EOCD_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+const EOCD_SIZE: u64 = 22;
+const ZIP64_EOCD_LOCATOR_SIZE: u64 = 20;
+const MAX_COMMENT_SIZE: u64 = 0xFFFF;
+const LOCAL_HEADER_SIZE: u64 = 30;
+
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_DEFLATED: u16 = 8;
+
+/// Limit on the output buffer of a registered method until `set_max_decompressed_length` is
+/// called.
+pub const DEFAULT_MAX_DECOMPRESSED_LENGTH: usize = 256 * 1024 * 1024;
+
+/// Decompressor for an additional compression method, in the same form as the block
+/// decompressors of this crate, so that those can be registered as they are: it gets the whole
+/// compressed data of an entry and an output buffer of exactly the size the central directory
+/// declares, and returns the decompressed length.
+pub type ZipMethodDecompressor = fn(&[u8], &mut [u8]) -> error::Result<usize>;
+
+/// An entry of the central directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    pub compression_method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// MS-DOS date and time.
+    pub last_modified_date: u16,
+    pub last_modified_time: u16,
+    pub external_attributes: u32,
+    pub comment: Vec<u8>,
+    local_header_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Lists and extracts entries of a zip (or jar) archive. Stored and deflated entries are
+/// supported out of the box, other methods can be added with `register_method`.
+pub struct ZipReader<R> {
+    inner: R,
+    entries: Vec<ZipEntry>,
+    comment: Vec<u8>,
+    methods: Vec<(u16, ZipMethodDecompressor)>,
+    max_decompressed_length: usize,
+}
+
+impl<R: Read + Seek> ZipReader<R> {
+    pub fn new(mut reader: R) -> io::Result<ZipReader<R>> {
+        let (eocd_pos, eocd) = find_eocd(&mut reader)?;
+
+        // `archive_offset` accounts for data prepended to the archive, such as a
+        // self-extractor stub, which shifts all offsets recorded in it
+        let (entry_count, cd_size, cd_offset, archive_offset) =
+            match read_zip64_eocd(&mut reader, eocd_pos)? {
+                Some(zip64) => (zip64.entry_count, zip64.cd_size, zip64.cd_offset, 0),
+                None => {
+                    if eocd.disk != 0
+                        || eocd.cd_disk != 0
+                        || eocd.entries_this_disk != eocd.entry_count
+                    {
+                        return Err(multi_disk());
+                    }
+                    let cd_end = eocd.cd_offset as u64 + eocd.cd_size as u64;
+                    let archive_offset = eocd_pos.checked_sub(cd_end).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            "zip central directory overlaps its end record",
+                        )
+                    })?;
+                    (
+                        eocd.entry_count as u64,
+                        eocd.cd_size as u64,
+                        eocd.cd_offset as u64,
+                        archive_offset,
+                    )
+                }
+            };
+
+        reader.seek(SeekFrom::Start(archive_offset + cd_offset))?;
+        let mut buf_cd = Vec::new();
+        reader.by_ref().take(cd_size).read_to_end(&mut buf_cd)?;
+        if buf_cd.len() as u64 != cd_size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "zip central directory is truncated",
+            ));
+        }
+
+        let mut cd = &buf_cd[..];
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            let mut entry = read_central_header(&mut cd)?;
+            entry.local_header_offset += archive_offset;
+            entries.push(entry);
+        }
+
+        Ok(ZipReader {
+            inner: reader,
+            entries,
+            comment: eocd.comment,
+            methods: Vec::new(),
+            max_decompressed_length: DEFAULT_MAX_DECOMPRESSED_LENGTH,
+        })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Archive comment from the end of central directory record.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// Index of the entry called `name`, if there is one.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// Makes entries compressed with `method` extractable, replacing any previously registered
+    /// decompressor for it. Stored and deflated entries are always handled by the reader itself.
+    pub fn register_method(&mut self, method: u16, decompressor: ZipMethodDecompressor) {
+        self.methods.retain(|&(m, _)| m != method);
+        self.methods.push((method, decompressor));
+    }
+
+    /// Registered decompressors get an output buffer of the size the central directory declares
+    /// up front, entries of those methods declaring more than `max_decompressed_length` are
+    /// refused rather than allocated for. Stored and deflated entries only grow their buffers as
+    /// data comes in and are not limited.
+    pub fn set_max_decompressed_length(&mut self, max_decompressed_length: usize) {
+        self.max_decompressed_length = max_decompressed_length;
+    }
+
+    /// Extracts the entry at `index` in `entries()`, verifying its CRC-32.
+    pub fn read_entry(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "zip entry index out of range"))?;
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "encrypted zip entries are not supported",
+            ));
+        }
+        let uncompressed_size = usize::try_from(entry.uncompressed_size)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "zip entry is too large"))?;
+
+        // local header repeats most of the central one, only its name and extra field lengths
+        // are needed to find where the data starts
+        self.inner
+            .seek(SeekFrom::Start(entry.local_header_offset))?;
+        if self.inner.read_u32::<LittleEndian>()? != LOCAL_HEADER_SIGNATURE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "wrong zip local header signature",
+            ));
+        }
+        self.inner
+            .seek(SeekFrom::Current(LOCAL_HEADER_SIZE as i64 - 8))?;
+        let name_length = self.inner.read_u16::<LittleEndian>()?;
+        let extra_length = self.inner.read_u16::<LittleEndian>()?;
+        self.inner
+            .seek(SeekFrom::Current(name_length as i64 + extra_length as i64))?;
+
+        let mut buf_compressed = Vec::new();
+        self.inner
+            .by_ref()
+            .take(entry.compressed_size)
+            .read_to_end(&mut buf_compressed)?;
+        if buf_compressed.len() as u64 != entry.compressed_size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "zip entry data is truncated",
+            ));
+        }
+
+        if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+            // crc and sizes were not known when the local header was written and follow the
+            // data instead, with an optional signature in front of them
+            let mut crc32 = self.inner.read_u32::<LittleEndian>()?;
+            if crc32 == DATA_DESCRIPTOR_SIGNATURE && entry.crc32 != DATA_DESCRIPTOR_SIGNATURE {
+                crc32 = self.inner.read_u32::<LittleEndian>()?;
+            }
+            if crc32 != entry.crc32 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "zip data descriptor does not match central directory",
+                ));
+            }
+        }
+
+        let buf_decompressed = match entry.compression_method {
+            METHOD_STORED => {
+                if buf_compressed.len() != uncompressed_size {
+                    return Err(size_mismatch());
+                }
+                buf_compressed
+            }
+            METHOD_DEFLATED => {
+                let mut buf_decompressed = Vec::new();
+                inflate(&buf_compressed, &mut buf_decompressed, uncompressed_size)?;
+                buf_decompressed
+            }
+            method => {
+                let decompressor = self
+                    .methods
+                    .iter()
+                    .find(|&&(m, _)| m == method)
+                    .map(|&(_, decompressor)| decompressor)
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::Unsupported, "unsupported zip compression method")
+                    })?;
+                if uncompressed_size > self.max_decompressed_length {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "zip entry size is over the allowed maximum",
+                    ));
+                }
+                let mut buf_decompressed = vec![0; uncompressed_size];
+                let decompressed_length = decompressor(&buf_compressed, &mut buf_decompressed)?;
+                buf_decompressed.truncate(decompressed_length);
+                buf_decompressed
+            }
+        };
+
+        if buf_decompressed.len() != uncompressed_size {
+            return Err(size_mismatch());
+        }
+
+        let mut crc32 = Crc32::new();
+        crc32.update(&buf_decompressed);
+        if crc32.digest() != entry.crc32 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "zip entry crc32 mismatch",
+            ));
+        }
+
+        Ok(buf_decompressed)
+    }
+}
+
+struct Eocd {
+    disk: u16,
+    cd_disk: u16,
+    entries_this_disk: u16,
+    entry_count: u16,
+    cd_size: u32,
+    cd_offset: u32,
+    comment: Vec<u8>,
+}
+
+struct Zip64Eocd {
+    entry_count: u64,
+    cd_size: u64,
+    cd_offset: u64,
+}
+
+// end of central directory record is the last thing in the archive, but it is followed by a
+// comment of unknown length, so its signature is searched for backwards
+fn find_eocd<R: Read + Seek>(reader: &mut R) -> io::Result<(u64, Eocd)> {
+    let file_length = reader.seek(SeekFrom::End(0))?;
+    let search_length = file_length.min(EOCD_SIZE + MAX_COMMENT_SIZE);
+    let search_start = file_length - search_length;
+
+    let mut buf = vec![0; search_length as usize];
+    reader.seek(SeekFrom::Start(search_start))?;
+    reader.read_exact(&mut buf)?;
+
+    let signature = EOCD_SIGNATURE.to_le_bytes();
+    let mut pos = buf
+        .len()
+        .checked_sub(EOCD_SIZE as usize)
+        .ok_or_else(no_eocd)?;
+    // record whose comment ends before the end of file, some tools append data after archives
+    let mut found = None;
+    loop {
+        if buf[pos..pos + 4] == signature {
+            let mut record = &buf[pos + 4..];
+            let disk = record.read_u16::<LittleEndian>()?;
+            let cd_disk = record.read_u16::<LittleEndian>()?;
+            let entries_this_disk = record.read_u16::<LittleEndian>()?;
+            let entry_count = record.read_u16::<LittleEndian>()?;
+            let cd_size = record.read_u32::<LittleEndian>()?;
+            let cd_offset = record.read_u32::<LittleEndian>()?;
+            let comment_length = record.read_u16::<LittleEndian>()? as usize;
+
+            if comment_length <= record.len() {
+                let eocd = Eocd {
+                    disk,
+                    cd_disk,
+                    entries_this_disk,
+                    entry_count,
+                    cd_size,
+                    cd_offset,
+                    comment: record[..comment_length].to_vec(),
+                };
+                // a signature inside the comment of the real record would not have the comment
+                // reach exactly up to the end of file, so such a record is preferred
+                if comment_length == record.len() {
+                    return Ok((search_start + pos as u64, eocd));
+                }
+                if found.is_none() {
+                    found = Some((search_start + pos as u64, eocd));
+                }
+            }
+        }
+        if pos == 0 {
+            return found.ok_or_else(no_eocd);
+        }
+        pos -= 1;
+    }
+}
+
+fn read_zip64_eocd<R: Read + Seek>(reader: &mut R, eocd_pos: u64) -> io::Result<Option<Zip64Eocd>> {
+    let locator_pos = match eocd_pos.checked_sub(ZIP64_EOCD_LOCATOR_SIZE) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    reader.seek(SeekFrom::Start(locator_pos))?;
+    if reader.read_u32::<LittleEndian>()? != ZIP64_EOCD_LOCATOR_SIGNATURE {
+        return Ok(None);
+    }
+    let eocd_disk = reader.read_u32::<LittleEndian>()?;
+    let eocd_offset = reader.read_u64::<LittleEndian>()?;
+    let disk_count = reader.read_u32::<LittleEndian>()?;
+    if eocd_disk != 0 || disk_count > 1 {
+        return Err(multi_disk());
+    }
+
+    reader.seek(SeekFrom::Start(eocd_offset))?;
+    if reader.read_u32::<LittleEndian>()? != ZIP64_EOCD_SIGNATURE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "wrong zip64 end of central directory signature",
+        ));
+    }
+    let _record_size = reader.read_u64::<LittleEndian>()?;
+    let _version_made_by = reader.read_u16::<LittleEndian>()?;
+    let _version_needed = reader.read_u16::<LittleEndian>()?;
+    let disk = reader.read_u32::<LittleEndian>()?;
+    let cd_disk = reader.read_u32::<LittleEndian>()?;
+    let entries_this_disk = reader.read_u64::<LittleEndian>()?;
+    let entry_count = reader.read_u64::<LittleEndian>()?;
+    let cd_size = reader.read_u64::<LittleEndian>()?;
+    let cd_offset = reader.read_u64::<LittleEndian>()?;
+    if disk != 0 || cd_disk != 0 || entries_this_disk != entry_count {
+        return Err(multi_disk());
+    }
+
+    Ok(Some(Zip64Eocd {
+        entry_count,
+        cd_size,
+        cd_offset,
+    }))
+}
+
+fn read_central_header(cd: &mut &[u8]) -> io::Result<ZipEntry> {
+    if cd.read_u32::<LittleEndian>()? != CENTRAL_HEADER_SIGNATURE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "wrong zip central header signature",
+        ));
+    }
+    let _version_made_by = cd.read_u16::<LittleEndian>()?;
+    let _version_needed = cd.read_u16::<LittleEndian>()?;
+    let flags = cd.read_u16::<LittleEndian>()?;
+    let compression_method = cd.read_u16::<LittleEndian>()?;
+    let last_modified_time = cd.read_u16::<LittleEndian>()?;
+    let last_modified_date = cd.read_u16::<LittleEndian>()?;
+    let crc32 = cd.read_u32::<LittleEndian>()?;
+    let mut compressed_size = cd.read_u32::<LittleEndian>()? as u64;
+    let mut uncompressed_size = cd.read_u32::<LittleEndian>()? as u64;
+    let name_length = cd.read_u16::<LittleEndian>()? as usize;
+    let extra_length = cd.read_u16::<LittleEndian>()? as usize;
+    let comment_length = cd.read_u16::<LittleEndian>()? as usize;
+    let _disk_start = cd.read_u16::<LittleEndian>()?;
+    let _internal_attributes = cd.read_u16::<LittleEndian>()?;
+    let external_attributes = cd.read_u32::<LittleEndian>()?;
+    let mut local_header_offset = cd.read_u32::<LittleEndian>()? as u64;
+
+    if cd.len() < name_length + extra_length + comment_length {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "zip central directory is truncated",
+        ));
+    }
+    let (name, rest) = cd.split_at(name_length);
+    let (mut extra, rest) = rest.split_at(extra_length);
+    let (comment, rest) = rest.split_at(comment_length);
+    *cd = rest;
+
+    // zip64 extended information only holds the fields that did not fit, in this order
+    while extra.len() >= 4 {
+        let id = extra.read_u16::<LittleEndian>()?;
+        let size = extra.read_u16::<LittleEndian>()? as usize;
+        if extra.len() < size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "zip extra field is truncated",
+            ));
+        }
+        let (mut data, rest) = extra.split_at(size);
+        extra = rest;
+
+        if id == ZIP64_EXTRA_FIELD_ID {
+            if uncompressed_size == 0xFFFFFFFF {
+                uncompressed_size = data.read_u64::<LittleEndian>()?;
+            }
+            if compressed_size == 0xFFFFFFFF {
+                compressed_size = data.read_u64::<LittleEndian>()?;
+            }
+            if local_header_offset == 0xFFFFFFFF {
+                local_header_offset = data.read_u64::<LittleEndian>()?;
+            }
+        }
+    }
+
+    let name = if flags & FLAG_UTF8 != 0 {
+        String::from_utf8_lossy(name).into_owned()
+    } else {
+        name.iter().map(|&b| cp437_char(b)).collect()
+    };
+
+    Ok(ZipEntry {
+        name,
+        compression_method,
+        flags,
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        last_modified_date,
+        last_modified_time,
+        external_attributes,
+        comment: comment.to_vec(),
+        local_header_offset,
+    })
+}
+
+// names without the utf-8 flag are in IBM code page 437
+fn cp437_char(b: u8) -> char {
+    const HIGH_HALF: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+        ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+        αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+    if b < 0x80 {
+        b as char
+    } else {
+        HIGH_HALF.chars().nth((b - 0x80) as usize).unwrap()
+    }
+}
+
+fn no_eocd() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "zip end of central directory record not found",
+    )
+}
+
+fn multi_disk() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "zip archives spanning several disks are not supported",
+    )
+}
+
+fn size_mismatch() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "zip entry decompressed size mismatch",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+    use crate::lzf_compress::lzf_compress;
+    use crate::lzf_decompress::lzf_decompress;
+
+    // archive with a single entry called "a", followed by `comment` and `trailing` bytes
+    fn single_entry_zip(
+        method: u16,
+        data: &[u8],
+        buf_compressed: &[u8],
+        comment: &[u8],
+        trailing: &[u8],
+    ) -> Vec<u8> {
+        let mut crc32 = Crc32::new();
+        crc32.update(data);
+
+        let mut zip = Vec::new();
+        zip.write_u32::<LittleEndian>(LOCAL_HEADER_SIGNATURE)
+            .unwrap();
+        zip.write_u16::<LittleEndian>(20).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(method).unwrap();
+        zip.write_u32::<LittleEndian>(0).unwrap();
+        zip.write_u32::<LittleEndian>(crc32.digest()).unwrap();
+        zip.write_u32::<LittleEndian>(buf_compressed.len() as u32)
+            .unwrap();
+        zip.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        zip.write_u16::<LittleEndian>(1).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.push(b'a');
+        zip.extend_from_slice(buf_compressed);
+
+        let cd_offset = zip.len() as u32;
+        zip.write_u32::<LittleEndian>(CENTRAL_HEADER_SIGNATURE)
+            .unwrap();
+        zip.write_u16::<LittleEndian>(20).unwrap();
+        zip.write_u16::<LittleEndian>(20).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(method).unwrap();
+        zip.write_u32::<LittleEndian>(0).unwrap();
+        zip.write_u32::<LittleEndian>(crc32.digest()).unwrap();
+        zip.write_u32::<LittleEndian>(buf_compressed.len() as u32)
+            .unwrap();
+        zip.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        zip.write_u16::<LittleEndian>(1).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u32::<LittleEndian>(0).unwrap();
+        zip.write_u32::<LittleEndian>(0).unwrap();
+        zip.push(b'a');
+        let cd_size = zip.len() as u32 - cd_offset;
+
+        zip.write_u32::<LittleEndian>(EOCD_SIGNATURE).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(0).unwrap();
+        zip.write_u16::<LittleEndian>(1).unwrap();
+        zip.write_u16::<LittleEndian>(1).unwrap();
+        zip.write_u32::<LittleEndian>(cd_size).unwrap();
+        zip.write_u32::<LittleEndian>(cd_offset).unwrap();
+        zip.write_u16::<LittleEndian>(comment.len() as u16).unwrap();
+        zip.extend_from_slice(comment);
+        zip.extend_from_slice(trailing);
+        zip
+    }
+
+    #[test]
+    fn data_after_end_of_central_directory_is_ignored() {
+        let zip = single_entry_zip(METHOD_STORED, b"abc", b"abc", b"comment", b"trailing");
+        let mut reader = ZipReader::new(io::Cursor::new(zip)).unwrap();
+        assert_eq!(reader.comment(), b"comment");
+        assert_eq!(reader.read_entry(0).unwrap(), b"abc");
+
+        // record that fits into the comment of the real one, but does not reach end of file
+        let mut comment = EOCD_SIGNATURE.to_le_bytes().to_vec();
+        comment.extend_from_slice(&[0; 18]);
+        comment.extend_from_slice(b"comment");
+        let zip = single_entry_zip(METHOD_STORED, b"abc", b"abc", &comment, b"");
+        let reader = ZipReader::new(io::Cursor::new(zip)).unwrap();
+        assert_eq!(reader.comment(), &comment[..]);
+    }
+
+    #[test]
+    fn codecs_of_this_crate_register_as_they_are() {
+        const METHOD_LZF: u16 = 0x4C5A;
+        let data = b"abcabcabcabcabcabcabcabcabcabc".repeat(10);
+        let mut buf_compressed = Vec::new();
+        lzf_compress(&data, &mut buf_compressed);
+        let zip = single_entry_zip(METHOD_LZF, &data, &buf_compressed, b"", b"");
+
+        let mut reader = ZipReader::new(io::Cursor::new(zip)).unwrap();
+        let err = reader.read_entry(0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+        reader.register_method(METHOD_LZF, lzf_decompress);
+        assert_eq!(reader.read_entry(0).unwrap(), data);
+    }
+
+    #[test]
+    fn registered_method_output_is_limited() {
+        const METHOD_LZF: u16 = 0x4C5A;
+        let data = vec![b'a'; 1000];
+        let mut buf_compressed = Vec::new();
+        lzf_compress(&data, &mut buf_compressed);
+        let zip = single_entry_zip(METHOD_LZF, &data, &buf_compressed, b"", b"");
+
+        let mut reader = ZipReader::new(io::Cursor::new(zip)).unwrap();
+        reader.register_method(METHOD_LZF, lzf_decompress);
+        reader.set_max_decompressed_length(999);
+        let err = reader.read_entry(0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        reader.set_max_decompressed_length(1000);
+        assert_eq!(reader.read_entry(0).unwrap(), data);
+    }
+}